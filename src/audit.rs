@@ -0,0 +1,124 @@
+use std::{fs::OpenOptions, io::{self, Write}, path::Path};
+
+use serde::Serialize;
+
+use crate::merge::{MergeDecision, MergeObserver, MergeReport, Verdict};
+
+/// A change the merge engine applied to the base.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditChange {
+    /// `add`, `remove` or `override`.
+    pub action: &'static str,
+    pub path: String
+}
+
+/// Observer recording every change applied to the base, without influencing the merge.
+#[derive(Debug, Default)]
+pub struct ChangeRecorder {
+    pub changes: Vec<AuditChange>
+}
+
+impl MergeObserver for ChangeRecorder {
+    fn on_decision(&mut self, decision: &MergeDecision) -> Verdict {
+        let (action, path) = match decision {
+            MergeDecision::Add { path, .. } => ("add", path),
+            MergeDecision::Remove { path, .. } => ("remove", path),
+            MergeDecision::Override { path, .. } => ("override", path),
+            MergeDecision::Skip { .. } => return Verdict::Accept
+        };
+
+        self.changes.push(AuditChange { action, path: path.to_string() });
+        Verdict::Accept
+    }
+}
+
+/// A policy rule that decided the strategy of at least one overlay entry.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FiredRule {
+    pub rule: String,
+    pub source: String,
+    /// Number of overlay entries the rule decided.
+    pub count: usize
+}
+
+/// Summarizes the rules recorded in the trace of a report, in the order they first fired.
+pub fn fired_rules(report: &MergeReport) -> Vec<FiredRule> {
+    let mut fired: Vec<FiredRule> = Vec::new();
+
+    for record in &report.trace {
+        let (Some(rule), Some(source)) = (&record.rule, &record.source) else {
+            continue;
+        };
+
+        match fired.iter_mut().find(|fired| fired.rule == *rule && fired.source == *source) {
+            Some(fired) => fired.count += 1,
+            None => fired.push(FiredRule { rule: rule.clone(), source: source.clone(), count: 1 })
+        }
+    }
+
+    fired
+}
+
+/// One entry of the audit log.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    pub file: String,
+    pub layers: Vec<String>,
+    /// File the result was written to, `-` for stdout and `None` for dry runs.
+    pub output: Option<String>,
+    pub rules: Vec<FiredRule>,
+    pub changes: Vec<AuditChange>
+}
+
+impl AuditRecord {
+    /// Creates a record stamped with the current time.
+    pub fn new(file: String, layers: Vec<String>, output: Option<String>, rules: Vec<FiredRule>, changes: Vec<AuditChange>) -> AuditRecord {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        AuditRecord { timestamp, file, layers, output, rules, changes }
+    }
+}
+
+/// Appends a record to the audit log as a single line of JSON, creating the log if needed.
+pub fn append_audit_record(path: &Path, record: &AuditRecord) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    // a single write keeps concurrent appends from interleaving
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{merge::{merge_configs_with_observer, MergeOptions}, parsers::blk::parse_config, policy::MergePolicy};
+
+    #[test]
+    fn test_audit_record() {
+        let mut base = parse_config("graphics{sky:i=1;};old:i=1;").unwrap().1;
+        let overlay = parse_config("graphics{sky:i=2;};new:i=1;old:i=0;").unwrap().1;
+        let policy = MergePolicy { rules: vec!["old=delete".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), trace: true, ..Default::default() };
+        let mut recorder = ChangeRecorder::default();
+
+        let report = merge_configs_with_observer(&mut base, overlay, &options, &mut recorder).unwrap();
+        let actions: Vec<(&str, &str)> = recorder.changes.iter().map(|change| (change.action, change.path.as_str())).collect();
+
+        assert_eq!(actions, vec![("override", "graphics/sky"), ("add", "new"), ("remove", "old")]);
+        assert_eq!(fired_rules(&report), vec![FiredRule { rule: "old".to_string(), source: "command line".to_string(), count: 1 }]);
+
+        let path = std::env::temp_dir().join(format!("blk-merge-audit-{}.log", std::process::id()));
+        let record = AuditRecord::new("config.blk".to_string(), Vec::new(), None, fired_rules(&report), recorder.changes);
+
+        append_audit_record(&path, &record).unwrap();
+        append_audit_record(&path, &record).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fmt, str::FromStr};
+
+use nom::{branch::alt, bytes::complete::{tag, take_until}, character::complete::{alpha1, char, digit1, multispace0}, combinator::{all_consuming, recognize}, multi::{many0, many1, separated_list1}, sequence::{delimited, preceded}, IResult, Parser};
+
+use crate::types::*;
+
+/// A literal value a property is compared against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Text(String),
+    Number(f64),
+    Boolean(bool)
+}
+
+impl Literal {
+    /// Returns true if a property value equals the literal.
+    pub fn matches(&self, value: &BlkPropertyValue) -> bool {
+        compare(value, Comparison::Eq, self)
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Text(text) => write!(f, "\"{}\"", text),
+            Literal::Number(number) => write!(f, "{}", number),
+            Literal::Boolean(boolean) => write!(f, "{}", boolean)
+        }
+    }
+}
+
+impl FromStr for Literal {
+    type Err = String;
+
+    /// Parses a literal like in conditions, or a bare word as text, e.g. `ultralow`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok((_, literal)) = all_consuming(parse_literal).parse(s) {
+            return Ok(literal);
+        }
+
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+            return Err(format!("invalid literal `{}`", s));
+        }
+
+        Ok(Literal::Text(s.to_string()))
+    }
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison { Eq, Ne, Lt, Le, Gt, Ge }
+
+/// A boolean expression over property values, e.g. `graphics/shadowQuality == "ultralow" && hdClient == no`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Compare { path: Vec<String>, op: Comparison, value: Literal },
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>)
+}
+
+impl Condition {
+    /// Evaluates the condition against a config. Comparisons against missing keys are false.
+    pub fn evaluate(&self, config: &BlkConfig) -> bool {
+        match self {
+            Condition::Compare { path, op, value } => match config.find(path) {
+                Some(BlkEntry::Property(property)) => compare(&property.value, *op, value),
+                _ => false
+            },
+            Condition::Not(inner) => !inner.evaluate(config),
+            Condition::And(left, right) => left.evaluate(config) && right.evaluate(config),
+            Condition::Or(left, right) => left.evaluate(config) || right.evaluate(config)
+        }
+    }
+}
+
+/// Compares a property value with a literal. Values of incompatible types never compare equal.
+fn compare(value: &BlkPropertyValue, op: Comparison, literal: &Literal) -> bool {
+    let ordering = match (value, literal) {
+        (BlkPropertyValue::Text(a), Literal::Text(b)) => a.as_str().partial_cmp(b.as_str()),
+        (BlkPropertyValue::Boolean(a), Literal::Boolean(b)) => a.partial_cmp(b),
+        (BlkPropertyValue::Integer(a), Literal::Number(b)) => f64::from(*a).partial_cmp(b),
+        (BlkPropertyValue::Long(a), Literal::Number(b)) => (*a as f64).partial_cmp(b),
+        (BlkPropertyValue::Real(a), Literal::Number(b)) => f64::from(*a).partial_cmp(b),
+        _ => None
+    };
+
+    let Some(ordering) = ordering else {
+        return op == Comparison::Ne;
+    };
+
+    match op {
+        Comparison::Eq => ordering.is_eq(),
+        Comparison::Ne => ordering.is_ne(),
+        Comparison::Lt => ordering.is_lt(),
+        Comparison::Le => ordering.is_le(),
+        Comparison::Gt => ordering.is_gt(),
+        Comparison::Ge => ordering.is_ge()
+    }
+}
+
+/// Parses a `/`-separated property path.
+fn parse_path(input: &str) -> IResult<&str, Vec<String>> {
+    separated_list1(char('/'), recognize(many1(alt((alpha1, digit1, tag("_"))))).map(str::to_string))
+        .parse(input)
+}
+
+/// Parses a comparison operator.
+fn parse_comparison(input: &str) -> IResult<&str, Comparison> {
+    alt((
+        tag("==").map(|_| Comparison::Eq),
+        tag("!=").map(|_| Comparison::Ne),
+        tag("<=").map(|_| Comparison::Le),
+        tag(">=").map(|_| Comparison::Ge),
+        tag("<").map(|_| Comparison::Lt),
+        tag(">").map(|_| Comparison::Gt)
+    )).parse(input)
+}
+
+/// Parses a literal: a string in double or single quotes, a number or a boolean.
+fn parse_literal(input: &str) -> IResult<&str, Literal> {
+    alt((
+        delimited(char('"'), take_until("\""), char('"')).map(|text: &str| Literal::Text(text.to_string())),
+        delimited(char('\''), take_until("'"), char('\'')).map(|text: &str| Literal::Text(text.to_string())),
+        alt((tag("true"), tag("yes"))).map(|_| Literal::Boolean(true)),
+        alt((tag("false"), tag("no"))).map(|_| Literal::Boolean(false)),
+        nom::number::complete::double.map(Literal::Number)
+    )).parse(input)
+}
+
+/// Parses a single comparison, a negation or a parenthesized expression.
+fn parse_unary(input: &str) -> IResult<&str, Condition> {
+    delimited(multispace0, alt((
+        preceded(char('!'), parse_unary).map(|inner| Condition::Not(Box::new(inner))),
+        delimited(char('('), parse_or, char(')')),
+        (parse_path, delimited(multispace0, parse_comparison, multispace0), parse_literal)
+            .map(|(path, op, value)| Condition::Compare { path, op, value })
+    )), multispace0).parse(input)
+}
+
+/// Parses comparisons joined by `&&`.
+fn parse_and(input: &str) -> IResult<&str, Condition> {
+    let (input, first) = parse_unary(input)?;
+    let (input, rest) = many0(preceded(tag("&&"), parse_unary)).parse(input)?;
+
+    Ok((input, rest.into_iter().fold(first, |left, right| Condition::And(Box::new(left), Box::new(right)))))
+}
+
+/// Parses conjunctions joined by `||`.
+fn parse_or(input: &str) -> IResult<&str, Condition> {
+    let (input, first) = parse_and(input)?;
+    let (input, rest) = many0(preceded(tag("||"), parse_and)).parse(input)?;
+
+    Ok((input, rest.into_iter().fold(first, |left, right| Condition::Or(Box::new(left), Box::new(right)))))
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        all_consuming(parse_or).parse(s)
+            .map(|(_, condition)| condition)
+            .map_err(|_| format!("invalid condition `{}`", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::blk::parse_config;
+
+    #[test]
+    fn test_evaluate_conditions() {
+        let config = parse_config("clientType:t=\"32bit\";graphics{shadowQuality:t=\"ultralow\";skyQuality:i=2;};").unwrap().1;
+        let check = |expr: &str| expr.parse::<Condition>().unwrap().evaluate(&config);
+
+        assert!(check("clientType == \"32bit\""));
+        assert!(check("clientType == '32bit'"));
+        assert!(check("graphics/shadowQuality != \"high\""));
+        assert!(check("graphics/skyQuality >= 2 && !(graphics/skyQuality > 2)"));
+        assert!(check("missing == 1 || clientType == \"32bit\""));
+        assert!(!check("missing == 1"));
+        assert!(!check("graphics/skyQuality == \"2\""));
+    }
+
+    #[test]
+    fn test_parse_invalid_condition() {
+        assert!("clientType = 1".parse::<Condition>().is_err());
+        assert!("clientType == 1 &&".parse::<Condition>().is_err());
+    }
+}
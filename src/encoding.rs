@@ -0,0 +1,256 @@
+use std::{borrow::Cow, io::{self, Read}, str::FromStr};
+
+pub use encoding_rs::Encoding;
+
+/// Encoding assumed for files that are not UTF-8 when detecting it, as community configs
+/// with Russian comments and text are practically always saved in it.
+pub const FALLBACK: &Encoding = encoding_rs::WINDOWS_1251;
+
+/// Encoding text files are read in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TextEncoding {
+    /// UTF-8, or [`FALLBACK`] if the file is not valid UTF-8 and everything before the first
+    /// invalid byte is ASCII.
+    #[default]
+    Auto,
+    Fixed(&'static Encoding)
+}
+
+impl FromStr for TextEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TextEncoding::Auto),
+            label => parse_label(label).map(TextEncoding::Fixed)
+        }
+    }
+}
+
+/// Looks up an encoding by a label such as `utf-8`, `cp1251` or `windows-1252`.
+pub fn parse_label(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding `{}`", label))
+}
+
+/// Encoding written files are in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputEncoding {
+    pub encoding: &'static Encoding,
+    /// Whether UTF-8 output starts with a byte order mark.
+    pub bom: bool
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8-bom" | "utf-8+bom" | "utf8-bom" | "utf8+bom" => Ok(OutputEncoding { encoding: encoding_rs::UTF_8, bom: true }),
+            _ => parse_label(s).map(|encoding| OutputEncoding { encoding, bom: false })
+        }
+    }
+}
+
+/// Number of bytes [`DecodingReader`] reads at once.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Reader transcoding the text of another reader into UTF-8.
+///
+/// UTF-8 input is passed through unchanged, so a byte order mark is left for the parser.
+pub struct DecodingReader<R> {
+    reader: R,
+    requested: TextEncoding,
+    /// Encoding the input turned out to be in.
+    encoding: &'static Encoding,
+    /// Decoder of the input if it is not UTF-8.
+    decoder: Option<encoding_rs::Decoder>,
+    /// Whether all input passed on so far is ASCII.
+    ascii: bool,
+    /// Number of input bytes passed on so far.
+    passed: usize,
+    /// Input not decoded yet.
+    input: Vec<u8>,
+    /// Decoded text not read yet, starting at `position`.
+    output: Vec<u8>,
+    position: usize,
+    eof: bool
+}
+
+impl<R: Read> DecodingReader<R> {
+    pub fn new(reader: R, requested: TextEncoding) -> Self {
+        let (encoding, decoder) = match requested {
+            TextEncoding::Fixed(encoding) if encoding != encoding_rs::UTF_8 => (encoding, Some(encoding.new_decoder_without_bom_handling())),
+            _ => (encoding_rs::UTF_8, None)
+        };
+
+        DecodingReader {
+            reader,
+            requested,
+            encoding,
+            decoder,
+            ascii: true,
+            passed: 0,
+            input: Vec::new(),
+            output: Vec::new(),
+            position: 0,
+            eof: false
+        }
+    }
+
+    /// Returns the encoding the input is read in, which is only final once all of it was read.
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Reads the next chunk of input and decodes as much of it as possible.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let read = self.reader.read(&mut chunk)?;
+
+        self.eof = read == 0;
+        self.input.extend_from_slice(&chunk[..read]);
+        self.output.clear();
+        self.position = 0;
+
+        if self.decoder.is_none() {
+            let valid = match std::str::from_utf8(&self.input) {
+                Ok(_) => self.input.len(),
+                // a character split between chunks is completed by the next one
+                Err(err) if err.error_len().is_none() && !self.eof => err.valid_up_to(),
+                Err(err) if self.requested == TextEncoding::Auto && self.ascii && self.input[..err.valid_up_to()].is_ascii() => {
+                    // ASCII reads the same in both encodings, so nothing passed on so far changes
+                    self.encoding = FALLBACK;
+                    self.decoder = Some(FALLBACK.new_decoder_without_bom_handling());
+                    return self.decode();
+                },
+                Err(err) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("not valid UTF-8 after byte {}", self.passed + err.valid_up_to())
+                ))
+            };
+
+            self.ascii &= self.input[..valid].is_ascii();
+            self.passed += valid;
+            self.output.extend(self.input.drain(..valid));
+            return Ok(());
+        }
+
+        self.decode()
+    }
+
+    /// Decodes all buffered input with the decoder.
+    fn decode(&mut self) -> io::Result<()> {
+        let Some(decoder) = &mut self.decoder else {
+            return Ok(());
+        };
+
+        let mut text = String::with_capacity(decoder.max_utf8_buffer_length(self.input.len()).unwrap_or(self.input.len() * 3));
+        // the capacity fits the whole input, so it is always decoded at once
+        let _ = decoder.decode_to_string(&self.input, &mut text, self.eof);
+
+        self.passed += self.input.len();
+        self.input.clear();
+        self.output.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.output.len() && !self.eof {
+            self.fill()?;
+        }
+
+        let available = &self.output[self.position..];
+        let count = available.len().min(buf.len());
+
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Decodes the text of a file, returning it with the encoding it was read in.
+pub fn decode(data: &[u8], encoding: TextEncoding) -> io::Result<(String, &'static Encoding)> {
+    let mut reader = DecodingReader::new(data, encoding);
+    let mut text = String::new();
+
+    reader.read_to_string(&mut text)?;
+    Ok((text, reader.encoding()))
+}
+
+/// Encodes text in the given encoding, failing if it has characters the encoding cannot represent.
+pub fn encode<'a>(text: &'a str, encoding: &'static Encoding) -> io::Result<Cow<'a, [u8]>> {
+    match encoding.encode(text) {
+        (_, used, true) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("output has characters that cannot be written in {}", used.name()))),
+        (bytes, _, false) => Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // "name:t=\"Привет\"" in Windows-1251
+        let cp1251 = b"name:t=\"\xcf\xf0\xe8\xe2\xe5\xf2\"\n";
+
+        assert_eq!(decode(cp1251, TextEncoding::Auto).unwrap(), ("name:t=\"Привет\"\n".to_string(), FALLBACK));
+        assert_eq!(decode("a:t=\"Привет\"".as_bytes(), TextEncoding::Auto).unwrap(), ("a:t=\"Привет\"".to_string(), encoding_rs::UTF_8));
+        assert_eq!(decode(b"\xe9", "windows-1252".parse().unwrap()).unwrap().0, "é");
+        assert!(decode(cp1251, "utf-8".parse().unwrap()).is_err());
+        assert!(decode(&["Привет ".as_bytes(), b"\xcf"].concat(), TextEncoding::Auto).is_err());
+        assert!("klingon".parse::<TextEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_decode_across_chunks() {
+        let mut data = vec![b'a'; CHUNK_SIZE * 2];
+        data.extend(b"\xcf");
+
+        let (text, encoding) = decode(&data, TextEncoding::Auto).unwrap();
+        assert_eq!(encoding, FALLBACK);
+        assert!(text.ends_with("aП"));
+
+        let mut data = vec![b'a'; CHUNK_SIZE - 1];
+        data.extend("П".as_bytes());
+        assert_eq!(decode(&data, TextEncoding::Auto).unwrap().1, encoding_rs::UTF_8);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_encoding() {
+        let cp1251 = b"name:t=\"\xcf\xf0\xe8\xe2\xe5\xf2\"\n";
+        let config = crate::parsers::parse_reader(&cp1251[..], &Default::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(config.encoding, Some(FALLBACK));
+
+        let mut output = Vec::new();
+        crate::types::stringify_config(&config, &mut output).unwrap();
+        assert_eq!(output, cp1251);
+    }
+
+    #[test]
+    fn test_output_encoding() {
+        let config = crate::parsers::blk::parse_config("a:i=1\n").unwrap().1;
+        let write = |encoding: &str| {
+            let encoding: OutputEncoding = encoding.parse().unwrap();
+            let options = crate::types::SerializeOptions { encoding: Some(encoding.encoding), bom: encoding.bom, ..Default::default() };
+            let mut output = Vec::new();
+
+            crate::types::stringify_config_with(&config, &mut output, &options).unwrap();
+            output
+        };
+
+        assert_eq!(write("utf-8"), b"a:i=1\n");
+        assert_eq!(write("UTF-8+BOM"), b"\xef\xbb\xbfa:i=1\n");
+        assert_eq!(write("cp1251"), b"a:i=1\n");
+        assert!("utf-9".parse::<OutputEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("Привет", FALLBACK).unwrap().as_ref(), b"\xcf\xf0\xe8\xe2\xe5\xf2");
+        assert!(encode("日本", FALLBACK).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use crate::{parsers::{self, blk::ParseOptions}, types::*};
+
+/// Returns the canonical form of a path, or the path itself if it cannot be resolved.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Reads and parses an included file, adding warnings about it to `warnings`.
+fn load_include(path: &Path, options: &ParseOptions, warnings: &mut Vec<String>) -> Result<BlkConfig, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| format!("failed to read include {}: {}", path.display(), err))?;
+
+    let mut include_warnings = Vec::new();
+    let result = parsers::parse_reader(std::io::BufReader::new(file), options, &mut include_warnings);
+    warnings.extend(include_warnings.into_iter().map(|warning| format!("{}: {}", path.display(), warning)));
+
+    result.map_err(|err| format!("failed to parse include {}: {}", path.display(), err))
+}
+
+/// Inlines the includes among the entries of a file, `chain` holding the files currently being resolved.
+fn resolve_entries(
+    entries: &mut Vec<BlkEntry>,
+    file: &Path,
+    chain: &mut Vec<PathBuf>,
+    options: &ParseOptions,
+    warnings: &mut Vec<String>
+) -> Result<(), String> {
+    let mut resolved = Vec::with_capacity(entries.len());
+
+    for entry in std::mem::take(entries) {
+        match entry {
+            BlkEntry::Include(include) => {
+                let path = file.parent().unwrap_or(Path::new("")).join(&include);
+                let canonical_path = canonical(&path);
+
+                if chain.contains(&canonical_path) {
+                    let cycle: Vec<String> = chain.iter().chain([&canonical_path]).map(|path| path.display().to_string()).collect();
+                    return Err(format!("include cycle: {}", cycle.join(" -> ")));
+                }
+
+                let mut included = load_include(&path, options, warnings)?;
+
+                chain.push(canonical_path);
+                resolve_entries(&mut included.block.entries, &path, chain, options, warnings)?;
+                chain.pop();
+
+                resolved.extend(included.block.entries);
+            },
+            BlkEntry::Section(mut section) => {
+                resolve_entries(&mut section.entries, file, chain, options, warnings)?;
+                resolved.push(BlkEntry::Section(section));
+            },
+            entry => resolved.push(entry)
+        }
+    }
+
+    *entries = resolved;
+    Ok(())
+}
+
+/// Replaces every `include` directive of a config read from `file` with the entries of the
+/// included file.
+///
+/// Paths are relative to the directory of the including file. Included files may include
+/// further files, but a file including itself, directly or through other files, is an error.
+/// Included files are parsed with `options`, warnings about them are added to `warnings`.
+pub fn resolve_includes(config: &mut BlkConfig, file: &Path, options: &ParseOptions, warnings: &mut Vec<String>) -> Result<(), String> {
+    resolve_entries(&mut config.block.entries, file, &mut vec![canonical(file)], options, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_includes() {
+        let dir = std::env::temp_dir().join(format!("blk-merge-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(dir.join("shared/video.blk"), "sky:i=2\ninclude \"quality.blk\"\n").unwrap();
+        std::fs::write(dir.join("shared/quality.blk"), "clouds:i=3\n").unwrap();
+        std::fs::write(dir.join("a.blk"), "include \"b.blk\"\n").unwrap();
+        std::fs::write(dir.join("b.blk"), "include \"a.blk\"\n").unwrap();
+
+        let mut config = parsers::blk::parse_config("version:i=1\ngraphics{\ninclude \"shared/video.blk\"\n}\n").unwrap().1;
+        resolve_includes(&mut config, &dir.join("config.blk"), &ParseOptions::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(config, parsers::blk::parse_config("version:i=1;graphics{sky:i=2;clouds:i=3;};").unwrap().1);
+
+        let mut config = parsers::blk::parse_config("include \"a.blk\"\n").unwrap().1;
+        assert!(resolve_includes(&mut config, &dir.join("config.blk"), &ParseOptions::default(), &mut Vec::new()).unwrap_err().starts_with("include cycle"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+//! Parsing, merging and serializing of Dagor BLK configuration files.
+
+pub mod audit;
+pub mod condition;
+pub mod encoding;
+pub mod include;
+pub mod merge;
+pub mod output;
+pub mod parsers;
+pub mod path;
+pub mod policy;
+pub mod stack;
+pub mod transform;
+pub mod types;
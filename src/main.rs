@@ -1,64 +1,998 @@
-use std::fs::File;
+use std::{io::Write, path::{Path, PathBuf}, time::Instant};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use serde::Serialize;
 
-use crate::types::{stringify_config, BlkConfig};
-
-mod parsers;
-mod types;
+use blk_merge::{audit::{self, AuditRecord, ChangeRecorder}, encoding::{self, OutputEncoding, TextEncoding}, include, merge::{self, MergeOptions, MergeReport, MergeStrategy, NoneValues}, output::{self, OutputOptions}, parsers::{self, blk::{Dialect, IntegerOverflow, ParseLimits, ParseOptions, RealPolicy}}, policy::{self, ConditionalRule, KeyAlias, MergePolicy, PolicyFormat, PolicyRule, PolicyVariable, ProtectRule, RequiredKey, PolicySet, PolicySource, Severity}, stack::{self, StackLayer}, types::{stringify_config_preserving, stringify_config_with, BlankLines, BlkConfig, BooleanStyle, ColorStyle, ComponentSpacing, IndentStyle, InlineSections, LineEnding, OutputStyle, QuoteKeys, QuoteStyle, SerializeOptions, SortKeys, Terminators}};
 
 /// Command line arguments
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Options for reading BLK files
+#[derive(clap::Args, Debug)]
+struct ParseArgs {
+    /// Encoding of text input files, e.g. `cp1251`. `auto` (the default) reads UTF-8 and falls back to Windows-1251
+    #[arg(long, value_name = "ENCODING", default_value = "auto")]
+    encoding: TextEncoding,
+
+    /// What to do with `:i=` values that do not fit in 32 bits: error, promote (to `:i64=`, the default) or saturate
+    #[arg(long, value_name = "MODE", default_value = "promote")]
+    integer_overflow: IntegerOverflow,
+
+    /// What to do with reals that are infinite or not a number: accept, reject (the default) or replace (with 0)
+    #[arg(long, value_name = "POLICY", default_value = "reject")]
+    real_policy: RealPolicy,
+
+    /// Syntax accepted in text BLK files: lenient (the default) accepts comments, includes, hexadecimal integers
+    /// and names with `.`, `-`, `/` or `@`, strict only the syntax the game reads
+    #[arg(long, value_name = "DIALECT", default_value = "lenient")]
+    dialect: Dialect,
+
+    /// Shared name map (`nm` file) used to decode slim binary BLK files
+    #[arg(long, value_name = "FILE")]
+    name_map: Option<PathBuf>,
+
+    /// Zstd dictionary used to decompress dictionary-compressed binary BLK files
+    #[arg(long, value_name = "FILE")]
+    zstd_dict: Option<PathBuf>,
+
+    /// Warn about properties defined more than once in the same section of an input file
+    #[arg(long)]
+    warn_duplicates: bool,
+
+    /// Refuse to parse files with sections nested deeper than this (128 by default)
+    #[arg(long, value_name = "LEVELS")]
+    max_depth: Option<usize>,
+
+    /// Refuse to parse files with more entries than this, counting the ones in sections (10000000 by default)
+    #[arg(long, value_name = "COUNT")]
+    max_entries: Option<usize>,
+
+    /// Refuse to parse files with text values longer than this many bytes (16 MiB by default)
+    #[arg(long, value_name = "BYTES")]
+    max_value_length: Option<usize>,
+}
+
+impl ParseArgs {
+    /// Builds the options for parsing, reading the name map and the zstd dictionary
+    fn options(&self) -> ParseOptions {
+        let name_map = self.name_map.as_ref().map(|path| {
+            let data = std::fs::read(path)
+                .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read name map {}: {}", path.display(), err)));
+
+            parsers::bbf::parse_name_map(&data)
+                .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to parse name map {}: {}", path.display(), err)))
+        });
+        let zstd_dict = self.zstd_dict.as_ref().map(|path| std::fs::read(path)
+            .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read zstd dictionary {}: {}", path.display(), err))));
+        let defaults = ParseLimits::default();
+        let limits = ParseLimits {
+            max_depth: self.max_depth.unwrap_or(defaults.max_depth),
+            max_entries: self.max_entries.unwrap_or(defaults.max_entries),
+            max_value_length: self.max_value_length.unwrap_or(defaults.max_value_length)
+        };
+
+        ParseOptions { overflow: self.integer_overflow, reals: self.real_policy, encoding: self.encoding, dialect: self.dialect, limits, warn_duplicates: self.warn_duplicates, name_map, zstd_dict }
+    }
+}
+
+/// Options for writing BLK files
+#[derive(clap::Args, Debug)]
+struct FormatArgs {
+    /// Quote all text values with double, single or triple quotes instead of keeping the quotes they were written with
+    #[arg(long, value_name = "STYLE")]
+    quote: Option<QuoteStyle>,
+
+    /// Write the output in a fixed format that is the same for every equal config and stable across versions,
+    /// for hashing and byte-exact comparisons
+    #[arg(long, conflicts_with_all = ["style", "sort_keys", "group_sections", "quote", "quote_keys", "indent", "inline_sections", "blank_lines", "terminators", "align", "line_endings", "spacing", "booleans", "colors", "keep_hex", "precision", "normalize_numbers", "keep_bom", "output_encoding"])]
+    canonical: bool,
+
+    /// Quote keys and section names: when-needed (the default) if they are not identifiers, always, or never
+    #[arg(long, value_name = "WHEN", default_value = "when-needed")]
+    quote_keys: QuoteKeys,
+
+    /// Layout of the output: expanded (the default) with an entry per line, or compact with all entries on one line
+    #[arg(long, value_name = "STYLE", default_value = "expanded")]
+    style: OutputStyle,
+
+    /// Sort properties by name within each section, `all` sorts sections among each other too
+    #[arg(long, value_name = "ENTRIES", num_args = 0..=1, require_equals = true, default_missing_value = "properties")]
+    sort_keys: Option<SortKeys>,
+
+    /// Write the properties of each section before its nested sections, keeping their order otherwise
+    #[arg(long)]
+    group_sections: bool,
+
+    /// Indentation of entries in sections: a number of spaces (4 by default), tabs or none
+    #[arg(long, value_name = "INDENT", default_value = "4")]
+    indent: IndentStyle,
+
+    /// Write sections on a single line: never (the default), empty ones as `name{}`, or single ones that are empty or
+    /// hold a single property
+    #[arg(long, value_name = "SECTIONS", default_value = "never")]
+    inline_sections: InlineSections,
+
+    /// Blank lines between entries: none (the default), one before every section, or preserve the ones of the input file
+    #[arg(long, value_name = "LINES", default_value = "none")]
+    blank_lines: BlankLines,
+
+    /// Terminators of entries: newline (the default), semicolon after every property, or preserve the ones of the input file
+    #[arg(long, value_name = "STYLE", default_value = "newline")]
+    terminators: Terminators,
+
+    /// Align the types and values of the properties in a section in a column by padding their keys
+    #[arg(long)]
+    align: bool,
+
+    /// Line endings of the output, lf or crlf, by default the ones of the input file
+    #[arg(long, value_name = "ENDING")]
+    line_endings: Option<LineEnding>,
+
+    /// Literals booleans are written with: yes-no (the default), true-false, on-off or numeric (1 and 0)
+    #[arg(long, value_name = "STYLE", default_value = "yes-no")]
+    booleans: BooleanStyle,
+
+    /// Spacing between the components of vectors and colors, spaced (`1, 2`) or tight (`1,2`), instead of keeping the
+    /// spacing they were written with
+    #[arg(long, value_name = "SPACING")]
+    spacing: Option<ComponentSpacing>,
+
+    /// Write all colors as decimal components or as hex `#RRGGBBAA` instead of keeping the notation they were written in
+    #[arg(long, value_name = "STYLE")]
+    colors: Option<ColorStyle>,
+
+    /// Keep integers and colors that were written in hexadecimal in hexadecimal
+    #[arg(long)]
+    keep_hex: bool,
+
+    /// Number of decimals of reals that are written anew instead of as the literal they were written as
+    #[arg(long, value_name = "DECIMALS")]
+    precision: Option<usize>,
+
+    /// Write numbers, vectors and colors in their usual form instead of keeping the literals they were written as
+    #[arg(long)]
+    normalize_numbers: bool,
+
+    /// Start the output with a UTF-8 byte order mark if the input file started with one
+    #[arg(long)]
+    keep_bom: bool,
+
+    /// Encoding of the output, e.g. `utf-8`, `utf-8-bom` or `cp1251`, by default the one the input file was read in
+    #[arg(long, value_name = "ENCODING")]
+    output_encoding: Option<OutputEncoding>,
+}
+
+impl FormatArgs {
+    /// Builds the options for serializing, starting the output with a header if given
+    fn options(&self, header: Option<String>) -> SerializeOptions {
+        if self.canonical {
+            return SerializeOptions::CANONICAL;
+        }
+
+        SerializeOptions {
+            header,
+            style: self.style,
+            sort: self.sort_keys,
+            group: self.group_sections,
+            quote: self.quote,
+            quote_keys: self.quote_keys,
+            booleans: self.booleans,
+            indent: self.indent,
+            inline_sections: self.inline_sections,
+            blank_lines: self.blank_lines,
+            terminators: self.terminators,
+            align: self.align,
+            line_endings: self.line_endings,
+            spacing: self.spacing,
+            colors: self.colors,
+            keep_hex: self.keep_hex,
+            precision: self.precision,
+            formatters: Default::default(),
+            normalize_numbers: self.normalize_numbers,
+            keep_bom: self.keep_bom,
+            bom: self.output_encoding.is_some_and(|output| output.bom),
+            encoding: self.output_encoding.map(|output| output.encoding)
+        }
+    }
+}
+
+/// Options of the `merge` command
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
     /// Input file name
     #[arg(short, long)]
     file: String,
 
     /// Second file to merge with
-    #[arg(short, long)]
-    with: String,
+    #[arg(short, long, required_unless_present = "stack", conflicts_with = "stack")]
+    with: Option<String>,
 
-    /// Output file name. Will be used instead of rewriting the first file
+    /// Manifest listing an ordered stack of overlays to merge one after another
+    #[arg(long, value_name = "MANIFEST")]
+    stack: Option<PathBuf>,
+
+    /// Output file name. Will be used instead of rewriting the first file, `-` writes to stdout
     #[arg(short, long)]
     output: Option<String>,
 
+    /// Back up the file being replaced to `<file>.<SUFFIX>` (default `bak`), `{time}` inserts a timestamp
+    #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = "bak")]
+    backup: Option<String>,
+
+    #[command(flatten)]
+    format: FormatArgs,
+
+    /// Start the output with a comment recording the version of this tool, the time, the merged files and the
+    /// policies, which the strict dialect does not read
+    #[arg(long, conflicts_with_all = ["preserve_format", "canonical"])]
+    header: bool,
+
+    /// Copy the entries the merge left unchanged from the base file as they are written there, with their comments
+    /// and spacing, formatting only changed and added entries
+    #[arg(long, conflicts_with_all = ["canonical", "style", "sort_keys", "group_sections"])]
+    preserve_format: bool,
+
+    #[command(flatten)]
+    parse: ParseArgs,
+
+    /// Write merge statistics as JSON to a file, `-` writes to stdout
+    #[arg(long, value_name = "FILE")]
+    report_json: Option<String>,
+
     /// Dry run mode
     #[arg(short, long)]
     dry_run: bool,
 
-    /// Use a merging policy file
+    /// Check that the output reads back as the merged config before writing it, also when writing to stdout or in
+    /// dry runs. Written files are checked anyway unless they hold conflict markers
+    #[arg(long)]
+    verify: bool,
+
+    /// Merge in memory only and exit with 0 if the file is up to date, 1 if it would change, 2 on parse errors
+    #[arg(long, conflicts_with_all = ["output", "dry_run"])]
+    check: bool,
+
+    /// Use a merging policy file, or a bundled preset: `preset:controls`, `preset:graphics` or `preset:sights`.
+    /// Can be repeated, later policies and command line flags take precedence over earlier ones,
+    /// and all of them over the built-in default policy
     #[arg(short = 'p', long)]
-    use_policy: Option<String>,
+    use_policy: Vec<String>,
+
+    /// Format of the policy files: pol, toml or blk, detected from the file extension by default
+    #[arg(long, value_name = "FORMAT", requires = "use_policy")]
+    policy_format: Option<PolicyFormat>,
+
+    /// Fill `${NAME}` placeholders in policy files with a value, e.g. `device=js1`
+    #[arg(long = "policy-var", value_name = "NAME=VALUE")]
+    policy_vars: Vec<PolicyVariable>,
+
+    /// Show which policy rules match a `/`-separated path and which one wins, then exit without merging
+    #[arg(long, value_name = "PATH")]
+    explain_policy: Option<String>,
+
+    /// Append a JSON line with the fired policy rules and the changed keys to an audit log
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+
+    /// Print which policy rule matched every merged entry and the strategy it selected
+    #[arg(long)]
+    trace_policy: bool,
+
+    /// Do not apply the built-in default policy
+    #[arg(long)]
+    no_default_policy: bool,
+
+    /// Merge an overlay key onto a differently named base key, e.g. `skyQuality=cloudsQuality`
+    #[arg(short, long = "alias", value_name = "FROM=TO")]
+    aliases: Vec<KeyAlias>,
+
+    /// Merge strategy: override (theirs, the default), keep (ours), append, error or delete
+    #[arg(short, long)]
+    strategy: Option<MergeStrategy>,
+
+    /// Use a different strategy under matching sections, e.g. `hotkeys=append`,
+    /// optionally only while a condition holds on the base, e.g. `hotkeys=keep if version > 2`
+    #[arg(long = "strategy-for", value_name = "PATH=STRATEGY")]
+    strategy_for: Vec<PolicyRule>,
+
+    /// Write both values of a conflict into the output between comment markers instead of failing
+    #[arg(long)]
+    conflict_markers: bool,
+
+    /// Remove identical duplicate entries within each section after merging
+    #[arg(long)]
+    dedup: bool,
+
+    /// What overlay properties without a value (`key:t=`) do: empty (the default) replaces the base value, delete removes the base property
+    #[arg(long, value_name = "MODE", default_value = "empty")]
+    none_values: NoneValues,
+
+    /// Inline `include "file.blk"` directives, resolved relative to the including file, instead of keeping them
+    #[arg(long)]
+    resolve_includes: bool,
+
+    /// Only apply overlay entries at a path if a condition holds on the base,
+    /// e.g. `graphics if clientType == "32bit"`
+    #[arg(long = "when", value_name = "PATH if CONDITION")]
+    conditions: Vec<ConditionalRule>,
+
+    /// Never change or remove entries under a path, `PATH strict` fails the merge if the overlay tries
+    #[arg(long = "protect", value_name = "PATH")]
+    protected: Vec<ProtectRule>,
+
+    /// Fail unless the merged config contains an entry, `PATH TYPE` also checks its type, e.g. `version integer`
+    #[arg(long = "require", value_name = "PATH")]
+    required: Vec<RequiredKey>,
 }
 
-/// Reads a file and parses it into a BlkConfig
-fn read_and_parse(filename: &str) -> BlkConfig {
-    let content = std::fs::read_to_string(filename)
-        .expect("Failed to read file");
+/// Commands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Merge a file, or a stack of files one after another, into a base file
+    Merge(Box<MergeArgs>),
+    /// List the paths where two files differ, exiting with 1 if there are any
+    Diff {
+        /// Base file
+        base: String,
+
+        /// File to compare with
+        other: String,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+    },
+    /// Check that files parse, exiting with 2 if any does not
+    Validate {
+        /// Files to check
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+    },
+    /// Convert a text or binary file into a text file in the requested format
+    Convert {
+        /// Input file name
+        file: String,
 
-    parsers::blk::parse_config(&content)
-        .expect("Failed to parse config").1
+        /// Output file name, `-` or leaving it out writes to stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Rewrite text files in the requested format
+    Fmt {
+        /// Files to format
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Exit with 1 if a file is not formatted instead of rewriting it
+        #[arg(long)]
+        check: bool,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Work with merging policy files
+    Policy {
+        #[command(subcommand)]
+        command: PolicyCommand,
+    },
 }
 
-/// Main function
-fn main() {
-    let args = Args::parse();
+/// Policy file commands
+#[derive(Subcommand, Debug)]
+enum PolicyCommand {
+    /// Check a policy file for syntax errors, unknown strategies, unreachable and conflicting rules
+    Validate {
+        /// Policy file name
+        file: PathBuf,
+
+        /// Format of the policy file: pol, toml or blk, detected from the file extension by default
+        #[arg(long, value_name = "FORMAT")]
+        policy_format: Option<PolicyFormat>,
+
+        /// Fill `${NAME}` placeholders in the policy file with a value
+        #[arg(long = "policy-var", value_name = "NAME=VALUE")]
+        policy_vars: Vec<PolicyVariable>,
+    },
+    /// Merge two files in memory with a policy and check the assertions of the policy against the result
+    Test {
+        /// Policy file name
+        file: PathBuf,
+
+        /// Base file
+        #[arg(long)]
+        base: String,
+
+        /// File to merge onto the base
+        #[arg(long)]
+        with: String,
+
+        /// Format of the policy file: pol, toml or blk, detected from the file extension by default
+        #[arg(long, value_name = "FORMAT")]
+        policy_format: Option<PolicyFormat>,
+
+        /// Fill `${NAME}` placeholders in the policy file with a value
+        #[arg(long = "policy-var", value_name = "NAME=VALUE")]
+        policy_vars: Vec<PolicyVariable>,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+    },
+    /// Write a starter policy with an `override` rule for every path where two files differ
+    Init {
+        /// Base file
+        #[arg(long)]
+        from: String,
+
+        /// File to compare with
+        #[arg(long)]
+        with: String,
+
+        /// Policy file to write instead of printing to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        parse: ParseArgs,
+    },
+}
+
+/// Exit code for failed commands, for files that would change in `--check` mode and for files that differ
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code for input files that cannot be read or parsed
+const EXIT_PARSE_ERROR: i32 = 2;
+
+/// Prints an error and exits with the given code
+fn fail(code: i32, message: impl std::fmt::Display) -> ! {
+    eprintln!("{} {}", "error:".red().bold(), message);
+    std::process::exit(code);
+}
+
+/// Prints a warning about an input file to stderr
+fn warn(filename: &str, message: impl std::fmt::Display) {
+    eprintln!("{} {}: {}", "warning:".yellow().bold(), filename, message);
+}
+
+/// Reads a file and parses it into a BlkConfig, printing the warnings about it
+fn try_read_and_parse(filename: &str, options: &ParseOptions) -> Result<BlkConfig, String> {
+    let file = std::fs::File::open(filename).map_err(|err| format!("failed to read {}: {}", filename, err))?;
+
+    let mut warnings = Vec::new();
+    let result = parsers::parse_reader(std::io::BufReader::new(file), options, &mut warnings);
+
+    for warning in warnings {
+        warn(filename, warning);
+    }
+
+    result.map_err(|err| format!("failed to parse {}: {}", filename, err))
+}
+
+/// Reads a file and parses it into a BlkConfig, exiting if that is not possible
+fn read_and_parse(filename: &str, options: &ParseOptions) -> BlkConfig {
+    try_read_and_parse(filename, options).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err))
+}
+
+/// Reads the text of a file to copy unchanged entries from, `None` if it is a binary file
+fn read_source(filename: &str, options: &ParseOptions) -> Option<String> {
+    let data = std::fs::read(filename)
+        .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read {}: {}", filename, err)));
+
+    if parsers::bbf::is_binary(&data) {
+        return None;
+    }
+
+    let (text, _) = encoding::decode(&data, options.encoding)
+        .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read {}: {}", filename, err)));
+
+    Some(text)
+}
+
+/// Reads and parses a file like `read_and_parse`, inlining its includes if requested
+fn read_config(filename: &str, resolve_includes: bool, options: &ParseOptions) -> BlkConfig {
+    let mut config = read_and_parse(filename, options);
+
+    if resolve_includes {
+        let mut warnings = Vec::new();
+        include::resolve_includes(&mut config, Path::new(filename), options, &mut warnings).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err));
+
+        for warning in warnings {
+            warn(filename, warning);
+        }
+    }
+
+    config
+}
+
+/// Prints what the merge engine did, including the policy trace if requested
+fn print_report(report: &MergeReport, trace: bool) {
+    for record in report.trace.iter().filter(|_| trace) {
+        let rule = match (&record.rule, &record.source) {
+            (Some(rule), Some(source)) => format!("{} from {}", rule, source),
+            _ => "default".to_string()
+        };
+
+        println!("{} {} {} ({})", "trace".magenta(), record.path, record.strategy, rule);
+    }
+
+    for hit in &report.aliases {
+        let location = if hit.path.is_empty() { "<root>" } else { &hit.path };
+        println!("{} {} -> {} in {}", "alias".cyan(), hit.from, hit.to, location);
+    }
+
+    for path in &report.skipped {
+        println!("{} {} (condition not met)", "skipped".dimmed(), path);
+    }
+
+    for path in &report.protected {
+        println!("{} {} (protected)", "skipped".dimmed(), path);
+    }
+
+    for path in &report.disallowed {
+        println!("{} {} (value not allowed)", "skipped".dimmed(), path);
+    }
+
+    for conflict in &report.conflicts {
+        println!("{} {}", "conflict".yellow(), conflict.path);
+    }
+}
+
+/// Describes how a merged file was produced, for the comment written with `--header`
+fn provenance_header(file: &str, layer_files: &[String], policies: &PolicySet) -> String {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let sources: Vec<String> = policies.layers.iter().map(|layer| layer.source.to_string()).collect();
+    let sources = if sources.is_empty() { "none".to_string() } else { sources.join(", ") };
+
+    format!(
+        "Merged by blk-merge {} at {} (Unix time)\nbase: {}\nmerged with: {}\npolicies: {}",
+        env!("CARGO_PKG_VERSION"), timestamp, file, layer_files.join(", "), sources
+    )
+}
+
+/// Generates a starter policy from the differences between two files
+fn init_policy(from: &str, with: &str, output: Option<&Path>, options: &ParseOptions) {
+    let (starter, skipped) = policy::starter_policy(&read_and_parse(from, options), &read_and_parse(with, options));
+
+    for path in &skipped {
+        warn(with, format!("no rule generated for `{}`, its names cannot be written as a path pattern", path));
+    }
+
+    let mut text = format!("# Starter policy generated from {} and {}\n\ndefault override\n\n", from, with);
+
+    for rule in &starter.rules {
+        text.push_str(&format!("rule {} {}\n", rule.pattern, rule.strategy));
+    }
+
+    // the generated file must read back as the very rules it was written from
+    match PolicyFormat::Pol.parse(&text) {
+        Ok(parsed) if parsed.rules == starter.rules => {},
+        Ok(_) => fail(EXIT_FAILURE, "the generated policy does not read back as the rules it was generated from"),
+        Err(err) => fail(EXIT_FAILURE, format!("the generated policy does not parse: {}", err))
+    }
+
+    match output {
+        Some(path) => std::fs::write(path, text)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write {}: {}", path.display(), err))),
+        None => print!("{}", text)
+    }
+}
+
+/// Prints every policy rule matching a path, the winning rule first
+fn print_explanation(policies: &PolicySet, path: &str, base: &BlkConfig) {
+    let segments: Vec<String> = path.trim_matches('/').split('/').map(str::to_string).collect();
+    let matches = policies.explain(&segments, base);
+
+    println!("{} {}", "explain".bold(), path);
+
+    if matches.is_empty() {
+        println!("  {} default strategy {}", "winner".green(), policies.default_strategy());
+    }
+
+    for (index, found) in matches.iter().enumerate() {
+        let marker = if index == 0 { "winner".green() } else { "      ".normal() };
+        let ancestor = if found.depth < segments.len() { format!(", matches {}", segments[..found.depth].join("/")) } else { String::new() };
+
+        println!(
+            "  {} {} {} (priority {}, specificity {}{}) from {}",
+            marker, found.rule.pattern, found.rule.strategy, found.rule.priority, found.rule.pattern.specificity(), ancestor, found.source
+        );
+    }
+}
+
+/// JSON document written by `--report-json`
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    file: &'a str,
+    layers: Vec<String>,
+    #[serde(flatten)]
+    report: &'a MergeReport,
+    elapsed_ms: f64,
+}
+
+/// Writes the merge statistics as JSON to a file or, for `-`, to stdout
+fn write_json_report(target: &str, report: &JsonReport) -> std::io::Result<()> {
+    if target == "-" {
+        let mut stdout = std::io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, report)?;
+        writeln!(stdout)
+    } else {
+        serde_json::to_writer_pretty(std::fs::File::create(target)?, report)?;
+        Ok(())
+    }
+}
+
+/// Validates a policy file and prints its diagnostics
+fn validate_policy_file(file: &Path, format: Option<PolicyFormat>, variables: &[PolicyVariable]) {
+    let content = std::fs::read_to_string(file)
+        .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read {}: {}", file.display(), err)));
+
+    let content = policy::substitute_variables(&content, variables)
+        .unwrap_or_else(|err| fail(EXIT_FAILURE, err.in_file(file)));
+
+    let diagnostics = match format.unwrap_or_else(|| PolicyFormat::from_path(file)) {
+        PolicyFormat::Pol => policy::validate_policy(&content),
+        format => {
+            if let Err(err) = format.parse(&content) {
+                fail(EXIT_FAILURE, err.in_file(file));
+            }
+
+            Vec::new()
+        }
+    };
+
+    for diagnostic in &diagnostics {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold()
+        };
+
+        println!("{}:{}:{}: {}: {}", file.display(), diagnostic.line, diagnostic.column, severity, diagnostic.message);
+    }
+
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    if diagnostics.is_empty() {
+        println!("{} {} is valid", "ok".green(), file.display());
+    }
+}
+
+/// Merges two files with a policy and prints the outcome of each of its assertions
+fn test_policy_file(file: &Path, base: &str, with: &str, format: Option<PolicyFormat>, variables: &[PolicyVariable], options: &ParseOptions) {
+    let format = format.unwrap_or_else(|| PolicyFormat::from_path(file));
+    let mut test_policy = MergePolicy::load_as(file, format, variables).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err));
+
+    // assertions are checked here one by one instead of failing the merge on the first failure
+    let assertions = std::mem::take(&mut test_policy.assertions);
+
+    let mut policies = PolicySet::default();
+    policies.push(PolicySource::Builtin, MergePolicy::builtin());
+    policies.push(PolicySource::File(file.to_path_buf()), test_policy);
+
+    let mut merged_config = read_and_parse(base, options);
+    merge::merge_configs(&mut merged_config, read_and_parse(with, options), &MergeOptions { policies, ..Default::default() })
+        .unwrap_or_else(|err| fail(EXIT_FAILURE, err));
+
+    if assertions.is_empty() {
+        println!("{} {} has no assertions", "ok".green(), file.display());
+        return;
+    }
+
+    let mut failed = 0;
+
+    for assertion in &assertions {
+        if assertion.check(&merged_config).is_some() {
+            println!("{} {}", "fail".red().bold(), assertion.source);
+            failed += 1;
+        } else {
+            println!("{} {}", "pass".green(), assertion.source);
+        }
+    }
+
+    if failed > 0 {
+        fail(EXIT_FAILURE, format!("{} of {} assertions failed", failed, assertions.len()));
+    }
+}
+
+/// Merges the overlays into the base file and writes the result as the arguments demand
+fn merge(args: MergeArgs) {
+    let started = Instant::now();
+    let file = args.file;
+
+    // policies from the least to the most important one: built-in defaults, presets and user policies,
+    // then per layer its policy file and manifest settings, and finally command line flags
+    let mut policies = PolicySet::default();
+
+    if !args.no_default_policy {
+        policies.push(PolicySource::Builtin, MergePolicy::builtin());
+    }
+
+    for path in &args.use_policy {
+        if let Some(name) = path.strip_prefix("preset:") {
+            let preset = MergePolicy::preset(name).unwrap_or_else(|err| fail(EXIT_FAILURE, err));
+            policies.push(PolicySource::Preset(name.to_string()), preset);
+            continue;
+        }
+
+        let format = args.policy_format.unwrap_or_else(|| PolicyFormat::from_path(Path::new(path)));
+        let user_policy = MergePolicy::load_as(Path::new(path), format, &args.policy_vars).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err));
+        policies.push(PolicySource::File(PathBuf::from(path)), user_policy);
+    }
+
+    let command_line_policy = MergePolicy {
+        strategy: args.strategy,
+        rules: args.strategy_for,
+        aliases: args.aliases,
+        conditions: args.conditions,
+        protected: args.protected,
+        required: args.required,
+        ..Default::default()
+    };
+
+    let layers = match (&args.with, &args.stack) {
+        (Some(with), _) => vec![StackLayer { file: PathBuf::from(with), strategy: None, rules: Vec::new(), policy: None }],
+        (None, Some(manifest)) => stack::load_stack(manifest).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err)),
+        (None, None) => unreachable!("clap requires either --with or --stack")
+    };
+
+    // when streaming the result or the JSON report to stdout, nothing else may be printed there
+    let quiet = args.output.as_deref() == Some("-") || args.report_json.as_deref() == Some("-");
+
+    if args.output.as_deref() == Some("-") && args.report_json.as_deref() == Some("-") {
+        fail(EXIT_FAILURE, "the merged config and the JSON report cannot both be written to stdout");
+    }
 
-    let first_config = read_and_parse(&args.file);
-    let second_config = read_and_parse(&args.with);
+    let parse_options = args.parse.options();
+    let mut merged_config = read_config(&file, args.resolve_includes, &parse_options);
+    let original_config = args.check.then(|| merged_config.clone());
+    let source = if args.preserve_format { read_source(&file, &parse_options) } else { None };
+    let mut total_report = MergeReport::default();
+    let mut recorder = ChangeRecorder::default();
+    let layer_files: Vec<String> = layers.iter().map(|layer| layer.file.display().to_string()).collect();
 
-    // TODO: merge two configs
+    for layer in layers {
+        let overlay = read_config(&layer.file.to_string_lossy(), args.resolve_includes, &parse_options);
 
-    let merged_config = first_config; // Placeholder for merged config
+        let mut layer_policies = policies.clone();
 
-    if !args.dry_run {
-        let output_file_name = args.output.unwrap_or_else(|| args.file);
+        if let Some(path) = &layer.policy {
+            let layer_policy = MergePolicy::load(path, &args.policy_vars).unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, err));
+            layer_policies.push(PolicySource::File(path.clone()), layer_policy);
+        }
 
-        let mut output_file = File::create(&output_file_name)
-            .expect("Failed to create output file");
+        if let Some(manifest) = &args.stack {
+            layer_policies.push(PolicySource::File(manifest.clone()), MergePolicy { strategy: layer.strategy, rules: layer.rules, ..Default::default() });
+        }
 
-        stringify_config(&merged_config, &mut output_file)
-            .expect("Failed to write output");
+        layer_policies.push(PolicySource::CommandLine, command_line_policy.clone());
+
+        let layer_options = MergeOptions {
+            policies: layer_policies,
+            conflict_markers: args.conflict_markers,
+            trace: args.trace_policy || args.audit_log.is_some(),
+            none_values: args.none_values
+        };
+
+        if args.stack.is_some() && !quiet {
+            println!("{} {}", "layer".bold(), layer.file.display());
+        }
+
+        if let Some(path) = &args.explain_policy {
+            print_explanation(&layer_options.policies, path, &merged_config);
+            continue;
+        }
+
+        let report = merge::merge_configs_with_observer(&mut merged_config, overlay, &layer_options, &mut recorder)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, err));
+
+        if !quiet {
+            print_report(&report, args.trace_policy);
+        }
+
+        total_report.absorb(report);
+    }
+
+    if args.explain_policy.is_some() {
+        return;
+    }
+
+    if args.dedup {
+        let removed = merge::dedup_entries(&mut merged_config.block.entries);
+        total_report.removed += removed;
+
+        if !quiet {
+            println!("{} removed {} duplicate entries", "dedup".cyan(), removed);
+        }
+    }
+
+    if let Some(target) = &args.report_json {
+        let json_report = JsonReport {
+            file: &file,
+            layers: layer_files.clone(),
+            report: &total_report,
+            elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+        };
+
+        write_json_report(target, &json_report)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write JSON report: {}", err)));
+    }
+
+    if let Some(original_config) = original_config {
+        // with the JSON report on stdout, the exit code alone tells the outcome
+        if merged_config == original_config {
+            if !quiet {
+                println!("{} {} is up to date", "check".green(), file);
+            }
+
+            return;
+        }
+
+        if !quiet {
+            println!("{} {} would change", "check".yellow(), file);
+        }
+
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let header = args.header.then(|| provenance_header(&file, &layer_files, &policies));
+    let format = args.format.options(header);
+
+    if args.verify {
+        output::verify_round_trip(&merged_config, &format, &parse_options)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("the merged config does not round-trip: {}", err)));
+    }
+
+    let output_file_name = if args.output.as_deref() == Some("-") && !args.dry_run {
+        let mut stdout = std::io::stdout().lock();
+
+        match &source {
+            Some(source) => stringify_config_preserving(&merged_config, source, &mut stdout, &format),
+            None => stringify_config_with(&merged_config, &mut stdout, &format)
+        }.unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write to stdout: {}", err)));
+
+        Some("-".to_string())
+    } else if !args.dry_run {
+        let output_file_name = args.output.unwrap_or_else(|| file.clone());
+
+        // conflict markers are comments and intentionally do not round-trip
+        let output_options = OutputOptions { backup: args.backup, validate: total_report.conflicts.is_empty(), format, source, parse: parse_options };
+
+        output::write_output(Path::new(&output_file_name), &merged_config, &output_options)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write {}: {}", output_file_name, err)));
+
+        Some(output_file_name)
+    } else {
+        None
+    };
+
+    if let Some(log) = &args.audit_log {
+        let record = AuditRecord::new(file, layer_files, output_file_name, audit::fired_rules(&total_report), recorder.changes);
+
+        audit::append_audit_record(log, &record)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write audit log {}: {}", log.display(), err)));
+    }
+}
+
+/// Prints the paths where two files differ, exiting with 1 if there are any
+fn diff(base: &str, other: &str, options: &ParseOptions) {
+    let differences = policy::differing_paths(&read_and_parse(base, options), &read_and_parse(other, options));
+
+    for path in &differences {
+        println!("{}", path);
+    }
+
+    if !differences.is_empty() {
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Parses every file and reports whether it is valid, exiting with 2 if any is not
+fn validate(files: &[String], options: &ParseOptions) {
+    let mut failed = false;
+
+    for file in files {
+        match try_read_and_parse(file, options) {
+            Ok(_) => println!("{} {} is valid", "ok".green(), file),
+            Err(err) => {
+                eprintln!("{} {}", "error:".red().bold(), err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(EXIT_PARSE_ERROR);
+    }
+}
+
+/// Writes a file in the requested format to another file or, without one or for `-`, to stdout
+fn convert(file: &str, output: Option<&str>, options: &ParseOptions, format: SerializeOptions) {
+    let config = read_and_parse(file, options);
+
+    match output {
+        Some(output) if output != "-" => {
+            let output_options = OutputOptions { validate: true, format, parse: options.clone(), ..Default::default() };
+
+            output::write_output(Path::new(output), &config, &output_options)
+                .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write {}: {}", output, err)));
+        },
+        _ => stringify_config_with(&config, &mut std::io::stdout().lock(), &format)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write to stdout: {}", err)))
+    }
+}
+
+/// Rewrites text files in the requested format, or with `check` exits with 1 if any of them would change
+fn format_files(files: &[String], check: bool, options: &ParseOptions, format: SerializeOptions) {
+    let mut unformatted = false;
+
+    for file in files {
+        let data = std::fs::read(file)
+            .unwrap_or_else(|err| fail(EXIT_PARSE_ERROR, format!("failed to read {}: {}", file, err)));
+
+        if parsers::bbf::is_binary(&data) {
+            fail(EXIT_FAILURE, format!("{} is a binary file, use `convert` to write it as text", file));
+        }
+
+        let config = read_and_parse(file, options);
+        let mut formatted = Vec::new();
+        stringify_config_with(&config, &mut formatted, &format)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to format {}: {}", file, err)));
+
+        if formatted == data {
+            continue;
+        }
+
+        if check {
+            println!("{} {} would change", "check".yellow(), file);
+            unformatted = true;
+            continue;
+        }
+
+        let output_options = OutputOptions { validate: true, format: format.clone(), parse: options.clone(), ..Default::default() };
+
+        output::write_output(Path::new(file), &config, &output_options)
+            .unwrap_or_else(|err| fail(EXIT_FAILURE, format!("failed to write {}: {}", file, err)));
+        println!("{} {}", "formatted".green(), file);
+    }
+
+    if unformatted {
+        std::process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Main function
+fn main() {
+    match Args::parse().command {
+        Command::Merge(args) => merge(*args),
+        Command::Diff { base, other, parse } => diff(&base, &other, &parse.options()),
+        Command::Validate { files, parse } => validate(&files, &parse.options()),
+        Command::Convert { file, output, parse, format } => convert(&file, output.as_deref(), &parse.options(), format.options(None)),
+        Command::Fmt { files, check, parse, format } => format_files(&files, check, &parse.options(), format.options(None)),
+        Command::Policy { command: PolicyCommand::Validate { file, policy_format, policy_vars } } =>
+            validate_policy_file(&file, policy_format, &policy_vars),
+        Command::Policy { command: PolicyCommand::Test { file, base, with, policy_format, policy_vars, parse } } =>
+            test_policy_file(&file, &base, &with, policy_format, &policy_vars, &parse.options()),
+        Command::Policy { command: PolicyCommand::Init { from, with, output, parse } } => init_policy(&from, &with, output.as_deref(), &parse.options())
     }
 }
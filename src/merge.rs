@@ -0,0 +1,1065 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{policy::{Disallowed, PolicySet}, types::*};
+
+/// Describes how overlay entries are combined with the base.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Overlay values replace base values.
+    #[default]
+    Override,
+    /// Base values win, overlay only contributes missing entries.
+    Keep,
+    /// Overlay entries are appended without matching them against the base.
+    Append,
+    /// Differing values are reported as a conflict.
+    Error,
+    /// Base entries the overlay mentions are removed, the overlay entry itself is dropped.
+    Delete
+}
+
+impl FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "override" | "theirs" => Ok(MergeStrategy::Override),
+            "keep" | "ours" => Ok(MergeStrategy::Keep),
+            "append" => Ok(MergeStrategy::Append),
+            "error" => Ok(MergeStrategy::Error),
+            "delete" => Ok(MergeStrategy::Delete),
+            _ => Err(format!("unknown merge strategy `{}`", s))
+        }
+    }
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MergeStrategy::Override => "override",
+            MergeStrategy::Keep => "keep",
+            MergeStrategy::Append => "append",
+            MergeStrategy::Error => "error",
+            MergeStrategy::Delete => "delete"
+        })
+    }
+}
+
+/// Describes how overlay properties without a value, written as `key:t=`, are merged.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NoneValues {
+    /// The missing value replaces the base value like any other value.
+    #[default]
+    Empty,
+    /// The base property is removed where the overlay would override it.
+    Delete
+}
+
+impl FromStr for NoneValues {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "empty" => Ok(NoneValues::Empty),
+            "delete" => Ok(NoneValues::Delete),
+            _ => Err(format!("unknown handling of missing values `{}`, expected `empty` or `delete`", s))
+        }
+    }
+}
+
+/// Options controlling how an overlay is merged onto a base config.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Policies consulted for every key and section decision.
+    pub policies: PolicySet,
+    /// Keep both values of a conflict in the output instead of aborting the merge.
+    pub conflict_markers: bool,
+    /// Record the policy rule and strategy chosen for every overlay entry in the report.
+    pub trace: bool,
+    /// How overlay properties without a value are merged.
+    pub none_values: NoneValues
+}
+
+/// Records an alias that was applied while merging.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AliasHit {
+    /// Path of the section the aliased entry lives in, `/`-separated.
+    pub path: String,
+    pub from: String,
+    pub to: String
+}
+
+/// Records a conflict that was left in the output as markers.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ConflictRecord {
+    pub path: String,
+    pub base: BlkPropertyValue,
+    pub overlay: BlkPropertyValue
+}
+
+/// Records which policy rule decided the strategy for an overlay entry.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TraceRecord {
+    pub path: String,
+    pub strategy: MergeStrategy,
+    /// Pattern of the winning rule, `None` if the default strategy applied.
+    pub rule: Option<String>,
+    /// Where the winning rule comes from.
+    pub source: Option<String>
+}
+
+/// Summary of what the merge engine did.
+#[derive(Debug, Default, Serialize)]
+pub struct MergeReport {
+    /// Number of overlay entries added to the base.
+    pub added: usize,
+    /// Number of base values replaced by overlay values.
+    pub overridden: usize,
+    /// Number of entries removed from the result.
+    pub removed: usize,
+    pub aliases: Vec<AliasHit>,
+    pub conflicts: Vec<ConflictRecord>,
+    /// Paths of overlay entries skipped because a condition did not hold.
+    pub skipped: Vec<String>,
+    /// Paths of overlay entries skipped because they would have changed or added to a protected entry.
+    pub protected: Vec<String>,
+    /// Paths of overlay values skipped because the policy does not allow them.
+    pub disallowed: Vec<String>,
+    /// Policy decisions for every overlay entry, only recorded when tracing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<TraceRecord>
+}
+
+impl MergeReport {
+    /// Adds the counters and records of another report to this one.
+    pub fn absorb(&mut self, other: MergeReport) {
+        self.added += other.added;
+        self.overridden += other.overridden;
+        self.removed += other.removed;
+        self.aliases.extend(other.aliases);
+        self.conflicts.extend(other.conflicts);
+        self.skipped.extend(other.skipped);
+        self.protected.extend(other.protected);
+        self.disallowed.extend(other.disallowed);
+        self.trace.extend(other.trace);
+    }
+}
+
+/// Errors that abort a merge.
+#[derive(Debug, PartialEq)]
+pub enum MergeError {
+    /// Both configs define a key with different values under the `error` strategy.
+    Conflict { path: String, base: BlkPropertyValue, overlay: BlkPropertyValue },
+    /// The overlay attempts to change or add to an entry protected by a strict rule.
+    Protected { path: String },
+    /// An overlay value cannot be converted to the type a coercion rule demands.
+    Coercion { path: String, message: String },
+    /// An overlay value is not one of the values an allow rule permits.
+    Disallowed { path: String, value: BlkPropertyValue, allowed: String },
+    /// The merged config does not contain entries the policy requires.
+    MissingRequired(Vec<String>),
+    /// The merged config fails assertions of the policy.
+    AssertionFailed(Vec<String>)
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::Conflict { path, base, overlay } =>
+                write!(f, "conflicting values for `{}`: {:?} in base, {:?} in overlay", path, base, overlay),
+            MergeError::Protected { path } => write!(f, "`{}` is protected and cannot be changed by the overlay", path),
+            MergeError::Coercion { path, message } => write!(f, "cannot coerce `{}`: {}", path, message),
+            MergeError::Disallowed { path, value, allowed } =>
+                write!(f, "{:?} is not allowed for `{}`, expected one of {}", value, path, allowed),
+            MergeError::MissingRequired(problems) => {
+                write!(f, "merged config does not meet the policy requirements:")?;
+
+                for problem in problems {
+                    write!(f, "\n    {}", problem)?;
+                }
+
+                Ok(())
+            },
+            MergeError::AssertionFailed(failures) => {
+                write!(f, "merged config fails the policy assertions:")?;
+
+                for failure in failures {
+                    write!(f, "\n    {}", failure)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Why an overlay entry was not applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkipReason {
+    /// The `keep` strategy preserved the base value.
+    Kept,
+    /// A conditional rule did not hold.
+    Condition,
+    /// The entry would have changed or added to a protected entry.
+    Protected,
+    /// The policy does not allow the overlay value.
+    Disallowed
+}
+
+/// A decision the merge engine is about to make, passed to a [`MergeObserver`].
+#[derive(Debug)]
+pub enum MergeDecision<'a> {
+    /// An overlay entry without a counterpart in the base is about to be added.
+    Add { path: &'a str, entry: &'a BlkEntry },
+    /// A base entry is about to be removed by the `delete` strategy.
+    Remove { path: &'a str, entry: &'a BlkEntry },
+    /// A base property value is about to be replaced by a different overlay value.
+    Override { path: &'a str, base: &'a BlkPropertyValue, overlay: &'a BlkPropertyValue },
+    /// An overlay entry is not applied. The returned verdict is ignored.
+    Skip { path: &'a str, entry: &'a BlkEntry, reason: SkipReason }
+}
+
+/// What the merge engine should do with a decision.
+// verdicts are returned one at a time and never stored, so boxing the entry would gain nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Apply the decision as is.
+    Accept,
+    /// Leave the base untouched.
+    Veto,
+    /// Put this entry in the base instead of the overlay entry.
+    Replace(BlkEntry)
+}
+
+/// Callback interface invoked for every decision of the merge engine.
+pub trait MergeObserver {
+    fn on_decision(&mut self, _decision: &MergeDecision) -> Verdict {
+        Verdict::Accept
+    }
+}
+
+impl MergeObserver for () {}
+
+/// Walks the overlay and applies it to the base, keeping track of the current section path.
+struct Merger<'a> {
+    options: &'a MergeOptions,
+    observer: &'a mut dyn MergeObserver,
+    report: MergeReport,
+    path: Vec<String>,
+    /// Copy of the base before merging, used to evaluate conditions. Only taken if the policies have any.
+    original: Option<BlkConfig>
+}
+
+impl Merger<'_> {
+    /// Renames the entry if an alias matches its name, recording the hit.
+    fn apply_alias(&mut self, entry: &mut BlkEntry) {
+        let Some(alias) = self.options.policies.alias_for(entry.name()) else {
+            return;
+        };
+
+        match entry {
+            BlkEntry::Section(section) => section.name = alias.to.clone(),
+            BlkEntry::Property(property) => property.key = alias.to.clone(),
+            BlkEntry::Conflict(_) | BlkEntry::Include(_) => return
+        }
+
+        self.report.aliases.push(AliasHit {
+            path: self.path.join("/"),
+            from: alias.from.clone(),
+            to: alias.to.clone()
+        });
+    }
+
+    /// Coerces, transforms and checks an overlay value at the current path as the policies demand.
+    ///
+    /// Returns `None` if the value is not allowed and should be skipped.
+    fn convert_value(&self, value: BlkPropertyValue) -> Result<Option<BlkPropertyValue>, MergeError> {
+        let value = match self.options.policies.coercion_for(&self.path) {
+            Some(ty) => value.coerce(ty).map_err(|message| MergeError::Coercion { path: self.path.join("/"), message })?,
+            None => value
+        };
+
+        let value = self.options.policies.transform(&self.path, value);
+
+        let Some(rule) = self.options.policies.allow_for(&self.path).filter(|rule| !rule.permits(&value)) else {
+            return Ok(Some(value));
+        };
+
+        match (rule.otherwise, rule.nearest(&value)) {
+            (Disallowed::Keep, _) => Ok(None),
+            (Disallowed::Nearest, Some(nearest)) => Ok(Some(nearest)),
+            _ => Err(MergeError::Disallowed { path: self.path.join("/"), value, allowed: rule.describe_values() })
+        }
+    }
+
+    /// Records an overlay value skipped because the policies do not allow it.
+    fn disallow(&mut self, entry: &BlkEntry, path: String) {
+        self.observer.on_decision(&MergeDecision::Skip { path: &path, entry, reason: SkipReason::Disallowed });
+        self.report.disallowed.push(path);
+    }
+
+    /// Converts the values of all properties nested in an overlay section.
+    fn convert_section(&mut self, section: &mut BlkSection) -> Result<(), MergeError> {
+        self.path.push(section.name.clone());
+
+        for mut entry in std::mem::take(&mut section.entries) {
+            match &mut entry {
+                BlkEntry::Section(section) => self.convert_section(section)?,
+                BlkEntry::Property(property) => {
+                    self.path.push(property.key.clone());
+                    let value = self.convert_value(property.value.clone())?;
+                    let path = self.path.join("/");
+                    self.path.pop();
+
+                    match value {
+                        Some(value) => property.value = value,
+                        None => {
+                            self.disallow(&entry, path);
+                            continue;
+                        }
+                    }
+                },
+                BlkEntry::Conflict(_) | BlkEntry::Include(_) => {}
+            }
+
+            section.entries.push(entry);
+        }
+
+        self.path.pop();
+        Ok(())
+    }
+
+    /// Refuses a change to a protected subtree, failing the merge if the protection is strict.
+    fn protect(&mut self, entry: &BlkEntry, path: String, strict: bool) -> Result<(), MergeError> {
+        if strict {
+            return Err(MergeError::Protected { path });
+        }
+
+        self.observer.on_decision(&MergeDecision::Skip { path: &path, entry, reason: SkipReason::Protected });
+        self.report.protected.push(path);
+        Ok(())
+    }
+
+    /// Adds an overlay entry to the base unless the observer vetoes it.
+    fn add_entry(&mut self, base: &mut Vec<BlkEntry>, mut entry: BlkEntry, path: &str) -> Result<(), MergeError> {
+        if let BlkEntry::Section(section) = &mut entry {
+            self.convert_section(section)?;
+        }
+
+        match self.observer.on_decision(&MergeDecision::Add { path, entry: &entry }) {
+            Verdict::Accept => base.push(entry),
+            Verdict::Veto => return Ok(()),
+            Verdict::Replace(replacement) => base.push(replacement)
+        }
+
+        self.report.added += 1;
+        Ok(())
+    }
+
+    /// Merges overlay entries into the base entries of the same block.
+    ///
+    /// The n-th overlay entry with a given name is matched with the n-th base entry of the
+    /// same kind and name, so repeated sections and keys line up positionally.
+    fn merge_entries(&mut self, base: &mut Vec<BlkEntry>, overlay: Vec<BlkEntry>) -> Result<(), MergeError> {
+        let mut occurrences: HashMap<(bool, String), usize> = HashMap::new();
+        let mut removals = Vec::new();
+
+        for mut entry in overlay {
+            self.apply_alias(&mut entry);
+
+            self.path.push(entry.name().to_string());
+            let path = self.path.join("/");
+            let empty = BlkConfig::default();
+            let original = self.original.as_ref().unwrap_or(&empty);
+            let (strategy, exact) = self.options.policies.strategy_for(&self.path, original);
+            let strategy = match &entry {
+                BlkEntry::Property(BlkProperty { value: BlkPropertyValue::None(_), .. })
+                    if strategy == MergeStrategy::Override && self.options.none_values == NoneValues::Delete => MergeStrategy::Delete,
+                _ => strategy
+            };
+            let protection = self.options.policies.protection_for(&self.path);
+
+            if self.options.trace {
+                let winner = self.options.policies.explain(&self.path, original).into_iter().next();
+
+                self.report.trace.push(TraceRecord {
+                    path: path.clone(),
+                    strategy,
+                    rule: winner.as_ref().map(|winner| winner.rule.pattern.to_string()),
+                    source: winner.map(|winner| winner.source.to_string())
+                });
+            }
+
+            let mut permitted = true;
+
+            if let BlkEntry::Property(property) = &mut entry {
+                match self.convert_value(property.value.clone())? {
+                    Some(value) => property.value = value,
+                    None => permitted = false
+                }
+            }
+
+            let allowed = self.options.policies.conditions_hold(&self.path, original);
+            self.path.pop();
+
+            if !allowed {
+                self.observer.on_decision(&MergeDecision::Skip { path: &path, entry: &entry, reason: SkipReason::Condition });
+                self.report.skipped.push(path);
+                continue;
+            }
+
+            if !permitted {
+                self.disallow(&entry, path);
+                continue;
+            }
+
+            // a section named by an append rule is still matched, only its contents are appended
+            if strategy == MergeStrategy::Append && !(exact && matches!(entry, BlkEntry::Section(_))) {
+                match protection {
+                    Some(strict) => self.protect(&entry, path, strict)?,
+                    None => self.add_entry(base, entry, &path)?
+                }
+
+                continue;
+            }
+
+            let is_section = matches!(entry, BlkEntry::Section(_));
+            let occurrence = occurrences.entry((is_section, entry.name().to_string())).or_insert(0);
+
+            let target = base.iter()
+                .enumerate()
+                .filter(|(_, candidate)| candidate.same_kind(&entry) && candidate.name() == entry.name())
+                .nth(*occurrence)
+                .map(|(index, _)| index);
+
+            *occurrence += 1;
+
+            if strategy == MergeStrategy::Delete {
+                if let (Some(strict), Some(_)) = (protection, target) {
+                    self.protect(&entry, path, strict)?;
+                } else if let Some(index) = target {
+                    match self.observer.on_decision(&MergeDecision::Remove { path: &path, entry: &base[index] }) {
+                        Verdict::Accept => {
+                            removals.push(index);
+                            self.report.removed += 1;
+                        },
+                        Verdict::Veto => {},
+                        Verdict::Replace(entry) => base[index] = entry
+                    }
+                }
+
+                continue;
+            }
+
+            let Some(index) = target else {
+                // protected subtrees accept no new entries, whatever the strategy
+                match protection {
+                    Some(strict) => self.protect(&entry, path, strict)?,
+                    None => self.add_entry(base, entry, &path)?
+                }
+
+                continue;
+            };
+
+            let mut replacement = None;
+
+            match (&mut base[index], entry) {
+                (BlkEntry::Section(base_section), BlkEntry::Section(overlay_section)) => {
+                    self.path.push(overlay_section.name);
+                    self.merge_entries(&mut base_section.entries, overlay_section.entries)?;
+                    self.path.pop();
+                },
+                (BlkEntry::Property(base_property), BlkEntry::Property(overlay_property)) => match strategy {
+                    _ if base_property.value == overlay_property.value => {},
+                    _ if let Some(strict) = protection && strategy != MergeStrategy::Keep => self.protect(&BlkEntry::Property(overlay_property), path, strict)?,
+                    MergeStrategy::Override | MergeStrategy::Append | MergeStrategy::Delete => {
+                        let decision = MergeDecision::Override { path: &path, base: &base_property.value, overlay: &overlay_property.value };
+
+                        match self.observer.on_decision(&decision) {
+                            Verdict::Accept => base_property.value = overlay_property.value,
+                            Verdict::Veto => continue,
+                            Verdict::Replace(entry) => replacement = Some(entry)
+                        }
+
+                        self.report.overridden += 1;
+                    },
+                    MergeStrategy::Keep => {
+                        let entry = BlkEntry::Property(overlay_property);
+                        self.observer.on_decision(&MergeDecision::Skip { path: &path, entry: &entry, reason: SkipReason::Kept });
+                    },
+                    MergeStrategy::Error => {
+                        if !self.options.conflict_markers {
+                            return Err(MergeError::Conflict {
+                                path,
+                                base: base_property.value.clone(),
+                                overlay: overlay_property.value
+                            });
+                        }
+
+                        self.report.conflicts.push(ConflictRecord {
+                            path,
+                            base: base_property.value.clone(),
+                            overlay: overlay_property.value.clone()
+                        });
+
+                        replacement = Some(BlkEntry::Conflict(BlkConflict {
+                            base: Box::new(BlkEntry::Property(base_property.clone())),
+                            overlay: Box::new(BlkEntry::Property(overlay_property))
+                        }));
+                    }
+                },
+                // the same file is included on both sides
+                (BlkEntry::Include(_), BlkEntry::Include(_)) => {},
+                _ => unreachable!("matched entries are always of the same kind")
+            }
+
+            if let Some(entry) = replacement {
+                base[index] = entry;
+            }
+        }
+
+        // removed last so positional matching of the remaining overlay entries is not disturbed
+        removals.sort_unstable();
+
+        for index in removals.into_iter().rev() {
+            base.remove(index);
+        }
+
+        Ok(())
+    }
+}
+
+/// Merges the overlay config into the base config in place.
+pub fn merge_configs(base: &mut BlkConfig, overlay: BlkConfig, options: &MergeOptions) -> Result<MergeReport, MergeError> {
+    merge_configs_with_observer(base, overlay, options, &mut ())
+}
+
+/// Merges the overlay config into the base config in place, consulting the observer for every decision.
+pub fn merge_configs_with_observer(
+    base: &mut BlkConfig,
+    overlay: BlkConfig,
+    options: &MergeOptions,
+    observer: &mut dyn MergeObserver
+) -> Result<MergeReport, MergeError> {
+    let original = options.policies.has_conditions().then(|| base.clone());
+    let mut merger = Merger { options, observer, report: MergeReport::default(), path: Vec::new(), original };
+
+    merger.merge_entries(&mut base.block.entries, overlay.block.entries)?;
+
+    if options.policies.has_strips() {
+        merger.report.removed += strip_entries(&mut base.block.entries, &mut Vec::new(), &options.policies);
+    }
+
+    if options.policies.has_order() {
+        reorder_entries(&mut base.block.entries, &mut Vec::new(), &options.policies);
+    }
+
+    let problems = options.policies.check_required(base);
+
+    if !problems.is_empty() {
+        return Err(MergeError::MissingRequired(problems));
+    }
+
+    let failures = options.policies.check_assertions(base);
+
+    if !failures.is_empty() {
+        return Err(MergeError::AssertionFailed(failures));
+    }
+
+    Ok(merger.report)
+}
+
+/// Removes every entry the policies strip, returning the number of removed entries.
+pub fn strip_entries(entries: &mut Vec<BlkEntry>, path: &mut Vec<String>, policies: &PolicySet) -> usize {
+    let count = entries.len();
+
+    entries.retain(|entry| {
+        path.push(entry.name().to_string());
+        let stripped = policies.strips(path);
+        path.pop();
+        !stripped
+    });
+
+    let mut removed = count - entries.len();
+
+    for entry in entries.iter_mut() {
+        if let BlkEntry::Section(section) = entry {
+            path.push(section.name.clone());
+            removed += strip_entries(&mut section.entries, path, policies);
+            path.pop();
+        }
+    }
+
+    removed
+}
+
+/// Sorts the entries of every section by the order rules of the policies.
+///
+/// The sort is stable, so entries not named by a rule keep their relative order.
+pub fn reorder_entries(entries: &mut [BlkEntry], path: &mut Vec<String>, policies: &PolicySet) {
+    if let Some(rule) = policies.order_for(path) {
+        entries.sort_by_key(|entry| rule.rank(entry.name()));
+    }
+
+    for entry in entries.iter_mut() {
+        if let BlkEntry::Section(section) = entry {
+            path.push(section.name.clone());
+            reorder_entries(&mut section.entries, path, policies);
+            path.pop();
+        }
+    }
+}
+
+/// Removes entries that are structurally identical to an earlier entry of the same section.
+///
+/// Nested sections are deduplicated first, so two sections that only differ by duplicates
+/// inside them are treated as identical. Returns the number of removed entries.
+pub fn dedup_entries(entries: &mut Vec<BlkEntry>) -> usize {
+    let mut removed = 0;
+
+    for entry in entries.iter_mut() {
+        if let BlkEntry::Section(section) = entry {
+            removed += dedup_entries(&mut section.entries);
+        }
+    }
+
+    let mut unique: Vec<BlkEntry> = Vec::with_capacity(entries.len());
+
+    for entry in entries.drain(..) {
+        if unique.contains(&entry) {
+            removed += 1;
+        } else {
+            unique.push(entry);
+        }
+    }
+
+    *entries = unique;
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parsers::blk::parse_config, policy::MergePolicy};
+
+    fn parse(input: &str) -> BlkConfig {
+        parse_config(input).unwrap().1
+    }
+
+    #[test]
+    fn test_merge_overrides_and_appends() {
+        let mut base = parse("a:i=1;graphics{b:i=2;};");
+        let overlay = parse("a:i=5;graphics{c:i=3;};d:t=\"x\";");
+
+        merge_configs(&mut base, overlay, &MergeOptions::default()).unwrap();
+
+        assert_eq!(base, parse("a:i=5;graphics{b:i=2;c:i=3;};d:t=\"x\";"));
+    }
+
+    #[test]
+    fn test_merge_preserves_format() {
+        let source = "// video\ngraphics{\n  sky:i=1 // high\n  clouds:r=0.50;   shadows:b=yes\n\n  /* keep */ water:i=2\n}\nsound{ volume:r=1.0 }\nold:i=3\n";
+        let mut base = parse(source);
+        let overlay = parse("graphics{clouds:r=0.75;added:b=no;};sound{volume:r=1.0;};extra:i=4;");
+
+        merge_configs(&mut base, overlay, &MergeOptions::default()).unwrap();
+
+        let mut output = Vec::new();
+        stringify_config_preserving(&base, source, &mut output, &SerializeOptions::default()).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "// video\ngraphics{\n  sky:i=1 // high\n  clouds:r=0.75;   shadows:b=yes\n\n  /* keep */ water:i=2\n    added:b=no\n}\nsound{ volume:r=1.0 }\nold:i=3\nextra:i=4\n"
+        );
+        assert_eq!(parse(std::str::from_utf8(&output).unwrap()), base);
+
+        base.block.entries.retain(|entry| !matches!(entry, BlkEntry::Property(property) if property.key == "old"));
+
+        let mut output = Vec::new();
+        stringify_config_preserving(&base, source, &mut output, &SerializeOptions::default()).unwrap();
+        assert!(std::str::from_utf8(&output).unwrap().ends_with("}\nsound{ volume:r=1.0 }\nextra:i=4\n"));
+    }
+
+    #[test]
+    fn test_merge_repeated_sections_positionally() {
+        let mut base = parse("line{a:i=1;};line{a:i=2;};");
+        let overlay = parse("line{a:i=1;};line{a:i=7;};line{a:i=9;};");
+
+        merge_configs(&mut base, overlay, &MergeOptions::default()).unwrap();
+
+        assert_eq!(base, parse("line{a:i=1;};line{a:i=7;};line{a:i=9;};"));
+    }
+
+    #[test]
+    fn test_merge_with_alias() {
+        let mut base = parse("graphics{cloudsQuality:i=2;};");
+        let overlay = parse("graphics{skyQuality:i=4;};");
+        let policy = MergePolicy { aliases: vec!["skyQuality=cloudsQuality".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{cloudsQuality:i=4;};"));
+        assert_eq!(report.aliases, vec![AliasHit {
+            path: "graphics".to_string(),
+            from: "skyQuality".to_string(),
+            to: "cloudsQuality".to_string()
+        }]);
+    }
+
+    #[test]
+    fn test_merge_with_section_strategies() {
+        let mut base = parse("controls{hotkeys{ID_AAM{key:i=1;};};};graphics{sky:i=1;};misc{a:i=1;};");
+        let overlay = parse("controls{hotkeys{ID_AAM{key:i=2;};};};graphics{sky:i=2;};misc{a:i=2;};");
+        let policy = MergePolicy {
+            strategy: Some(MergeStrategy::Keep),
+            rules: vec!["hotkeys=append".parse().unwrap(), "graphics=theirs".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("controls{hotkeys{ID_AAM{key:i=1;};ID_AAM{key:i=2;};};};graphics{sky:i=2;};misc{a:i=1;};"));
+    }
+
+    #[test]
+    fn test_merge_with_glob_strategies() {
+        let mut base = parse("controls{hotkeys{ID_AAM{key:i=1;};};joystick{devId:t=\"a\";axes:i=1;};};");
+        let overlay = parse("controls{hotkeys{ID_AAM{key:i=2;};};joystick{devId:t=\"b\";axes:i=2;};};");
+        let policy = MergePolicy {
+            rules: vec!["hotkeys/**=append".parse().unwrap(), "joystick/dev*=keep".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("controls{hotkeys{ID_AAM{key:i=1;};ID_AAM{key:i=2;};};joystick{devId:t=\"a\";axes:i=2;};};"));
+    }
+
+    #[test]
+    fn test_merge_with_delete_strategy() {
+        let mut base = parse("graphics{sky:i=1;legacy:i=1;};telemetry{a:i=1;};hotkeys{a:i=1;};");
+        let overlay = parse("graphics{sky:i=2;legacy:i=0;};telemetry{};hotkeys{a:i=2;};");
+        let policy = MergePolicy {
+            strategy: Some(MergeStrategy::Delete),
+            rules: vec!["graphics=override".parse().unwrap(), "graphics/legacy=delete".parse().unwrap(), "hotkeys/**=keep".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{sky:i=2;};hotkeys{a:i=1;};"));
+        assert_eq!(report.removed, 2);
+    }
+
+    #[test]
+    fn test_merge_with_observer_on_delete() {
+        struct Keep;
+
+        impl MergeObserver for Keep {
+            fn on_decision(&mut self, decision: &MergeDecision) -> Verdict {
+                match decision {
+                    MergeDecision::Remove { path, .. } if *path == "a" => Verdict::Veto,
+                    MergeDecision::Remove { path, entry } if *path == "b" => Verdict::Replace((*entry).clone()),
+                    _ => Verdict::Accept
+                }
+            }
+        }
+
+        let mut base = parse("a:i=1;b:i=2;c:i=3;");
+        let overlay = parse("a:i=0;b:i=0;c:i=0;");
+        let policy = MergePolicy { strategy: Some(MergeStrategy::Delete), ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs_with_observer(&mut base, overlay, &options, &mut Keep).unwrap();
+
+        assert_eq!(base, parse("a:i=1;b:i=2;"));
+        assert_eq!((report.added, report.overridden, report.removed), (0, 0, 1));
+    }
+
+    #[test]
+    fn test_merge_none_values() {
+        let base = parse("graphics{sky:i=1;clouds:t=\"high\";};");
+        let overlay = parse("graphics{sky:i=;clouds:t=\"low\";rain:b=;};");
+
+        let mut empty = base.clone();
+        merge_configs(&mut empty, overlay.clone(), &MergeOptions::default()).unwrap();
+        assert_eq!(empty, parse("graphics{sky:i=;clouds:t=\"low\";rain:b=;};"));
+
+        let mut deleted = base;
+        let options = MergeOptions { none_values: NoneValues::Delete, ..Default::default() };
+        let report = merge_configs(&mut deleted, overlay, &options).unwrap();
+
+        assert_eq!(deleted, parse("graphics{clouds:t=\"low\";};"));
+        assert_eq!(report.removed, 1);
+    }
+
+    #[test]
+    fn test_merge_error_strategy_reports_conflict() {
+        let mut base = parse("graphics{sky:i=1;same:i=3;};");
+        let overlay = parse("graphics{same:i=3;sky:i=2;};");
+        let policy = MergePolicy { strategy: Some(MergeStrategy::Error), ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        assert_eq!(merge_configs(&mut base, overlay, &options).unwrap_err(), MergeError::Conflict {
+            path: "graphics/sky".to_string(),
+            base: BlkPropertyValue::Integer(1),
+            overlay: BlkPropertyValue::Integer(2)
+        });
+    }
+
+    #[test]
+    fn test_merge_error_strategy_with_conflict_markers() {
+        let mut base = parse("graphics{sky:i=1;};");
+        let overlay = parse("graphics{sky:i=2;};");
+        let policy = MergePolicy { strategy: Some(MergeStrategy::Error), ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), conflict_markers: true, ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+        let mut output = Vec::new();
+        stringify_config(&base, &mut output).unwrap();
+
+        assert_eq!(report.conflicts, vec![ConflictRecord {
+            path: "graphics/sky".to_string(),
+            base: BlkPropertyValue::Integer(1),
+            overlay: BlkPropertyValue::Integer(2)
+        }]);
+        assert_eq!(String::from_utf8(output).unwrap(), concat!(
+            "graphics{\n",
+            "    // <<<<<<< base\n",
+            "    sky:i=1\n",
+            "    // =======\n",
+            "    sky:i=2\n",
+            "    // >>>>>>> overlay\n",
+            "}\n"
+        ));
+    }
+
+    #[test]
+    fn test_merge_with_conditions() {
+        let mut base = parse("clientType:t=\"64bit\";graphics{shadowQuality:t=\"ultralow\";sky:i=1;};hud{a:i=1;};");
+        let overlay = parse("graphics{shadowQuality:t=\"low\";sky:i=2;};hud{a:i=2;};");
+        let policy = MergePolicy {
+            conditions: vec![
+                "hud if clientType == \"32bit\"".parse().unwrap(),
+                "graphics/shadowQuality if graphics/shadowQuality == \"ultralow\"".parse().unwrap()
+            ],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("clientType:t=\"64bit\";graphics{shadowQuality:t=\"low\";sky:i=2;};hud{a:i=1;};"));
+        assert_eq!(report.skipped, vec!["hud".to_string()]);
+    }
+
+    #[test]
+    fn test_merge_with_transforms() {
+        let mut base = parse("graphics{rendinstDistMul:r=1.0;};");
+        let overlay = parse("graphics{rendinstDistMul:r=5.0;};sound{volume:r=0.33333;};");
+        let policy = MergePolicy {
+            transforms: vec!["rendinstDistMul clamp 0.1 2.0".parse().unwrap(), "sound/* round 2".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{rendinstDistMul:r=2.0;};sound{volume:r=0.33;};"));
+    }
+
+    #[test]
+    fn test_merge_with_protected_keys() {
+        let mut base = parse("clientType:t=\"64bit\";controls{devId:t=\"a\";axes:i=1;};");
+        let overlay = parse("clientType:t=\"32bit\";controls{devId:t=\"b\";axes:i=2;};");
+        let policy = MergePolicy { protected: vec!["clientType".parse().unwrap(), "devId".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay.clone(), &options).unwrap();
+
+        assert_eq!(base, parse("clientType:t=\"64bit\";controls{devId:t=\"a\";axes:i=2;};"));
+        assert_eq!(report.protected, vec!["clientType".to_string(), "controls/devId".to_string()]);
+
+        let policy = MergePolicy { protected: vec!["devId strict".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        assert_eq!(merge_configs(&mut base, overlay, &options).unwrap_err(), MergeError::Protected { path: "controls/devId".to_string() });
+    }
+
+    #[test]
+    fn test_protected_sections_accept_no_new_entries() {
+        let overlay = parse("controls{a:i=2;b:i=3;};");
+
+        for rule in ["controls=override", "controls/**=append"] {
+            let mut base = parse("controls{a:i=1;};");
+            let policy = MergePolicy { rules: vec![rule.parse().unwrap()], protected: vec!["controls".parse().unwrap()], ..Default::default() };
+            let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+            let report = merge_configs(&mut base, overlay.clone(), &options).unwrap();
+
+            assert_eq!(base, parse("controls{a:i=1;};"), "{}", rule);
+            assert!(report.protected.contains(&"controls/b".to_string()), "{}", rule);
+        }
+
+        let mut base = parse("controls{a:i=1;};");
+        let policy = MergePolicy { protected: vec!["controls strict".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        assert_eq!(merge_configs(&mut base, parse("controls{b:i=3;};"), &options).unwrap_err(), MergeError::Protected { path: "controls/b".to_string() });
+    }
+
+    #[test]
+    fn test_merge_with_coercion() {
+        let mut base = parse("graphics{cloudsQuality:i=1;};");
+        let overlay = parse("graphics{cloudsQuality:r=3.0;sky:t=\"2\";};");
+        let policy = MergePolicy {
+            coercions: vec!["cloudsQuality -> integer".parse().unwrap(), "sky -> integer".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{cloudsQuality:i=3;sky:i=2;};"));
+        assert!(merge_configs(&mut base, parse("graphics{sky:t=\"high\";};"), &options).is_err());
+    }
+
+    #[test]
+    fn test_merge_with_allowed_values() {
+        let mut base = parse("graphics{shadowQuality:t=\"low\";msaa:i=2;};");
+        let overlay = parse("graphics{shadowQuality:t=\"extreme\";msaa:i=3;};hud{scale:r=1.3;};");
+        let policy = MergePolicy {
+            allowed: vec![
+                "shadowQuality {ultralow, low, medium, high} keep".parse().unwrap(),
+                "msaa {0, 2, 4, 8} nearest".parse().unwrap(),
+                "hud/scale {1, 1.5} nearest".parse().unwrap()
+            ],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{shadowQuality:t=\"low\";msaa:i=2;};hud{scale:r=1.5;};"));
+        assert_eq!(report.disallowed, vec!["graphics/shadowQuality"]);
+
+        let policy = MergePolicy { allowed: vec!["shadowQuality {low, high}".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        assert!(matches!(merge_configs(&mut base, parse("graphics{shadowQuality:t=\"extreme\";};"), &options), Err(MergeError::Disallowed { .. })));
+    }
+
+    #[test]
+    fn test_merge_with_trace() {
+        let mut base = parse("controls{hotkeys{a:i=1;};};");
+        let overlay = parse("controls{hotkeys{a:i=2;};};");
+        let policy = MergePolicy { rules: vec!["hotkeys/**=keep".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), trace: true, ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+        let trace: Vec<(&str, MergeStrategy, Option<&str>)> = report.trace.iter()
+            .map(|record| (record.path.as_str(), record.strategy, record.rule.as_deref()))
+            .collect();
+
+        assert_eq!(trace, vec![
+            ("controls", MergeStrategy::Override, None),
+            ("controls/hotkeys", MergeStrategy::Keep, Some("hotkeys/**")),
+            ("controls/hotkeys/a", MergeStrategy::Keep, Some("hotkeys/**"))
+        ]);
+    }
+
+    #[test]
+    fn test_merge_with_output_order() {
+        let mut base = parse("controls{axes{};hotkeys{};misc:i=1;};graphics{};version:i=1;");
+        let overlay = parse("footer:i=1;");
+        let policy = MergePolicy {
+            order: vec!["/ version * footer".parse().unwrap(), "controls hotkeys axes".parse().unwrap()],
+            ..Default::default()
+        };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("version:i=1;controls{hotkeys{};axes{};misc:i=1;};graphics{};footer:i=1;"));
+    }
+
+    #[test]
+    fn test_merge_with_stripped_entries() {
+        let mut base = parse("telemetry{id:i=1;};graphics{debugOverlay:b=yes;sky:i=1;};");
+        let overlay = parse("graphics{sky:i=2;};");
+        let policy = MergePolicy { stripped: vec!["telemetry".parse().unwrap(), "graphics/debug*".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        let report = merge_configs(&mut base, overlay, &options).unwrap();
+
+        assert_eq!(base, parse("graphics{sky:i=2;};"));
+        assert_eq!(report.removed, 2);
+    }
+
+    #[test]
+    fn test_merge_with_assertions() {
+        let policy = MergePolicy { assertions: vec!["graphics/shadowQuality == \"medium\"".parse().unwrap()], ..Default::default() };
+        let options = MergeOptions { policies: policy.into(), ..Default::default() };
+
+        assert!(merge_configs(&mut parse("graphics{shadowQuality:t=\"low\";};"), parse("graphics{shadowQuality:t=\"medium\";};"), &options).is_ok());
+        assert_eq!(
+            merge_configs(&mut parse("graphics{shadowQuality:t=\"low\";};"), parse("graphics{};"), &options).unwrap_err(),
+            MergeError::AssertionFailed(vec!["assertion `graphics/shadowQuality == \"medium\"` failed".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_merge_with_observer() {
+        struct Protect(Vec<String>);
+
+        impl MergeObserver for Protect {
+            fn on_decision(&mut self, decision: &MergeDecision) -> Verdict {
+                match decision {
+                    MergeDecision::Override { path, .. } if *path == "clientType" => Verdict::Veto,
+                    MergeDecision::Add { path, .. } => {
+                        self.0.push(path.to_string());
+                        Verdict::Replace(BlkEntry::Property(BlkProperty {
+                            key: "added".to_string(),
+                            value: BlkPropertyValue::Boolean(true),
+                            quote: QuoteStyle::Double,
+                            hex: false,
+                            hex_color: false,
+                            raw: None,
+                            span: None,
+                            comments: Comments::default()
+                        }))
+                    },
+                    _ => Verdict::Accept
+                }
+            }
+        }
+
+        let mut base = parse("clientType:t=\"32bit\";graphics{sky:i=1;};");
+        let overlay = parse("clientType:t=\"64bit\";graphics{sky:i=2;extra:i=3;};");
+        let mut observer = Protect(Vec::new());
+
+        merge_configs_with_observer(&mut base, overlay, &MergeOptions::default(), &mut observer).unwrap();
+
+        assert_eq!(base, parse("clientType:t=\"32bit\";graphics{sky:i=2;added:b=yes;};"));
+        assert_eq!(observer.0, vec!["graphics/extra".to_string()]);
+    }
+
+    #[test]
+    fn test_dedup_entries() {
+        let mut config = parse("hotkeys{ID_AAM{key:i=1;};ID_AAM{key:i=1;key:i=1;};ID_AAM{key:i=2;};};a:i=1;a:i=1;");
+
+        let removed = dedup_entries(&mut config.block.entries);
+
+        assert_eq!(removed, 3);
+        assert_eq!(config, parse("hotkeys{ID_AAM{key:i=1;};ID_AAM{key:i=2;};};a:i=1;"));
+    }
+}
@@ -0,0 +1,284 @@
+use std::{fmt, fs::File, io::{self, Write}, path::{Path, PathBuf}};
+
+use crate::{encoding::{decode, Encoding, TextEncoding}, parsers::blk::{parse_file, ParseError, ParseOptions}, types::*};
+
+/// Options for writing the merged config to a file.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Copy an existing target to `<file>.<suffix>` before replacing it.
+    /// `{time}` in the suffix is replaced with the current Unix timestamp.
+    pub backup: Option<String>,
+    /// Re-parse the serialized output and refuse to write it unless it matches the config.
+    pub validate: bool,
+    /// How the config is formatted.
+    pub format: SerializeOptions,
+    /// Text of the file the config was parsed from, entries that are unchanged are copied from it.
+    pub source: Option<String>,
+    /// How the output is parsed back when it is validated, the options the config was read with.
+    pub parse: ParseOptions
+}
+
+/// Returns the path a backup of `path` is written to.
+pub fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    let suffix = suffix.replace("{time}", &timestamp.to_string());
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+
+    path.with_file_name(format!("{}.{}", file_name, suffix))
+}
+
+/// Returns the temporary file name used while writing `path`, placed next to it so the final rename stays on one filesystem.
+fn temporary_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()))
+}
+
+/// Writes already serialized output into `path` without ever leaving a partially written file behind.
+///
+/// The output goes to a temporary file in the same directory, which is renamed over
+/// `path` only after the data reached the disk.
+fn write_bytes_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temporary = temporary_path(path);
+
+    let result = File::create(&temporary).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()?;
+
+        if let Ok(metadata) = std::fs::metadata(path) {
+            std::fs::set_permissions(&temporary, metadata.permissions())?;
+        }
+
+        std::fs::rename(&temporary, path)
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+
+    result
+}
+
+/// Reasons serialized output does not read back as the config it was produced from.
+#[derive(Debug)]
+pub enum RoundTripError {
+    /// The config cannot be written as the options demand, e.g. in an encoding lacking some of its characters.
+    Serialize(io::Error),
+    /// The output does not parse.
+    Parse(ParseError),
+    /// The output parses into a config differing from the original first at `path`.
+    Mismatch { path: String }
+}
+
+impl fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoundTripError::Serialize(err) => write!(f, "cannot serialize the config: {}", err),
+            RoundTripError::Parse(err) => write!(f, "serialized output does not parse: {}", err),
+            RoundTripError::Mismatch { path } => write!(f, "serialized output differs from the config at `{}`", path)
+        }
+    }
+}
+
+impl std::error::Error for RoundTripError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RoundTripError::Serialize(err) => Some(err),
+            RoundTripError::Parse(err) => Some(err),
+            RoundTripError::Mismatch { .. } => None
+        }
+    }
+}
+
+/// Serializes the config as the options demand and checks that the output parses back into it
+/// with the parse options the config was read with.
+pub fn verify_round_trip(config: &BlkConfig, options: &SerializeOptions, parse_options: &ParseOptions) -> Result<(), RoundTripError> {
+    let mut buffer = Vec::new();
+    stringify_config_with(config, &mut buffer, options).map_err(RoundTripError::Serialize)?;
+
+    verify_output(&buffer, config, options.encoding.or(config.encoding), parse_options)
+}
+
+/// Checks that serialized output in the given encoding parses back into the config it was produced from.
+fn verify_output(bytes: &[u8], config: &BlkConfig, encoding: Option<&'static Encoding>, parse_options: &ParseOptions) -> Result<(), RoundTripError> {
+    let encoding = TextEncoding::Fixed(encoding.unwrap_or(encoding_rs::UTF_8));
+    let (text, _) = decode(bytes, encoding).map_err(RoundTripError::Serialize)?;
+    let parsed = parse_file(&text, parse_options, &mut Vec::new()).map_err(RoundTripError::Parse)?;
+
+    match first_difference(&config.block.entries, &parsed.block.entries, &mut Vec::new()) {
+        Some(path) => Err(RoundTripError::Mismatch { path }),
+        None => Ok(())
+    }
+}
+
+/// Returns true if two values are equal, counting NaN reals as equal to each other as they
+/// are written and read back as the same literal.
+fn same_value(expected: &BlkPropertyValue, found: &BlkPropertyValue) -> bool {
+    if expected == found {
+        return true;
+    }
+
+    let (mut expected, mut found) = (expected.clone(), found.clone());
+
+    for (x, y) in expected.reals_mut().into_iter().zip(found.reals_mut()) {
+        if x.is_nan() && y.is_nan() {
+            (*x, *y) = (0.0, 0.0);
+        }
+    }
+
+    expected == found
+}
+
+/// Returns true if two entries are equal like their `PartialEq` tells, except that NaN reals are equal.
+fn same_entry(expected: &BlkEntry, found: &BlkEntry) -> bool {
+    match (expected, found) {
+        (BlkEntry::Property(expected), BlkEntry::Property(found)) => expected.key == found.key && same_value(&expected.value, &found.value),
+        (BlkEntry::Section(expected), BlkEntry::Section(found)) => {
+            expected.name == found.name
+                && expected.annotation == found.annotation
+                && expected.entries.len() == found.entries.len()
+                && expected.entries.iter().zip(&found.entries).all(|(expected, found)| same_entry(expected, found))
+        },
+        (BlkEntry::Conflict(expected), BlkEntry::Conflict(found)) =>
+            same_entry(&expected.base, &found.base) && same_entry(&expected.overlay, &found.overlay),
+        (expected, found) => expected == found
+    }
+}
+
+/// Returns the `/`-separated path of the first entry that differs between two blocks at `path`.
+fn first_difference(expected: &[BlkEntry], found: &[BlkEntry], path: &mut Vec<String>) -> Option<String> {
+    for index in 0..expected.len().max(found.len()) {
+        match (expected.get(index), found.get(index)) {
+            (Some(expected), Some(found)) if same_entry(expected, found) => continue,
+            (Some(BlkEntry::Section(expected)), Some(BlkEntry::Section(found))) if expected.name == found.name => {
+                path.push(expected.name.clone());
+                // sections with equal entries differ in their annotations
+                let difference = first_difference(&expected.entries, &found.entries, path).unwrap_or_else(|| path.join("/"));
+                path.pop();
+                return Some(difference);
+            },
+            (expected, found) => {
+                let name = expected.or(found).map(BlkEntry::name).unwrap_or_default();
+                return Some(path.iter().map(String::as_str).chain([name]).collect::<Vec<_>>().join("/"));
+            }
+        }
+    }
+
+    None
+}
+
+/// Writes the config to `path`, backing up the file it replaces if requested.
+///
+/// Nothing is touched on disk if serialization or validation fails.
+pub fn write_output(path: &Path, config: &BlkConfig, options: &OutputOptions) -> io::Result<()> {
+    let mut buffer = Vec::new();
+
+    match &options.source {
+        Some(source) => stringify_config_preserving(config, source, &mut buffer, &options.format)?,
+        None => stringify_config_with(config, &mut buffer, &options.format)?
+    }
+
+    if options.validate {
+        verify_output(&buffer, config, options.format.encoding.or(config.encoding), &options.parse)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    }
+
+    if let Some(suffix) = &options.backup && path.exists() {
+        std::fs::copy(path, backup_path(path, suffix))?;
+    }
+
+    write_bytes_atomically(path, &buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::blk::{parse_config, RealPolicy};
+
+    #[test]
+    fn test_write_output_replaces_file() {
+        let path = std::env::temp_dir().join(format!("blk-merge-atomic-{}.blk", std::process::id()));
+        std::fs::write(&path, "old:i=1\n").unwrap();
+
+        write_output(&path, &parse_config("new:i=2;").unwrap().1, &OutputOptions::default()).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new:i=2\n");
+        assert!(!temporary_path(&path).exists());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_output_with_backup() {
+        let path = std::env::temp_dir().join(format!("blk-merge-backup-{}.blk", std::process::id()));
+        let options = OutputOptions { backup: Some("bak".to_string()), validate: true, ..Default::default() };
+        std::fs::write(&path, "old:i=1\n").unwrap();
+
+        write_output(&path, &parse_config("new:i=2;").unwrap().1, &options).unwrap();
+
+        let backup = backup_path(&path, "bak");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "old:i=1\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new:i=2\n");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn test_verify_round_trip() {
+        let mut config = parse_config("a:r=1; sky{ clouds:r=0.5; tint:c=1, 2, 3, 4 }; b:t=\"Пилот\";").unwrap().1;
+        assert!(verify_round_trip(&config, &SerializeOptions::default(), &ParseOptions::default()).is_ok());
+
+        let options = SerializeOptions { encoding: Some(encoding_rs::WINDOWS_1252), ..Default::default() };
+        assert!(matches!(verify_round_trip(&config, &options, &ParseOptions::default()), Err(RoundTripError::Serialize(_))));
+
+        let options = SerializeOptions { precision: Some(0), normalize_numbers: true, ..Default::default() };
+        assert_eq!(verify_round_trip(&config, &options, &ParseOptions::default()).unwrap_err().to_string(), "serialized output differs from the config at `sky/clouds`");
+
+        if let BlkEntry::Property(property) = &mut config.block.entries[0] {
+            property.value = BlkPropertyValue::Real(f32::NAN);
+        }
+
+        assert!(matches!(verify_round_trip(&config, &SerializeOptions::default(), &ParseOptions::default()), Err(RoundTripError::Parse(_))));
+    }
+
+    #[test]
+    fn test_verify_round_trip_with_parse_options() {
+        let options = ParseOptions { reals: RealPolicy::Accept, ..Default::default() };
+        let config = parse_file("a:r=inf; b:r=nan; c:m=[[1, nan, 0] [0, 1, 0] [0, 0, 1] [0, 0, 0]];", &options, &mut Vec::new()).unwrap();
+
+        assert!(matches!(verify_round_trip(&config, &SerializeOptions::default(), &ParseOptions::default()), Err(RoundTripError::Parse(_))));
+        assert!(verify_round_trip(&config, &SerializeOptions::default(), &options).is_ok());
+    }
+
+    #[test]
+    fn test_write_output_rejects_broken_round_trip() {
+        let path = std::env::temp_dir().join(format!("blk-merge-validate-{}.blk", std::process::id()));
+        // without decimals 0.5 is written as 0, which does not read back as the config
+        let format = SerializeOptions { precision: Some(0), normalize_numbers: true, ..Default::default() };
+        let options = OutputOptions { validate: true, format, ..Default::default() };
+        std::fs::write(&path, "old:i=1\n").unwrap();
+
+        let config = parse_config("real:r=0.5;").unwrap().1;
+
+        assert!(write_output(&path, &config, &options).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old:i=1\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_output_with_nan() {
+        let path = std::env::temp_dir().join(format!("blk-merge-nan-{}.blk", std::process::id()));
+        let parse = ParseOptions { reals: RealPolicy::Accept, ..Default::default() };
+        let config = parse_file("a:r=nan; b:p2=1, nan;", &parse, &mut Vec::new()).unwrap();
+
+        write_output(&path, &config, &OutputOptions { validate: true, parse, ..Default::default() }).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a:r=NaN\nb:p2=1, NaN\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
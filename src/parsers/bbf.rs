@@ -0,0 +1,426 @@
+use std::borrow::Cow;
+
+use crate::{parsers::blk::{ParseLimits, ParseOptions}, types::*};
+
+/// File type byte of an uncompressed binary BLK carrying its own name table.
+const FAT: u8 = 0x01;
+
+/// File type byte of an uncompressed binary BLK whose names are in a shared name map.
+const SLIM: u8 = 0x03;
+
+/// File type byte of a fat binary BLK compressed with zstd.
+const FAT_ZSTD: u8 = 0x02;
+
+/// File type byte of a slim binary BLK compressed with zstd.
+const SLIM_ZSTD: u8 = 0x04;
+
+/// File type byte of a slim binary BLK compressed with zstd using a dictionary.
+const SLIM_ZSTD_DICT: u8 = 0x05;
+
+/// Returns whether the data is a binary BLK rather than text.
+///
+/// Binary files start with a file type byte between `0x01` and `0x05`, or with the
+/// `\0BBF` magic of the legacy format, neither of which can start a text file.
+pub fn is_binary(data: &[u8]) -> bool {
+    matches!(data.first(), Some(0x01..=0x05)) || data.starts_with(b"\0BBF")
+}
+
+/// Reads values from binary BLK data.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self.offset.checked_add(count)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or_else(|| format!("unexpected end of data at offset {}", self.offset))?;
+
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.bytes(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads an unsigned LEB128 number.
+    fn uleb(&mut self) -> Result<usize, String> {
+        let mut value = 0usize;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= usize::from(byte & 0x7F) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(format!("malformed number at offset {}", self.offset))
+    }
+}
+
+/// Reads a name table: the number of names, followed by the size and the data of the null-terminated names.
+fn read_names(reader: &mut Reader) -> Result<Vec<String>, String> {
+    let count = reader.uleb()?;
+
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let size = reader.uleb()?;
+    let names: Vec<String> = reader.bytes(size)?
+        .split(|&byte| byte == 0)
+        .take(count)
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect();
+
+    if names.len() < count {
+        return Err(format!("name table holds {} of {} names", names.len(), count));
+    }
+
+    Ok(names)
+}
+
+/// Decodes a shared name map (`nm` file), which holds a name table on its own.
+pub fn parse_name_map(data: &[u8]) -> Result<Vec<String>, String> {
+    read_names(&mut Reader { data, offset: 0 })
+}
+
+/// Reads a null-terminated string starting at `offset`.
+fn read_string(data: &[u8], offset: usize) -> Result<String, String> {
+    let bytes = data.get(offset..).ok_or_else(|| format!("string offset {} is out of range", offset))?;
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// Decodes the value of a parameter from its type byte and 4 bytes of data, reading
+/// larger values and strings from the parameter data.
+fn read_value(ty: u8, data: u32, params: &[u8], names: &[String]) -> Result<BlkPropertyValue, String> {
+    let offset = data as usize;
+    let chunk = |size: usize| params.get(offset..offset.saturating_add(size))
+        .ok_or_else(|| format!("value offset {} is out of range", offset));
+    let floats = |count: usize| chunk(count * 4).map(|bytes| bytes.chunks(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect::<Vec<_>>());
+    let integers = |count: usize| chunk(count * 4).map(|bytes| bytes.chunks(4)
+        .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect::<Vec<_>>());
+
+    Ok(match ty {
+        // the high bit marks strings stored in the name table
+        0x01 if data & 0x8000_0000 != 0 => {
+            let index = (data & 0x7FFF_FFFF) as usize;
+            BlkPropertyValue::Text(names.get(index).cloned().ok_or_else(|| format!("name index {} is out of range", index))?)
+        },
+        0x01 => BlkPropertyValue::Text(read_string(params, offset)?),
+        0x02 => BlkPropertyValue::Integer(data as i32),
+        0x03 => BlkPropertyValue::Real(f32::from_bits(data)),
+        0x04 => match floats(2)?[..] { [x, y] => BlkPropertyValue::Vector2(x, y), _ => unreachable!() },
+        0x05 => match floats(3)?[..] { [x, y, z] => BlkPropertyValue::Vector3(x, y, z), _ => unreachable!() },
+        0x06 => match floats(4)?[..] { [x, y, z, w] => BlkPropertyValue::Vector4(x, y, z, w), _ => unreachable!() },
+        0x07 => match integers(2)?[..] { [x, y] => BlkPropertyValue::IntVector2(x, y), _ => unreachable!() },
+        0x08 => match integers(3)?[..] { [x, y, z] => BlkPropertyValue::IntVector3(x, y, z), _ => unreachable!() },
+        0x09 => BlkPropertyValue::Boolean(data != 0),
+        // colors are stored as BGRA bytes
+        0x0A => {
+            let [b, g, r, a] = data.to_le_bytes().map(i32::from);
+            BlkPropertyValue::Color(r, g, b, a)
+        },
+        0x0B => {
+            let values = floats(12)?;
+            let row = |index: usize| [values[index * 3], values[index * 3 + 1], values[index * 3 + 2]];
+            BlkPropertyValue::Matrix(Box::new([row(0), row(1), row(2), row(3)]))
+        },
+        0x0C => BlkPropertyValue::Long(i64::from_le_bytes(chunk(8)?.try_into().unwrap())),
+        _ => return Err(format!("unknown parameter type 0x{:02X}", ty))
+    })
+}
+
+/// Decompresses a zstd-compressed binary BLK into the uncompressed file it holds.
+///
+/// Compressed fat files store the size of the zstd frame in 3 bytes after the file type, and
+/// the frame holds a whole fat file. Compressed slim files are followed by the frame right
+/// away, which holds the slim file without its file type byte.
+#[cfg(feature = "zstd")]
+fn decompress(data: &[u8], options: &ParseOptions) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let failed = |err: std::io::Error| format!("failed to decompress: {}", err);
+
+    match data[0] {
+        FAT_ZSTD => {
+            let size = data.get(1..4).ok_or("unexpected end of data")?;
+            let size = u32::from_le_bytes([size[0], size[1], size[2], 0]) as usize;
+            let frame = data.get(4..4 + size).ok_or("compressed data is shorter than its size")?;
+
+            zstd::stream::decode_all(frame).map_err(failed)
+        },
+        ty => {
+            let mut decompressed = vec![SLIM];
+
+            if ty == SLIM_ZSTD_DICT {
+                let dictionary = options.zstd_dict.as_deref().ok_or("dictionary-compressed binary BLK files need a zstd dictionary")?;
+                zstd::stream::read::Decoder::with_dictionary(&data[1..], dictionary).map_err(failed)?
+                    .read_to_end(&mut decompressed).map_err(failed)?;
+            } else {
+                zstd::stream::read::Decoder::new(&data[1..]).map_err(failed)?
+                    .read_to_end(&mut decompressed).map_err(failed)?;
+            }
+
+            Ok(decompressed)
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_: &[u8], _: &ParseOptions) -> Result<Vec<u8>, String> {
+    Err("zstd-compressed binary BLK files need blk-merge built with the `zstd` feature".to_string())
+}
+
+/// A block as described in the block table.
+struct BlockInfo {
+    name: String,
+    params: std::ops::Range<usize>,
+    blocks: std::ops::Range<usize>
+}
+
+/// Builds the entries of block `index`, its parameters followed by its sub-blocks.
+///
+/// `built` counts the entries built so far. Blocks may share sub-blocks, so it can exceed
+/// the number of entries in the tables and is checked against the limit on its own.
+fn build_entries(index: usize, blocks: &[BlockInfo], params: &[BlkProperty], depth: usize, built: &mut usize, limits: &ParseLimits) -> Result<Vec<BlkEntry>, String> {
+    let block = &blocks[index];
+
+    if depth > blocks.len() {
+        return Err("block table contains a cycle".to_string());
+    }
+
+    if depth > limits.max_depth {
+        return Err(format!("blocks are nested deeper than {} levels", limits.max_depth));
+    }
+
+    let block_params = params.get(block.params.clone())
+        .ok_or_else(|| format!("parameters of block {} are out of range", index))?;
+
+    *built += block_params.len();
+
+    if *built > limits.max_entries {
+        return Err(format!("the file has more than {} entries", limits.max_entries));
+    }
+
+    let mut entries: Vec<BlkEntry> = block_params.iter().cloned().map(BlkEntry::Property).collect();
+
+    for child in block.blocks.clone() {
+        let section = blocks.get(child).ok_or_else(|| format!("sub-block {} is out of range", child))?;
+
+        *built += 1;
+
+        if *built > limits.max_entries {
+            return Err(format!("the file has more than {} entries", limits.max_entries));
+        }
+
+        entries.push(BlkEntry::Section(BlkSection {
+            name: section.name.clone(),
+            annotation: None,
+            entries: build_entries(child, blocks, params, depth + 1, built, limits)?,
+            span: None,
+            comments: Comments::default()
+        }));
+    }
+
+    Ok(entries)
+}
+
+/// Decodes a binary BLK into the same AST the text parser produces.
+///
+/// The fat format carries its own name table, the slim one refers to the shared name map of
+/// the options instead. Both may be compressed with zstd, which needs the `zstd` feature. After the file type byte they hold,
+/// with counts and sizes written as unsigned LEB128:
+///
+/// - the number of names, followed by the size and the data of the null-terminated names,
+///   which slim files leave out
+/// - the number of blocks and of parameters, and the size and data of the parameter values
+/// - 8 bytes per parameter: a 24-bit name index, a type byte and 4 bytes of data, which is
+///   the value itself if it fits and an offset into the parameter values otherwise
+/// - per block its name index (0 for the root, names are 1-based), its number of parameters
+///   and sub-blocks, and the index of its first sub-block if it has any
+///
+/// Parameters are assigned to blocks in order, and the entries of a block are its
+/// parameters followed by its sub-blocks.
+pub fn parse_bbf(data: &[u8], options: &ParseOptions) -> Result<BlkConfig, String> {
+    if data.starts_with(b"\0BBF") {
+        return Err("the legacy BBF format is not supported".to_string());
+    }
+
+    if let Some(&(FAT_ZSTD | SLIM_ZSTD | SLIM_ZSTD_DICT)) = data.first() {
+        let decompressed = decompress(data, options)?;
+
+        return match decompressed.first() {
+            Some(&(FAT | SLIM)) => parse_bbf(&decompressed, options),
+            _ => Err("compressed data does not hold an uncompressed binary BLK".to_string())
+        };
+    }
+
+    let mut reader = Reader { data, offset: 1 };
+
+    let names: Cow<[String]> = match (data.first(), options.name_map.as_deref()) {
+        (Some(&FAT), _) => Cow::Owned(read_names(&mut reader)?),
+        (Some(&SLIM), Some(name_map)) => Cow::Borrowed(name_map),
+        (Some(&SLIM), None) => return Err("slim binary BLK files need a shared name map".to_string()),
+        (Some(ty), _) => return Err(format!("binary BLK type 0x{:02X} is not supported", ty)),
+        (None, _) => return Err("empty file".to_string())
+    };
+
+    let block_count = reader.uleb()?;
+    let param_count = reader.uleb()?;
+    let values_size = reader.uleb()?;
+    let values = reader.bytes(values_size)?;
+
+    if block_count.saturating_add(param_count) > options.limits.max_entries {
+        return Err(format!("the file has more than {} entries", options.limits.max_entries));
+    }
+
+    if param_count.checked_mul(8).is_none_or(|size| size > data.len() - reader.offset) {
+        return Err(format!("{} parameters do not fit in the file", param_count));
+    }
+
+    let params = (0..param_count)
+        .map(|_| {
+            let header = reader.u32()?;
+            let data = reader.u32()?;
+            let name_index = (header & 0x00FF_FFFF) as usize;
+
+            Ok(BlkProperty {
+                key: names.get(name_index).cloned().ok_or_else(|| format!("name index {} is out of range", name_index))?,
+                value: read_value((header >> 24) as u8, data, values, &names)?,
+                quote: QuoteStyle::Double,
+                hex: false,
+                hex_color: false,
+                raw: None,
+                span: None,
+                comments: Comments::default()
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut blocks = Vec::new();
+    let mut next_param: usize = 0;
+
+    for _ in 0..block_count {
+        let name = match reader.uleb()? {
+            0 => String::new(),
+            index => names.get(index - 1).cloned().ok_or_else(|| format!("name index {} is out of range", index - 1))?
+        };
+        let params = reader.uleb()?;
+        let children = reader.uleb()?;
+        let first_child = if children > 0 { reader.uleb()? } else { 0 };
+
+        let last_param = next_param.checked_add(params).ok_or("parameter count is out of range")?;
+        let last_child = first_child.checked_add(children).ok_or("sub-block count is out of range")?;
+
+        blocks.push(BlockInfo { name, params: next_param..last_param, blocks: first_child..last_child });
+        next_param = last_param;
+    }
+
+    if blocks.is_empty() {
+        return Err("file contains no root block".to_string());
+    }
+
+    Ok(BlkConfig { block: BlkBlock { entries: build_entries(0, &blocks, &params, 0, &mut 0, &options.limits)? }, bom: false, crlf: false, encoding: None, comments: Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::blk::parse_config;
+
+    #[test]
+    fn test_parse_bbf() {
+        // names: graphics, sky, mode, enabled
+        let mut data = vec![FAT, 4, 26];
+        data.extend(b"graphics\0sky\0mode\0enabled\0");
+        // 2 blocks, 3 parameters, 5 bytes of values
+        data.extend([2, 3, 5]);
+        data.extend(b"high\0");
+        data.extend([1, 0, 0, 0x02, 2, 0, 0, 0]);
+        data.extend([2, 0, 0, 0x01, 0, 0, 0, 0]);
+        data.extend([3, 0, 0, 0x09, 1, 0, 0, 0]);
+        // root: 1 parameter and the block at index 1, graphics: 2 parameters
+        data.extend([0, 1, 1, 1]);
+        data.extend([1, 2, 0]);
+
+        assert!(is_binary(&data));
+        assert!(!is_binary(b"sky:i=2"));
+        assert_eq!(parse_bbf(&data, &ParseOptions::default()).unwrap(), parse_config("sky:i=2;graphics{mode:t=\"high\";enabled:b=yes;};").unwrap().1);
+        assert!(parse_bbf(&data[..20], &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_slim_bbf() {
+        let name_map = parse_name_map(b"\x02\x0dgraphics\0sky\0").unwrap();
+        // 2 blocks, 1 parameter named `sky` holding the name map's `graphics`, the root holding the block `graphics`
+        let mut data = vec![SLIM, 2, 1, 0];
+        data.extend([1, 0, 0, 0x01, 0, 0, 0, 0x80]);
+        data.extend([0, 0, 1, 1]);
+        data.extend([1, 1, 0]);
+
+        assert_eq!(name_map, vec!["graphics", "sky"]);
+        let options = ParseOptions { name_map: Some(name_map), ..Default::default() };
+
+        assert_eq!(parse_bbf(&data, &options).unwrap(), parse_config("graphics{sky:t=\"graphics\";};").unwrap().1);
+        assert!(parse_bbf(&data, &ParseOptions::default()).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_parse_zstd_bbf() {
+        let fat = [FAT, 1, 4, b's', b'k', b'y', 0, 1, 1, 0, 0, 0, 0, 0x02, 2, 0, 0, 0, 0, 1, 0];
+        let frame = zstd::stream::encode_all(&fat[..], 3).unwrap();
+        let mut data = vec![FAT_ZSTD];
+        data.extend(&(frame.len() as u32).to_le_bytes()[..3]);
+        data.extend(frame);
+
+        assert_eq!(parse_bbf(&data, &ParseOptions::default()).unwrap(), parse_config("sky:i=2;").unwrap().1);
+
+        let mut data = vec![SLIM_ZSTD];
+        data.extend(zstd::stream::encode_all(&[1, 1, 0, 0, 0, 0, 0x02, 2, 0, 0, 0, 0, 1, 0][..], 3).unwrap());
+        let options = ParseOptions { name_map: Some(vec!["sky".to_string()]), ..Default::default() };
+
+        assert_eq!(parse_bbf(&data, &options).unwrap(), parse_config("sky:i=2;").unwrap().1);
+    }
+
+    #[test]
+    fn test_parse_bbf_rejects_overflowing_counts() {
+        // 1 block, no parameters, a huge number of sub-blocks starting at a huge index
+        let mut data = vec![FAT, 0, 1, 0, 0, 0, 0];
+        data.extend([0xFF; 9]);
+        data.push(0x01);
+        data.extend([0xFF; 9]);
+        data.push(0x01);
+
+        assert!(parse_bbf(&data, &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_bbf_limits_entries_of_shared_blocks() {
+        // names: a; 5 blocks whose sub-block ranges overlap, so blocks 2 to 4 are built more than once
+        let mut data = vec![FAT, 1, 2];
+        data.extend(b"a\0");
+        data.extend([5, 0, 0]);
+        data.extend([0, 0, 2, 1]);
+        data.extend([1, 0, 2, 2]);
+        data.extend([1, 0, 2, 3]);
+        data.extend([1, 0, 0]);
+        data.extend([1, 0, 0]);
+
+        let parsed = parse_bbf(&data, &ParseOptions::default()).unwrap();
+        assert_eq!(parsed.block.entries.len(), 2);
+
+        let options = ParseOptions { limits: ParseLimits { max_entries: 5, ..Default::default() }, ..Default::default() };
+        assert_eq!(parse_bbf(&data, &options).unwrap_err(), "the file has more than 5 entries");
+    }
+}
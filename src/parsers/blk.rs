@@ -1,19 +1,578 @@
-use nom::{branch::alt, bytes::complete::{tag, take_until}, character::complete::{alpha1, char, digit1, multispace0}, combinator::recognize, multi::{many0, many1}, sequence::{delimited, terminated}, IResult, Parser};
-use crate::types::*;
+use nom::{branch::alt, bytes::complete::{escaped, is_not, tag, take_until, take_while1}, character::complete::{anychar, char, hex_digit1, multispace1, not_line_ending, one_of, space0, space1}, combinator::{all_consuming, eof, not, opt, peek, recognize}, multi::{many0, many1}, sequence::{delimited, preceded, terminated}, IResult, Parser};
+use std::{borrow::Cow, cell::{Cell, RefCell}, collections::{hash_map::Entry, HashMap}, fmt, io::{self, Read}, ops::Range, str::FromStr};
+
+use crate::{encoding::TextEncoding, types::*};
+
+/// Byte order mark some editors start UTF-8 files with.
+const BOM: char = '\u{FEFF}';
+
+/// What happens to `:i=` values that do not fit in 32 bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum IntegerOverflow {
+    /// Fail to parse the file.
+    Error,
+    /// Read the value as a 64-bit `:i64=` value.
+    #[default]
+    Promote,
+    /// Clamp the value to the 32-bit range and report a warning.
+    Saturate
+}
+
+impl FromStr for IntegerOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(IntegerOverflow::Error),
+            "promote" => Ok(IntegerOverflow::Promote),
+            "saturate" => Ok(IntegerOverflow::Saturate),
+            _ => Err(format!("unknown overflow handling `{}`, expected `error`, `promote` or `saturate`", s))
+        }
+    }
+}
+
+/// What happens to real values that are infinite or not a number, which the game cannot read.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RealPolicy {
+    /// Keep them as parsed.
+    Accept,
+    /// Fail to parse the file.
+    #[default]
+    Reject,
+    /// Replace them with 0 and report a warning.
+    Replace
+}
+
+impl FromStr for RealPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(RealPolicy::Accept),
+            "reject" => Ok(RealPolicy::Reject),
+            "replace" => Ok(RealPolicy::Replace),
+            _ => Err(format!("unknown real policy `{}`, expected `accept`, `reject` or `replace`", s))
+        }
+    }
+}
+
+/// Which extensions of the game's BLK syntax the parser accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    /// `//` and `/* */` comments.
+    pub comments: bool,
+    /// `include "file.blk"` directives.
+    pub includes: bool,
+    /// `0x` hexadecimal integers and `#RRGGBBAA` colors.
+    pub hex: bool,
+    /// Unquoted names with `.`, `-`, `/` or `@` besides alphanumerics and `_`.
+    pub loose_identifiers: bool,
+    /// A `,` after the last component of a vector or color.
+    pub trailing_commas: bool,
+    /// Text values in `"""` quotes, taken as written and possibly spanning several lines.
+    pub raw_strings: bool
+}
+
+impl Dialect {
+    /// Accepts every extension, for merging files as forgiving as possible.
+    pub const LENIENT: Dialect = Dialect { comments: true, includes: true, hex: true, loose_identifiers: true, trailing_commas: true, raw_strings: true };
+    /// Accepts no extension, for validating that files use only the syntax the game reads.
+    pub const STRICT: Dialect = Dialect { comments: false, includes: false, hex: false, loose_identifiers: false, trailing_commas: false, raw_strings: false };
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::LENIENT
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lenient" => Ok(Dialect::LENIENT),
+            "strict" => Ok(Dialect::STRICT),
+            _ => Err(format!("unknown dialect `{}`, expected `lenient` or `strict`", s))
+        }
+    }
+}
+
+/// Limits guarding the parser against corrupted or malicious files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseLimits {
+    /// Deepest nesting of sections.
+    pub max_depth: usize,
+    /// Most entries in a file, counting the ones in sections.
+    pub max_entries: usize,
+    /// Longest text value in bytes.
+    pub max_value_length: usize
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits { max_depth: 128, max_entries: 10_000_000, max_value_length: 16 * 1024 * 1024 }
+    }
+}
+
+/// Options for parsing BLK files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseOptions {
+    pub overflow: IntegerOverflow,
+    pub reals: RealPolicy,
+    /// Encoding text files read from bytes are decoded from.
+    pub encoding: TextEncoding,
+    pub dialect: Dialect,
+    pub limits: ParseLimits,
+    /// Warn about properties defined more than once in the same section.
+    pub warn_duplicates: bool,
+    /// Shared name map slim binary files refer to.
+    pub name_map: Option<Vec<String>>,
+    /// Dictionary of dictionary-compressed binary files.
+    pub zstd_dict: Option<Vec<u8>>
+}
+
+/// Error for a BLK file that does not parse, located at the offending line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// The offending line.
+    pub text: String
+}
+
+impl ParseError {
+    /// Creates an error located at `remaining`, the part of `input` left at the problem.
+    fn at(input: &str, remaining: &str, message: impl Into<String>) -> ParseError {
+        let before = &input[..input.len() - remaining.len()];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        ParseError {
+            message: message.into(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].trim_start_matches(BOM).chars().count() + 1,
+            text: input[line_start..].lines().next().unwrap_or_default().to_string()
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} at line {}, column {}", self.message, self.line, self.column)?;
+        writeln!(f, "    {}", self.text)?;
+        write!(f, "    {}^", " ".repeat(self.column - 1))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Kind of problem a [`ParseWarning`] reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    /// A value that was changed to be read, like a saturated integer or a replaced real.
+    SuspiciousValue,
+    /// A key defined more than once in the same section.
+    DuplicateKey
+}
+
+/// Problem in a BLK file that does not keep it from parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseWarning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// Where the problem is, `None` if it was not found in text.
+    pub span: Option<Span>
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A property defined more than once in the same section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateKey {
+    /// Path of the property, the names of its sections followed by its key.
+    pub path: Vec<String>,
+    /// Where the property is defined first.
+    pub first: Option<Span>,
+    /// Where it is defined again.
+    pub duplicate: Option<Span>
+}
+
+impl fmt::Display for DuplicateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.first, &self.duplicate) {
+            (Some(first), Some(duplicate)) => write!(
+                f,
+                "`{}` is defined again at line {}, column {}, first at line {}, column {}",
+                self.path.join("/"), duplicate.line, duplicate.column, first.line, first.column
+            ),
+            _ => write!(f, "`{}` is defined more than once", self.path.join("/"))
+        }
+    }
+}
+
+/// Finds properties defined more than once in the same section. Repeated sections are left
+/// alone, as they are the way BLK files write arrays.
+pub fn find_duplicate_keys(config: &BlkConfig) -> Vec<DuplicateKey> {
+    fn find(entries: &[BlkEntry], path: &mut Vec<String>, duplicates: &mut Vec<DuplicateKey>) {
+        let mut seen: HashMap<&str, &BlkProperty> = HashMap::new();
+
+        for entry in entries {
+            match entry {
+                BlkEntry::Property(property) => match seen.entry(&property.key) {
+                    Entry::Occupied(first) => duplicates.push(DuplicateKey {
+                        path: path.iter().cloned().chain([property.key.clone()]).collect(),
+                        first: first.get().span.clone(),
+                        duplicate: property.span.clone()
+                    }),
+                    Entry::Vacant(vacant) => {
+                        vacant.insert(property);
+                    }
+                },
+                BlkEntry::Section(section) => {
+                    path.push(section.name.clone());
+                    find(&section.entries, path, duplicates);
+                    path.pop();
+                },
+                _ => {}
+            }
+        }
+    }
+
+    let mut duplicates = Vec::new();
+    find(&config.block.entries, &mut Vec::new(), &mut duplicates);
+    duplicates
+}
+
+/// Adds warnings about duplicate keys in the config to `warnings` if the options ask for them.
+fn warn_duplicates(config: &BlkConfig, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) {
+    if options.warn_duplicates {
+        warnings.extend(find_duplicate_keys(config).into_iter().map(|duplicate| ParseWarning {
+            kind: WarningKind::DuplicateKey,
+            message: duplicate.to_string(),
+            span: duplicate.duplicate
+        }));
+    }
+}
+
+/// State shared by the parsers of one file.
+struct Context<'o, 's> {
+    options: &'o ParseOptions,
+    warnings: RefCell<Vec<ParseWarning>>,
+    /// The text being parsed.
+    source: &'s str,
+    /// Byte offsets of the lines of the text.
+    line_starts: Vec<usize>,
+    /// Number of line breaks in the whitespace right before the text, if it is a part of a file.
+    lead_newlines: usize,
+    /// Number of sections the parser is in.
+    depth: Cell<usize>,
+    /// Number of entries parsed so far.
+    entries: Cell<usize>,
+    /// Byte offset and description of the problem that stopped the parser.
+    failure: RefCell<Option<(usize, String)>>
+}
+
+impl<'o, 's> Context<'o, 's> {
+    fn new(options: &'o ParseOptions, source: &'s str) -> Self {
+        let line_starts = std::iter::once(0).chain(source.match_indices('\n').map(|(index, _)| index + 1)).collect();
+        Context {
+            options,
+            warnings: RefCell::new(Vec::new()),
+            source,
+            line_starts,
+            lead_newlines: 0,
+            depth: Cell::new(0),
+            entries: Cell::new(0),
+            failure: RefCell::new(None)
+        }
+    }
+
+    /// Records a problem of the text at `input`, returning the error that stops the parser.
+    fn fail<'a>(&self, input: &'a str, message: String) -> nom::Err<nom::error::Error<&'a str>> {
+        *self.failure.borrow_mut() = Some((self.source.len() - input.len(), message));
+        nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::TooLarge))
+    }
+
+    /// Fails at the first comment in the text from `start` up to `end`, which holds no
+    /// strings, unless the dialect allows comments.
+    fn check_comments<'a>(&self, start: &'a str, end: &str) -> Result<(), nom::Err<nom::error::Error<&'a str>>> {
+        match start[..start.len() - end.len()].find('/') {
+            Some(index) if !self.options.dialect.comments => Err(self.fail(&start[index..], "comments are not allowed by the strict dialect".to_string())),
+            _ => Ok(())
+        }
+    }
+
+    /// Reports a warning about the text from `start` up to `end`.
+    fn warn(&self, kind: WarningKind, start: &str, end: &str, message: String) {
+        self.warnings.borrow_mut().push(ParseWarning { kind, message, span: Some(self.span(start, end)) });
+    }
+
+    /// Applies the real policy to the infinite and NaN reals of the value of the property `key`
+    /// parsed from `input` up to `end`.
+    fn check_reals<'a>(&self, input: &'a str, end: &str, key: &str, value: &mut BlkPropertyValue) -> Result<(), nom::Err<nom::error::Error<&'a str>>> {
+        for real in value.reals_mut().into_iter().filter(|real| !real.is_finite()) {
+            match self.options.reals {
+                RealPolicy::Accept => {},
+                RealPolicy::Reject => return Err(self.fail(input, format!("`{}` has the value {}, which is not a finite number", key, real))),
+                RealPolicy::Replace => {
+                    self.warn(WarningKind::SuspiciousValue, input, end, format!("`{}` has the value {}, replaced with 0", key, real));
+                    *real = 0.0;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `parser` on the content of a section, failing if sections are nested too deeply.
+    fn nested<'a, T>(&self, input: &'a str, parser: impl FnOnce(&'a str) -> IResult<&'a str, T>) -> IResult<&'a str, T> {
+        let max_depth = self.options.limits.max_depth;
+
+        if self.depth.get() >= max_depth {
+            return Err(self.fail(input, format!("sections are nested deeper than {} levels", max_depth)));
+        }
+
+        self.depth.set(self.depth.get() + 1);
+        let result = parser(input);
+        self.depth.set(self.depth.get() - 1);
 
-/// Represents the different types of BLK properties.
-enum BlkType { Text, Boolean, Integer, Real, Point2, Point3, Point4, Color }
+        result
+    }
+
+    /// Returns the span of an entry starting at `start` and ending where `end` begins, both being parts of the text.
+    fn span(&self, start: &str, end: &str) -> Span {
+        let offset = self.source.len() - start.len();
+        let line = self.line_starts.partition_point(|&line_start| line_start <= offset);
+
+        Span {
+            line,
+            column: self.source[self.line_starts[line - 1]..offset].trim_start_matches(BOM).chars().count() + 1,
+            byte_range: offset..self.source.len() - end.len(),
+            blank_before: self.blank_line_before(offset),
+            semicolon: end.trim_start_matches([' ', '\t']).starts_with(';')
+        }
+    }
+
+    /// Returns whether the whitespace right before `offset` holds a blank line.
+    fn blank_line_before(&self, offset: usize) -> bool {
+        let before = &self.source[..offset];
+        let text = before.trim_end_matches(char::is_whitespace);
+        let newlines = before[text.len()..].matches('\n').count();
+
+        newlines + if text.is_empty() { self.lead_newlines } else { 0 } > 1
+    }
+}
+
+/// Moves a span in a part of a file starting at byte `offset`, 0-based `line` and `column`.
+fn shift_span(span: &mut Option<Span>, offset: usize, line: usize, column: usize) {
+    if let Some(span) = span {
+        span.column += if span.line == 1 { column } else { 0 };
+        span.line += line;
+        span.byte_range = span.byte_range.start + offset..span.byte_range.end + offset;
+    }
+}
+
+/// Moves the spans of entries parsed from a part of a file starting at byte `offset`, 0-based `line` and `column`.
+fn shift_spans(entries: &mut [BlkEntry], offset: usize, line: usize, column: usize) {
+    let shift = |span: &mut Option<Span>| shift_span(span, offset, line, column);
+
+    for entry in entries {
+        match entry {
+            BlkEntry::Property(property) => shift(&mut property.span),
+            BlkEntry::Section(section) => {
+                shift(&mut section.span);
+                shift_spans(&mut section.entries, offset, line, column);
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Comments found in the text between two entries.
+struct GapComments<'a> {
+    /// Comments on the line the text starts on, as the text holding them all.
+    same_line: Option<&'a str>,
+    /// Comments on lines of their own, each with whether a blank line comes right before it.
+    own_lines: Vec<(&'a str, bool)>
+}
+
+/// Finds the comments in the text between two entries, which comes after `lead_newlines` line
+/// breaks if it is a part of a file. Besides whitespace, separators and comments, it holds the
+/// includes between the entries, whose quoted paths are skipped.
+fn gap_comments(text: &str, lead_newlines: usize) -> GapComments<'_> {
+    let mut comments = GapComments { same_line: None, own_lines: Vec::new() };
+    let mut same_line: Option<Range<usize>> = None;
+    // line breaks since the previous entry, comment or separator
+    let mut newlines = lead_newlines;
+    let mut first_line = lead_newlines == 0;
+    let mut index = 0;
+
+    while let Some(c) = text[index..].chars().next() {
+        let rest = &text[index..];
+
+        let length = if rest.starts_with("//") {
+            rest.find(['\r', '\n']).unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map_or(rest.len(), |end| end + 2)
+        } else {
+            index += match c {
+                '"' | '\'' => {
+                    let mut escaped = false;
+                    let end = rest[1..].char_indices().find(|&(_, next)| {
+                        let closing = !escaped && next == c;
+                        escaped = !escaped && next == '\\';
+                        closing
+                    });
+
+                    end.map_or(rest.len(), |(end, _)| end + 2)
+                },
+                _ => c.len_utf8()
+            };
+
+            match c {
+                '\n' => {
+                    newlines += 1;
+                    first_line = false;
+                },
+                _ if c.is_whitespace() => {},
+                _ => newlines = 0
+            }
+
+            continue;
+        };
+
+        if first_line {
+            same_line = Some(same_line.map_or(index, |range| range.start)..index + length);
+        } else {
+            comments.own_lines.push((&rest[..length], newlines > 1));
+        }
+
+        newlines = 0;
+        index += length;
+    }
+
+    comments.same_line = same_line.map(|range| &text[range]);
+    comments
+}
+
+/// Returns the comments and span of a section or property parsed from text.
+fn annotated(entry: &mut BlkEntry) -> Option<(&mut Comments, &mut Span)> {
+    match entry {
+        BlkEntry::Property(property) => Some((&mut property.comments, property.span.as_mut()?)),
+        BlkEntry::Section(section) => Some((&mut section.comments, section.span.as_mut()?)),
+        _ => None
+    }
+}
+
+/// Returns the range between the braces of a section written at `range` in `source`.
+fn section_body(source: &str, range: Range<usize>) -> Range<usize> {
+    let text = &source[range.clone()];
+    let after_name = opt(parse_name).parse(text).map_or(text, |(rest, _)| rest);
+    let brace = text.len() - after_name.len() + after_name.find('{').unwrap_or(0);
+
+    range.start + brace + 1..range.end - 1
+}
+
+/// Attaches the comments in `source[range]`, the text of a block coming after `lead_newlines`
+/// line breaks, to the entries parsed from it. Returns the comments on the line the block starts on before its first entry, and the
+/// comments after its last entry along with whether a blank line comes right before each.
+fn attach_comments(entries: &mut [BlkEntry], source: &str, range: Range<usize>, lead_newlines: usize) -> (Option<String>, Vec<(String, bool)>) {
+    let mut cursor = range.start;
+    let mut lead_newlines = lead_newlines;
+    let mut previous = None;
+    let mut header = None;
+
+    // includes are not spanned, so their text is part of the gap around them
+    for index in 0..entries.len() {
+        let Some(start) = entries[index].span().map(|span| span.byte_range.start) else {
+            continue;
+        };
+
+        let gap = gap_comments(&source[cursor..start], std::mem::take(&mut lead_newlines));
+        let same_line = gap.same_line.map(str::to_string);
+
+        match previous.and_then(|previous| annotated(&mut entries[previous])) {
+            Some((comments, _)) => comments.trailing = same_line,
+            None => header = same_line
+        }
+
+        let Some((comments, span)) = annotated(&mut entries[index]) else {
+            continue;
+        };
+
+        if let Some(&(_, blank)) = gap.own_lines.first() {
+            span.blank_before = blank;
+        }
+
+        comments.leading = gap.own_lines.into_iter().map(|(comment, _)| comment.to_string()).collect();
+        cursor = span.byte_range.end;
+        previous = Some(index);
+
+        if let BlkEntry::Section(section) = &mut entries[index] {
+            let (header, closing) = attach_comments(&mut section.entries, source, section_body(source, start..cursor), 0);
+
+            section.comments.header = header;
+            section.comments.closing = closing.into_iter().map(|(comment, _)| comment).collect();
+        }
+    }
+
+    let gap = gap_comments(&source[cursor..range.end], lead_newlines);
+    let same_line = gap.same_line.map(str::to_string);
+
+    match previous.and_then(|previous| annotated(&mut entries[previous])) {
+        Some((comments, _)) => comments.trailing = same_line,
+        None => header = same_line
+    }
+
+    (header, gap.own_lines.into_iter().map(|(comment, blank)| (comment.to_string(), blank)).collect())
+}
+
+/// Attaches the comments in `source[range]` to the top-level entries parsed from it, like
+/// [`attach_comments`] does, leading the first entry with the `pending` comments found before the range. Returns the comments
+/// after the last entry along with whether a blank line comes right before each.
+fn attach_top_level_comments(entries: &mut [BlkEntry], source: &str, range: Range<usize>, lead_newlines: usize, pending: Vec<(String, bool)>) -> Vec<(String, bool)> {
+    let (header, closing) = attach_comments(entries, source, range, lead_newlines);
+    // nothing comes before the text on its first line, so comments there lead the first entry
+    let mut leading: Vec<(String, bool)> = pending.into_iter().chain(header.map(|comment| (comment, false))).collect();
+
+    match entries.iter_mut().find_map(annotated) {
+        Some((comments, span)) if !leading.is_empty() => {
+            span.blank_before = leading[0].1;
+            comments.leading.splice(0..0, leading.into_iter().map(|(comment, _)| comment));
+            closing
+        },
+        Some(_) => closing,
+        None => {
+            leading.extend(closing);
+            leading
+        }
+    }
+}
+
+/// Drops whether blank lines come before comments.
+fn comment_texts(comments: Vec<(String, bool)>) -> Vec<String> {
+    comments.into_iter().map(|(comment, _)| comment).collect()
+}
 
 /// Parses a BLK type identifier from the input string.
 fn parse_blk_type(input: &str) -> IResult<&str, BlkType> {
     alt((
         tag("t").map(|_| BlkType::Text),
         tag("b").map(|_| BlkType::Boolean),
+        tag("i64").map(|_| BlkType::Long),
+        tag("ip2").map(|_| BlkType::IntPoint2),
+        tag("ip3").map(|_| BlkType::IntPoint3),
         tag("i").map(|_| BlkType::Integer),
         tag("r").map(|_| BlkType::Real),
         tag("p2").map(|_| BlkType::Point2),
         tag("p3").map(|_| BlkType::Point3),
         tag("p4").map(|_| BlkType::Point4),
+        tag("m").map(|_| BlkType::Matrix),
         tag("c").map(|_| BlkType::Color)
     )).parse(input)
 }
@@ -23,7 +582,7 @@ fn parse_property_value(ty: BlkType) -> impl Fn(&str) -> IResult<&str, BlkProper
     move |input: &str| {
         match ty {
             BlkType::Text => parse_string
-                .map(|text| BlkPropertyValue::Text(text.to_string()))
+                .map(|text| BlkPropertyValue::Text(text.into_owned()))
                 .parse(input),
             BlkType::Boolean => parse_boolean
                 .map(BlkPropertyValue::Boolean)
@@ -31,6 +590,9 @@ fn parse_property_value(ty: BlkType) -> impl Fn(&str) -> IResult<&str, BlkProper
             BlkType::Integer => parse_integer
                 .map(BlkPropertyValue::Integer)
                 .parse(input),
+            BlkType::Long => nom::character::complete::i64
+                .map(BlkPropertyValue::Long)
+                .parse(input),
             BlkType::Real => parse_real
                 .map(BlkPropertyValue::Real)
                 .parse(input),
@@ -53,6 +615,20 @@ fn parse_property_value(ty: BlkType) -> impl Fn(&str) -> IResult<&str, BlkProper
                 ).parse(input)?;
                 Ok((rest, BlkPropertyValue::Vector4(x, y, z, w)))
             }
+            BlkType::IntPoint2 => {
+                let (rest, (x, _, y)) =
+                    (parse_integer, parse_vector_delimiter, parse_integer).parse(input)?;
+                Ok((rest, BlkPropertyValue::IntVector2(x, y)))
+            }
+            BlkType::IntPoint3 => {
+                let (rest, (x, y, z)) =
+                    (terminated(parse_integer, parse_vector_delimiter), terminated(parse_integer, parse_vector_delimiter), parse_integer).parse(input)?;
+                Ok((rest, BlkPropertyValue::IntVector3(x, y, z)))
+            }
+            BlkType::Matrix => parse_matrix
+                .map(|rows| BlkPropertyValue::Matrix(Box::new(rows)))
+                .parse(input),
+            BlkType::Color if input.starts_with('#') => parse_hex_color(input),
             BlkType::Color => {
                 let (rest, (r, g, b, a)) = (
                     terminated(parse_integer, parse_vector_delimiter),
@@ -66,82 +642,847 @@ fn parse_property_value(ty: BlkType) -> impl Fn(&str) -> IResult<&str, BlkProper
     }
 }
 
-/// Parses a newline character, supporting both Unix and Windows formats.
-fn newline_multiplatform(input: &str) -> IResult<&str, ()> {
-    alt((tag("\r\n"), tag("\n"))).map(|_| ()).parse(input)
-}
+/// Parses a newline character, supporting both Unix and Windows formats.
+fn newline_multiplatform(input: &str) -> IResult<&str, ()> {
+    alt((tag("\r\n"), tag("\n"))).map(|_| ()).parse(input)
+}
+
+/// Parses an identifier from the input string, made of alphanumerics and `_`, `.`, `-`, `/` or `@`.
+///
+/// A `/` ends the identifier if it starts a comment.
+fn parse_identifier(input: &str) -> IResult<&str, &str> {
+    let slash = terminated(char('/'), not(one_of("/*")));
+    recognize(many1(alt((take_while1(|c| is_identifier_char(c) && c != '/'), recognize(slash))))).parse(input)
+}
+
+/// Parses a key or section name, either a bare identifier or a quoted string.
+fn parse_name(input: &str) -> IResult<&str, Cow<'_, str>> {
+    alt((parse_identifier.map(Cow::Borrowed), parse_string)).parse(input)
+}
+
+/// Parses a key or section name, failing on names the dialect does not allow.
+fn parse_checked_name<'a>(input: &'a str, context: &Context) -> IResult<&'a str, Cow<'a, str>> {
+    let (remaining, name) = parse_name(input)?;
+    check_name(input, &name, context)?;
+
+    Ok((remaining, name))
+}
+
+/// Fails on a name written at `input` that the dialect does not allow.
+fn check_name<'a>(input: &'a str, name: &str, context: &Context) -> Result<(), nom::Err<nom::error::Error<&'a str>>> {
+    let quoted = input.starts_with(['"', '\'']);
+
+    match quoted || context.options.dialect.loose_identifiers || name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        true => Ok(()),
+        false => Err(context.fail(input, format!("`{}` must be quoted in the strict dialect", name)))
+    }
+}
+
+/// Parses a `//` comment up to the end of the line.
+fn parse_line_comment(input: &str) -> IResult<&str, ()> {
+    (tag("//"), not_line_ending).map(|_| ()).parse(input)
+}
+
+/// Parses a `/* */` comment, which may span multiple lines.
+fn parse_block_comment(input: &str) -> IResult<&str, ()> {
+    (tag("/*"), take_until("*/"), tag("*/")).map(|_| ()).parse(input)
+}
+
+/// Parses whitespace and comments between entries.
+fn parse_space(input: &str) -> IResult<&str, ()> {
+    many0(alt((multispace1.map(|_| ()), parse_line_comment, parse_block_comment))).map(|_| ()).parse(input)
+}
+
+/// Returns whether text holds nothing but whitespace, comments and `;` separators.
+pub fn is_blank(text: &str) -> bool {
+    all_consuming(many0(alt((multispace1.map(|_| ()), parse_line_comment, parse_block_comment, char(';').map(|_| ()))))).parse(text).is_ok()
+}
+
+/// Parses whitespace and block comments that do not end the current line.
+fn parse_inline_space(input: &str) -> IResult<&str, ()> {
+    many0(alt((space1.map(|_| ()), parse_block_comment))).map(|_| ()).parse(input)
+}
+
+/// Parses a line separator, which can be either a newline or a semicolon, optionally preceded by a comment.
+fn parse_separator(input: &str) -> IResult<&str, ()> {
+    many1((parse_inline_space, opt(parse_line_comment), alt((newline_multiplatform, char(';').map(|_| ()))))).map(|_| ()).parse(input)
+}
+
+/// Parses the end of the last entry of a file or section, which needs no separator before
+/// the end of the input or the closing `}`.
+fn parse_final_separator(input: &str) -> IResult<&str, ()> {
+    (parse_inline_space, opt(parse_line_comment), peek(alt((eof, tag("}"))))).map(|_| ()).parse(input)
+}
+
+/// Parses a boolean value from the input string: `yes`, `true`, `on` or `1` and `no`,
+/// `false`, `off` or `0`.
+fn parse_boolean(input: &str) -> IResult<&str, bool> {
+    alt((
+        alt((tag("true"), tag("yes"), tag("on"), tag("1"))).map(|_| true),
+        alt((tag("false"), tag("no"), tag("off"), tag("0"))).map(|_| false)
+    )).parse(input)
+}
+
+/// Parses an integer value from the input string, either decimal or a `0x` hexadecimal literal.
+///
+/// Hexadecimal literals cover the full 32 bits, so `0xFFFFFFFF` is read as `-1`.
+fn parse_integer(input: &str) -> IResult<&str, i32> {
+    let hex = preceded(alt((tag("0x"), tag("0X"))), hex_digit1)
+        .map_res(|digits| u32::from_str_radix(digits, 16).map(|value| value as i32));
+
+    alt((hex, nom::character::complete::i32)).parse(input)
+}
+
+/// Parses a `#RRGGBBAA` or `#RRGGBB` color from the input string, opaque if the alpha is left out.
+fn parse_hex_color(input: &str) -> IResult<&str, BlkPropertyValue> {
+    preceded(char('#'), hex_digit1).map_opt(|digits: &str| {
+        let value = u32::from_str_radix(digits, 16).ok()?;
+
+        match digits.len() {
+            8 => Some(value.to_be_bytes()),
+            6 => Some((value << 8 | 0xFF).to_be_bytes()),
+            _ => None
+        }
+    }).map(|[r, g, b, a]| BlkPropertyValue::Color(r.into(), g.into(), b.into(), a.into())).parse(input)
+}
+
+/// Parses a real (floating-point) value from the input string.
+fn parse_real(input: &str) -> IResult<&str, f32> {
+    nom::number::complete::float(input)
+}
+
+/// Parses a vector delimiter (comma followed by optional whitespace and comments) from the input string.
+fn parse_vector_delimiter(input: &str) -> IResult<&str, ()> {
+    (char(','), parse_space).map(|_| ()).parse(input)
+}
+
+/// Parses a `[x, y, z]` matrix row from the input string.
+fn parse_matrix_row(input: &str) -> IResult<&str, [f32; 3]> {
+    delimited(
+        (char('['), parse_space),
+        (terminated(parse_real, parse_vector_delimiter), terminated(parse_real, parse_vector_delimiter), parse_real),
+        (parse_space, char(']'))
+    ).map(|(x, y, z)| [x, y, z]).parse(input)
+}
+
+/// Parses a matrix of four rows, e.g. `[[1, 0, 0] [0, 1, 0] [0, 0, 1] [0, 0, 0]]`, from the input string.
+fn parse_matrix(input: &str) -> IResult<&str, [[f32; 3]; 4]> {
+    let row = || preceded(parse_space, parse_matrix_row);
+
+    delimited(char('['), (row(), row(), row(), row()), (parse_space, char(']')))
+        .map(|(a, b, c, d)| [a, b, c, d])
+        .parse(input)
+}
+
+/// Resolves the escapes of a string recognized by [`parse_quoted`].
+fn unescape(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => text.push('\n'),
+                Some('r') => text.push('\r'),
+                Some('t') => text.push('\t'),
+                Some(escaped @ ('"' | '\'' | '\\')) => text.push(escaped),
+                // unknown escapes such as in `C:\Games` are kept as written
+                Some(other) => {
+                    text.push('\\');
+                    text.push(other);
+                },
+                None => break
+            },
+            c => text.push(c)
+        }
+    }
+
+    text
+}
+
+/// Parses a string value enclosed in double or single quotes, resolving the escapes `\"`, `\'`,
+/// `\\`, `\n`, `\r` and `\t` and keeping any other backslash literally. The string borrows from
+/// the input unless it contains escapes.
+fn parse_quoted(quote: QuoteStyle) -> impl Fn(&str) -> IResult<&str, Cow<'_, str>> {
+    move |input: &str| {
+        let normal = match quote {
+            QuoteStyle::Single => is_not("'\\"),
+            _ => is_not("\"\\")
+        };
+
+        delimited(tag(quote.delimiter()), opt(escaped(normal, '\\', anychar)), tag(quote.delimiter()))
+            .map(|raw: Option<&str>| match raw.unwrap_or_default() {
+                raw if raw.contains('\\') => Cow::Owned(unescape(raw)),
+                raw => Cow::Borrowed(raw)
+            })
+            .parse(input)
+    }
+}
+
+/// Parses a raw string value enclosed in `"""` quotes, which has no escapes and may span
+/// several lines, so it always borrows from the input.
+fn parse_raw_string(input: &str) -> IResult<&str, Cow<'_, str>> {
+    let delimiter = QuoteStyle::Triple.delimiter();
+    delimited(tag(delimiter), take_until(delimiter), tag(delimiter))
+        .map(Cow::Borrowed)
+        .parse(input)
+}
+
+/// Parses a string value enclosed in double or single quotes from the input string.
+fn parse_string(input: &str) -> IResult<&str, Cow<'_, str>> {
+    alt((parse_quoted(QuoteStyle::Double), parse_quoted(QuoteStyle::Single))).parse(input)
+}
+
+/// Parses the whole text of a value of the given type, `None` if it is not one.
+pub fn parse_value(ty: BlkType, text: &str) -> Option<BlkPropertyValue> {
+    all_consuming(parse_property_value(ty)).parse(text).ok().map(|(_, value)| value)
+}
+
+/// Parses an integer of the property `key` that does not fit in 32 bits, handled as the options demand.
+fn parse_overflowing_integer<'a>(input: &'a str, key: &str, context: &Context) -> IResult<&'a str, BlkPropertyValue> {
+    let (rest, value) = nom::character::complete::i64(input)?;
+
+    match context.options.overflow {
+        IntegerOverflow::Error => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::MapRes))),
+        IntegerOverflow::Promote => Ok((rest, BlkPropertyValue::Long(value))),
+        IntegerOverflow::Saturate => {
+            let saturated = value.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32;
+            let message = format!("`{}` value {} does not fit in 32 bits, saturated to {}", key, value, saturated);
+
+            context.warn(WarningKind::SuspiciousValue, input, rest, message);
+            Ok((rest, BlkPropertyValue::Integer(saturated)))
+        }
+    }
+}
+
+/// Parses a BLK property from the input string. Spaces and tabs are allowed around `:` and `=`,
+/// a property with nothing after the `=` has no value, and a trailing `,` after a vector is dropped.
+fn parse_property<'a>(input: &'a str, context: &Context) -> IResult<&'a str, BorrowedEntry<'a>> {
+    let (value_input, (key, ty)) = (
+        |input| parse_checked_name(input, context),
+        delimited((space0, char(':'), space0), parse_blk_type, (space0, char('='), space0))
+    ).parse(input)?;
+    let quote = match value_input {
+        value if value.starts_with(QuoteStyle::Triple.delimiter()) => QuoteStyle::Triple,
+        value if value.starts_with('\'') => QuoteStyle::Single,
+        _ => QuoteStyle::Double
+    };
+    let hex = value_input.starts_with("0x") || value_input.starts_with("0X");
+    let hex_color = ty == BlkType::Color && value_input.starts_with('#');
+    let missing = peek((parse_inline_space, alt((eof, tag("//"), tag(";"), tag("}"), tag("\n"), tag("\r\n"))))).parse(value_input).is_ok();
+
+    let (remaining, mut value) = match ty {
+        _ if missing => (value_input, BorrowedValue::Other(BlkPropertyValue::None(ty))),
+        BlkType::Text if quote == QuoteStyle::Triple && !context.options.dialect.raw_strings => {
+            return Err(context.fail(value_input, "raw strings are not allowed by the strict dialect".to_string()));
+        },
+        BlkType::Text => {
+            let (rest, text) = alt((parse_raw_string, parse_string)).parse(value_input)?;
+            let max_length = context.options.limits.max_value_length;
+
+            if text.len() > max_length {
+                return Err(context.fail(value_input, format!("`{}` is longer than {} bytes", key, max_length)));
+            }
+
+            (rest, BorrowedValue::Text(text))
+        },
+        BlkType::Integer => alt((parse_property_value(ty), |input| parse_overflowing_integer(input, &key, context)))
+            .map(BorrowedValue::Other)
+            .parse(value_input)?,
+        _ => parse_property_value(ty).map(BorrowedValue::Other).parse(value_input)?
+    };
+
+    let literal = &value_input[..value_input.len() - remaining.len()];
+    let raw = match ty {
+        BlkType::Text | BlkType::Boolean => None,
+        _ if missing || literal.contains(['\n', '/']) => None,
+        _ => Some(literal)
+    };
+
+    let remaining = match ty {
+        BlkType::Point2 | BlkType::Point3 | BlkType::Point4 | BlkType::IntPoint2 | BlkType::IntPoint3 | BlkType::Color if !missing => {
+            let (rest, comma) = opt(preceded(space0, char(','))).parse(remaining)?;
+
+            if comma.is_some() && !context.options.dialect.trailing_commas {
+                return Err(context.fail(remaining.trim_start_matches([' ', '\t']), "trailing commas are not allowed by the strict dialect".to_string()));
+            }
+
+            rest
+        },
+        _ => remaining
+    };
+
+    if ty != BlkType::Text {
+        context.check_comments(value_input, remaining)?;
+
+        if let BorrowedValue::Other(value) = &mut value {
+            context.check_reals(value_input, remaining, &key, value)?;
+        }
+
+        let value_text = &value_input[..value_input.len() - remaining.len()];
+        if let Some(index) = value_text.find("0x").or_else(|| value_text.find("0X")) && !context.options.dialect.hex {
+            return Err(context.fail(&value_input[index..], "hexadecimal integers are not allowed by the strict dialect".to_string()));
+        }
+
+        if hex_color && !context.options.dialect.hex {
+            return Err(context.fail(value_input, "hexadecimal colors are not allowed by the strict dialect".to_string()));
+        }
+    }
+
+    let span = Some(context.span(input, remaining));
+    Ok((remaining, BorrowedEntry::Property(BorrowedProperty { key, value, quote, hex, hex_color, raw, span })))
+}
+
+/// Parses a BLK section from the input string. Sections without a name, used as array
+/// elements by some files, get an empty name. The name may be followed by a `:tag` or, if
+/// it is not quoted, an `@template` annotation.
+fn parse_section<'a>(input: &'a str, context: &Context) -> IResult<&'a str, BorrowedEntry<'a>> {
+    let (rest, name) = opt(parse_name).parse(input)?;
+    let quoted = input.starts_with(['"', '\'']);
+
+    let (name, template) = match name {
+        Some(Cow::Borrowed(name)) if !quoted && let Some((name, template)) = name.split_once('@') =>
+            (Cow::Borrowed(name), Some(SectionAnnotation::Template(template.to_string()))),
+        name => (name.unwrap_or_default(), None)
+    };
+
+    check_name(input, &name, context)?;
+
+    let (rest, annotation) = match template {
+        Some(template) => (rest, Some(template)),
+        None => opt(preceded(char(':'), parse_identifier).map(|tag| SectionAnnotation::Tag(tag.to_string()))).parse(rest)?
+    };
+
+    let (remaining, entries) = delimited(char('{'), |input| context.nested(input, |input| parse_block(input, context)), char('}')).parse(rest)?;
+    let span = Some(context.span(input, remaining));
+
+    Ok((remaining, BorrowedEntry::Section(BorrowedSection { name, annotation, entries, span })))
+}
+
+/// Parses an `include "file.blk"` directive.
+fn parse_include(input: &str) -> IResult<&str, BorrowedEntry<'_>> {
+    preceded((tag("include"), space1), parse_string)
+        .map(BorrowedEntry::Include)
+        .parse(input)
+}
+
+/// Parses a single entry in a BLK configuration, which can be either a section, a property or an include.
+///
+/// The closing `}` of a section ends it, so the next entry may follow on the same line without a separator.
+fn parse_entry<'a>(input: &'a str, context: &Context) -> IResult<&'a str, BorrowedEntry<'a>> {
+    let (entry_input, _) = parse_space(input)?;
+    context.check_comments(input, entry_input)?;
+
+    let input = entry_input;
+    let (separator, entry) = alt((parse_include, |input| parse_section(input, context), |input| parse_property(input, context))).parse(input)?;
+    let (remaining, _) = match entry {
+        BorrowedEntry::Section(_) => opt(alt((parse_separator, parse_final_separator))).map(|_| ()).parse(separator)?,
+        _ => alt((parse_separator, parse_final_separator)).parse(separator)?
+    };
+    context.check_comments(separator, remaining)?;
+
+    if matches!(entry, BorrowedEntry::Include(_)) && !context.options.dialect.includes {
+        return Err(context.fail(input, "includes are not allowed by the strict dialect".to_string()));
+    }
+
+    let max_entries = context.options.limits.max_entries;
+    context.entries.set(context.entries.get() + 1);
+
+    if context.entries.get() > max_entries {
+        return Err(context.fail(input, format!("the file has more than {} entries", max_entries)));
+    }
+
+    Ok((remaining, entry))
+}
+
+/// Parses a block of entries in a BLK configuration.
+fn parse_block<'a>(input: &'a str, context: &Context) -> IResult<&'a str, Vec<BorrowedEntry<'a>>> {
+    terminated(many0(|input| parse_entry(input, context)), parse_space).parse(input)
+}
+
+/// Parses a BLK configuration from the input string.
+pub fn parse_config(input: &str) -> IResult<&str, BlkConfig> {
+    parse_config_with(input, &ParseOptions::default(), &mut Vec::new())
+}
+
+/// Like [`parse_config`], parsing as the options demand and adding warnings about the input to `warnings`.
+pub fn parse_config_with<'a>(input: &'a str, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) -> IResult<&'a str, BlkConfig> {
+    let context = Context::new(options, input);
+    let text = input.strip_prefix(BOM).unwrap_or(input);
+    let result = parse_block(text, &context).map(|(remaining, entries)| {
+        let mut config = BorrowedConfig { entries, bom: text.len() < input.len(), crlf: uses_crlf(text) }.into_owned();
+        let range = input.len() - text.len()..input.len() - remaining.len();
+
+        config.comments = comment_texts(attach_top_level_comments(&mut config.block.entries, input, range, 0, Vec::new()));
+        (remaining, config)
+    });
+
+    warnings.extend(context.warnings.into_inner());
+    result
+}
+
+/// Finds out why the entry at the start of `input` does not parse, returning the input left
+/// at the problem and what was expected there.
+fn diagnose<'a>(input: &'a str, context: &Context) -> (&'a str, String) {
+    let after_entry = |input: &'a str| {
+        let (input, _) = parse_inline_space(input).unwrap_or((input, ()));
+        (input, "expected a newline or ';' after the entry".to_string())
+    };
+
+    let input = parse_space(input).map_or(input, |(input, _)| input);
+
+    if input.starts_with('}') {
+        return (input, "unexpected '}' without a matching '{'".to_string());
+    }
+
+    if let Ok((rest, _)) = parse_include(input) {
+        return after_entry(rest);
+    }
+
+    if let Some(rest) = input.strip_prefix("include") && rest.starts_with([' ', '\t']) {
+        return (rest.trim_start_matches([' ', '\t']), "expected a quoted file name after `include`".to_string());
+    }
+
+    let (rest, name) = match parse_name(input) {
+        Ok(parsed) => parsed,
+        Err(_) if input.starts_with('{') => (input, Cow::Borrowed("")),
+        Err(_) => return (input, "expected a key or section name".to_string())
+    };
+
+    // the annotation of a section
+    let rest = match preceded(char(':'), parse_identifier).parse(rest) {
+        Ok((after, _)) if after.starts_with('{') => after,
+        _ => rest
+    };
+
+    if let Some(block) = rest.strip_prefix('{') {
+        return match parse_block(block, context) {
+            Ok((inner, _)) if inner.starts_with('}') => after_entry(&inner[1..]),
+            Ok(("", _)) => ("", format!("expected '}}' to close section `{}`", name)),
+            Ok((inner, _)) => diagnose(inner, context),
+            Err(_) => (block, "expected entries after '{'".to_string())
+        };
+    }
+
+    let skip_space = |input: &'a str| input.trim_start_matches([' ', '\t']);
+
+    let Some(type_input) = skip_space(rest).strip_prefix(':').map(skip_space) else {
+        return (rest, format!("expected ':' or '{{' after `{}`", name));
+    };
+
+    let Ok((rest, ty)) = parse_blk_type(type_input) else {
+        return (type_input, "expected a type after ':'".to_string());
+    };
+
+    let Some(value_input) = skip_space(rest).strip_prefix('=').map(skip_space) else {
+        return (rest, format!("expected '=' after the type `{}`", ty));
+    };
+
+    match parse_property(input, context) {
+        Ok((rest, _)) => after_entry(rest),
+        Err(_) if ty == BlkType::Integer && nom::character::complete::i64::<_, ()>(value_input).is_ok() =>
+            (value_input, format!("`{}` does not fit in 32 bits", name)),
+        Err(_) => (value_input, format!("expected {} value after '='", ty))
+    }
+}
+
+/// Parses the whole content of a BLK file, reporting where and why it does not parse.
+pub fn parse_file(input: &str, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) -> Result<BlkConfig, ParseError> {
+    let mut config = parse_borrowed(input, options, warnings)?.into_owned();
+    let start = if input.starts_with(BOM) { BOM.len_utf8() } else { 0 };
+
+    config.comments = comment_texts(attach_top_level_comments(&mut config.block.entries, input, start..input.len(), 0, Vec::new()));
+    warn_duplicates(&config, options, warnings);
+    Ok(config)
+}
+
+/// Like [`parse_file`], returning the warnings about the input along with the config.
+pub fn parse_config_with_diagnostics(input: &str, options: &ParseOptions) -> Result<(BlkConfig, Vec<ParseWarning>), ParseError> {
+    let mut warnings = Vec::new();
+    parse_file(input, options, &mut warnings).map(|config| (config, warnings))
+}
+
+/// Like [`parse_file`], borrowing names and text values from the input instead of copying
+/// them, which saves most allocations for read-only uses of large files.
+pub fn parse_borrowed<'a>(input: &'a str, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) -> Result<BorrowedConfig<'a>, ParseError> {
+    let context = Context::new(options, input);
+    let result = parse_all(input, &context);
+
+    warnings.extend(context.warnings.into_inner());
+    result.map(|entries| BorrowedConfig { entries, bom: input.starts_with(BOM), crlf: uses_crlf(input) })
+}
+
+/// Returns whether the lines of a text end with `\r\n`, judging by its first line, or `None`
+/// if it has only one.
+fn line_ending(text: &str) -> Option<bool> {
+    text.find('\n').map(|newline| text[..newline].ends_with('\r'))
+}
+
+/// Returns whether the lines of a text end with `\r\n`.
+fn uses_crlf(text: &str) -> bool {
+    line_ending(text).unwrap_or(false)
+}
+
+/// Parses all of `input`, the text of `context`, reporting where and why it does not parse.
+/// A byte order mark at the start of the input is skipped.
+fn parse_all<'a>(input: &'a str, context: &Context) -> Result<Vec<BorrowedEntry<'a>>, ParseError> {
+    match (parse_block(input.strip_prefix(BOM).unwrap_or(input), context), context.failure.take()) {
+        (Ok(("", entries)), _) => Ok(entries),
+        (Ok((remaining, _)), _) => {
+            let (problem, message) = diagnose(remaining, &Context::new(context.options, input));
+            Err(ParseError::at(input, problem, message))
+        },
+        (Err(_), Some((offset, message))) => Err(ParseError::at(input, &input[offset..], message)),
+        (Err(nom::Err::Error(err) | nom::Err::Failure(err)), None) => Err(ParseError::at(input, err.input, "unexpected input")),
+        (Err(nom::Err::Incomplete(_)), None) => Err(ParseError::at(input, "", "unexpected end of input"))
+    }
+}
+
+/// Turns an error in a part of a file starting at 0-based `line` and `column` into an I/O error located in the file.
+fn shift_error(mut err: ParseError, line: usize, column: usize) -> io::Error {
+    err.column += if err.line == 1 { column } else { 0 };
+    err.line += line;
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Number of bytes [`parse_stream`] reads at least at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parses a BLK file from a reader without holding all of its text in memory.
+///
+/// The input is read in chunks and every complete top-level entry is parsed and dropped
+/// from the buffer right away, so besides the config itself only about the largest
+/// top-level entry is held in memory. Parse errors are returned as [`io::ErrorKind::InvalidData`]
+/// errors wrapping a [`ParseError`].
+pub fn parse_stream(reader: impl Read, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) -> io::Result<BlkConfig> {
+    parse_chunked(reader, CHUNK_SIZE, options, warnings)
+}
+
+/// Like [`parse_stream`], reading at least `chunk_size` bytes at once.
+fn parse_chunked(mut reader: impl Read, chunk_size: usize, options: &ParseOptions, warnings: &mut Vec<ParseWarning>) -> io::Result<BlkConfig> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut entries = Vec::new();
+    // entries counted against the limit, including the ones in sections
+    let mut counted = 0;
+    let mut buffer = Vec::new();
+    // byte offset, line and column the buffer starts at, all counted from 0
+    let (mut offset, mut line, mut column) = (0, 0, 0);
+    let mut bom = false;
+    let mut crlf = None;
+    let mut lead_newlines = 0;
+    // comments after the last entry parsed, which lead the next one
+    let mut pending = Vec::new();
+
+    loop {
+        // reading at least as much as is buffered keeps the re-parsing of a large entry linear
+        let wanted = buffer.len().max(chunk_size);
+        let eof = (&mut reader).take(wanted as u64).read_to_end(&mut buffer)? < wanted;
+
+        if offset == 0 && buffer.starts_with(BOM.encode_utf8(&mut [0; 3]).as_bytes()) {
+            buffer.drain(..BOM.len_utf8());
+            offset = BOM.len_utf8();
+            bom = true;
+        }
+
+        let text = match std::str::from_utf8(&buffer) {
+            Ok(text) => text,
+            // a character split between chunks is completed by the next one
+            Err(err) if err.error_len().is_none() && !eof => std::str::from_utf8(&buffer[..err.valid_up_to()]).unwrap(),
+            Err(err) => return Err(invalid(format!("not valid UTF-8 after byte {}", err.valid_up_to())))
+        };
+
+        crlf = crlf.or_else(|| line_ending(text));
+
+        let mut context = Context::new(options, text);
+        context.lead_newlines = lead_newlines;
+        context.entries.set(counted);
+        let first = entries.len();
+        let take_warnings = |context: &Context| context.warnings.take().into_iter().map(|mut warning| {
+            shift_span(&mut warning.span, offset, line, column);
+            warning
+        });
+
+        if eof {
+            let result = parse_all(text, &context);
+            warnings.extend(take_warnings(&context));
+
+            entries.extend(result.map_err(|err| shift_error(err, line, column))?.into_iter().map(BorrowedEntry::into_owned));
+            let comments = comment_texts(attach_top_level_comments(&mut entries[first..], text, 0..text.len(), lead_newlines, pending));
+            shift_spans(&mut entries[first..], offset, line, column);
+
+            let config = BlkConfig { block: BlkBlock { entries }, bom, crlf: crlf.unwrap_or(false), encoding: None, comments };
+            warn_duplicates(&config, options, warnings);
+
+            return Ok(config);
+        }
+
+        let mut input = text;
+
+        // an entry running up to the end of the buffer may continue in the next chunk
+        while let Ok((rest, entry)) = parse_entry(input, &context) && !rest.is_empty() {
+            let mut entry = entry.into_owned();
+            let range = text.len() - input.len()..text.len() - rest.len();
+
+            let lead_newlines = if range.start == 0 { lead_newlines } else { 0 };
+
+            pending = attach_top_level_comments(std::slice::from_mut(&mut entry), text, range, lead_newlines, std::mem::take(&mut pending));
+            entries.push(entry);
+            warnings.extend(take_warnings(&context));
+            counted = context.entries.get();
+            input = rest;
+        }
+
+        // warnings about an entry left for the next chunk are reported again when it is parsed
+        shift_spans(&mut entries[first..], offset, line, column);
+
+        if let Some((problem, message)) = context.failure.take() {
+            return Err(shift_error(ParseError::at(text, &text[problem..], message), line, column));
+        }
+
+        let consumed = &text[..text.len() - input.len()];
+        let entries_text = consumed.trim_end_matches(char::is_whitespace);
+        lead_newlines = consumed[entries_text.len()..].matches('\n').count() + if entries_text.is_empty() { lead_newlines } else { 0 };
+
+        match consumed.rfind('\n') {
+            Some(newline) => {
+                line += consumed.matches('\n').count();
+                column = consumed[newline + 1..].chars().count();
+            },
+            None => column += consumed.chars().count()
+        }
+
+        let consumed = consumed.len();
+        offset += consumed;
+        buffer.drain(..consumed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream() {
+        let input = "// settings\nname:t=\"Пилот\"; sky:i=2\ngraphics{\n  /* video */ clouds:r=0.5\n}\nlast:b=yes\n";
+
+        for chunk_size in [1, 3, 7, 1024] {
+            let config = parse_chunked(input.as_bytes(), chunk_size, &ParseOptions::default(), &mut Vec::new()).unwrap();
+            assert_eq!(config, parse_config(input).unwrap().1);
+        }
+
+        let err = parse_chunked("a:i=1\nb:i=2; c:i=x\n".as_bytes(), 4, &ParseOptions::default(), &mut Vec::new()).unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<ParseError>().unwrap();
+
+        assert_eq!((err.line, err.column), (2, 12));
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let input = "a:i=1; b:t=\"x\"\ngraphics{\n  sky:i=2\n}\n";
+        let span = |config: &BlkConfig, path: &[&str]| match config.find(path) {
+            Some(BlkEntry::Property(property)) => property.span.clone(),
+            Some(BlkEntry::Section(section)) => section.span.clone(),
+            _ => None
+        };
+
+        for config in [parse_config(input).unwrap().1, parse_chunked(input.as_bytes(), 5, &ParseOptions::default(), &mut Vec::new()).unwrap()] {
+            assert_eq!(span(&config, &["b"]), Some(Span { line: 1, column: 8, byte_range: 7..14, blank_before: false, semicolon: false }));
+            assert_eq!(span(&config, &["graphics"]), Some(Span { line: 2, column: 1, byte_range: 15..36, blank_before: false, semicolon: false }));
+            assert_eq!(span(&config, &["graphics", "sky"]), Some(Span { line: 3, column: 3, byte_range: 27..34, blank_before: false, semicolon: false }));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &str| parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap_err();
+
+        assert_eq!(error("a:i=1\ngraphics{\n  sky:i=high\n}\n"), ParseError {
+            message: "expected integer value after '='".to_string(),
+            line: 3,
+            column: 9,
+            text: "  sky:i=high".to_string()
+        });
+        assert_eq!(error("a:i=1 b:i=2\n").message, "expected a newline or ';' after the entry");
+        assert_eq!(error("a:q=1\n").message, "expected a type after ':'");
+        assert_eq!(error("a i=1\n").message, "expected ':' or '{' after `a`");
+        assert_eq!(error("graphics{\n  sky:i=1\n").message, "expected '}' to close section `graphics`");
+        assert_eq!(error("a:i=1\n}\n").to_string(), "unexpected '}' without a matching '{' at line 2, column 1\n    }\n    ^");
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = ParseLimits { max_depth: 2, max_entries: 4, max_value_length: 3 };
+        let options = ParseOptions { limits, ..Default::default() };
+        let error = |input: &str| parse_file(input, &options, &mut Vec::new()).unwrap_err();
+
+        assert!(parse_file("a{ b{ c:t=\"abc\"; }\n}\n", &options, &mut Vec::new()).is_ok());
+        assert_eq!(error("a{ b{ c{}\n}\n}\n").message, "sections are nested deeper than 2 levels");
+        assert_eq!(error("a:i=1\nb:i=2\nc:i=3\nd:i=4\ne:i=5\n").line, 5);
+        assert_eq!(error("a:t=\"abcd\"\n").message, "`a` is longer than 3 bytes");
+
+        let deep = format!("{}{}", "a{".repeat(ParseLimits::default().max_depth), "}\n".repeat(ParseLimits::default().max_depth));
+        assert!(parse_file(&deep, &ParseOptions::default(), &mut Vec::new()).is_ok());
+        assert_eq!(parse_file(&"a{".repeat(100_000), &ParseOptions::default(), &mut Vec::new()).unwrap_err().message, "sections are nested deeper than 128 levels");
+
+        let err = parse_chunked("a:i=1\nb:i=2\nc:i=3\nd:i=4\ne:i=5\nf:i=6\n".as_bytes(), 8, &options, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "the file has more than 4 entries at line 5, column 1\n    e:i=5\n    ^");
+    }
 
-/// Parses an identifier from the input string.
-fn parse_identifier(input: &str) -> IResult<&str, &str> {
-    recognize(many1(alt((alpha1, digit1, tag("_"))))).parse(input)
-}
+    #[test]
+    fn test_parse_real_policy() {
+        let input = "sight{ offset:p2=+0.5, -1.25; scale:r=inf\n}\n";
+        let parse = |reals: RealPolicy, warnings: &mut Vec<ParseWarning>| parse_file(input, &ParseOptions { reals, ..Default::default() }, warnings);
 
-/// Parses a line separator, which can be either a newline or a semicolon.
-fn parse_separator(input: &str) -> IResult<&str, ()> {
-    many1(alt((newline_multiplatform, char(';').map(|_| ())))).map(|_| ()).parse(input)
-}
+        let error = parse(RealPolicy::Reject, &mut Vec::new()).unwrap_err();
+        assert_eq!((error.message.as_str(), error.line, error.column), ("`scale` has the value inf, which is not a finite number", 1, 39));
 
-/// Parses a boolean value from the input string.
-fn parse_boolean(input: &str) -> IResult<&str, bool> {
-    alt((
-        alt((tag("true"), tag("yes"))).map(|_| true),
-        alt((tag("false"), tag("no"))).map(|_| false)
-    )).parse(input)
-}
+        let config = parse(RealPolicy::Accept, &mut Vec::new()).unwrap();
+        assert!(matches!(config.find(&["sight", "scale"]), Some(BlkEntry::Property(BlkProperty { value: BlkPropertyValue::Real(real), .. })) if *real == f32::INFINITY));
 
-/// Parses an integer value from the input string.
-fn parse_integer(input: &str) -> IResult<&str, i32> {
-    nom::character::complete::i32(input)
-}
+        let mut warnings = Vec::new();
+        let config = parse(RealPolicy::Replace, &mut warnings).unwrap();
+        assert_eq!(config, parse_config("sight{ offset:p2=0.5, -1.25; scale:r=0\n}\n").unwrap().1);
+        assert_eq!(warnings.iter().map(ToString::to_string).collect::<Vec<_>>(), ["`scale` has the value inf, replaced with 0"]);
+        assert!(parse_file("m:m=[1, 0, 0] [0, NaN, 0] [0, 0, 1] [0, 0, 0]\n", &ParseOptions::default(), &mut Vec::new()).is_err());
+    }
 
-/// Parses a real (floating-point) value from the input string.
-fn parse_real(input: &str) -> IResult<&str, f32> {
-    nom::number::complete::float(input)
-}
+    #[test]
+    fn test_parse_strict_dialect() {
+        let options = ParseOptions { dialect: Dialect::STRICT, ..Default::default() };
+        let error = |input: &str| parse_file(input, &options, &mut Vec::new()).unwrap_err();
+
+        assert!(parse_file("a:i=1\n'b.c'{ d:c=1, 2, 3, 4; }\n", &options, &mut Vec::new()).is_ok());
+        assert_eq!(error("a:i=1 // one\n"), ParseError {
+            message: "comments are not allowed by the strict dialect".to_string(),
+            line: 1,
+            column: 7,
+            text: "a:i=1 // one".to_string()
+        });
+        assert_eq!(error("/* a */\na:i=1\n").column, 1);
+        assert_eq!(error("a:p2=1, /* y */ 2\n").column, 9);
+        assert_eq!(error("include \"a.blk\"\n").message, "includes are not allowed by the strict dialect");
+        assert_eq!(error("a:c=1, 0xFF, 3, 4\n").column, 8);
+        assert_eq!(error("a.b:i=1\n").message, "`a.b` must be quoted in the strict dialect");
+        assert_eq!(error("x{ a-b{}\n}\n").column, 4);
+        assert_eq!(error("a:t=\"\"\"raw\"\"\"\n").message, "raw strings are not allowed by the strict dialect");
+        assert!(parse_file("a:i=1 // one\n", &ParseOptions::default(), &mut Vec::new()).is_ok());
+    }
 
-/// Parses a vector delimiter (comma followed by optional whitespace) from the input string.
-fn parse_vector_delimiter(input: &str) -> IResult<&str, ()> {
-    (char(','), multispace0).map(|_| ()).parse(input)
-}
+    #[test]
+    fn test_parse_bom() {
+        let input = "\u{FEFF}a:i=1\nb:i=x\n";
+        let error = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap_err();
+
+        assert_eq!((error.line, error.column), (2, 5));
+        assert!(parse_file("a:i=1\n", &ParseOptions::default(), &mut Vec::new()).is_ok_and(|config| !config.bom));
+
+        let config = parse_file("\u{FEFF}a:i=1\n", &ParseOptions::default(), &mut Vec::new()).unwrap();
+        assert!(config.bom);
+        assert_eq!(config, parse_config("a:i=1\n").unwrap().1);
+        assert_eq!(parse_chunked("\u{FEFF}a:i=1\n".as_bytes(), 4, &ParseOptions::default(), &mut Vec::new()).unwrap(), config);
+
+        let stream = parse_stream("\u{FEFF}a:i=1\n".as_bytes(), &ParseOptions::default(), &mut Vec::new()).unwrap();
+        assert!(stream.bom);
+        assert_eq!(stream.find(&["a"]).and_then(|entry| match entry {
+            BlkEntry::Property(property) => property.span.clone(),
+            _ => None
+        }), Some(Span { line: 1, column: 1, byte_range: 3..8, blank_before: false, semicolon: false }));
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { keep_bom: true, ..Default::default() }).unwrap();
+        assert_eq!(output, "\u{FEFF}a:i=1\n".as_bytes());
+
+        output.clear();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(output, b"a:i=1\n");
+    }
 
-/// Parses a string value enclosed in double quotes from the input string.
-fn parse_string(input: &str) -> IResult<&str, &str> {
-    delimited(char('"'), take_until("\""), char('"')).parse(input)
-}
+    #[test]
+    fn test_parse_spaces_around_type() {
+        let (remaining, config) = parse_config("a : t = \"x\"\nb:i =  1\nc\t:p2=\t1, 2\nd{ e :b= yes; }\n").unwrap();
 
-/// Parses a BLK property from the input string.
-fn parse_property(input: &str) -> IResult<&str, BlkEntry> {
-    let (remaining, (identifier, ty)) = (parse_identifier, delimited(char(':'), parse_blk_type, char('='))).parse(input)?;
-    let (remaining, value) = parse_property_value(ty).parse(remaining)?;
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("a:t=\"x\"\nb:i=1\nc:p2=1, 2\nd{ e:b=yes; }\n").unwrap().1);
+        assert!(parse_config("a :\nt=1\n").is_ok_and(|(remaining, _)| !remaining.is_empty()));
 
-    Ok((remaining, BlkEntry::Property(BlkProperty { key: identifier.to_string(), value })))
-}
+        let error = parse_file("a : q = 1\n", &ParseOptions::default(), &mut Vec::new()).unwrap_err();
+        assert_eq!((error.message.as_str(), error.column), ("expected a type after ':'", 5));
+        assert_eq!(parse_file("a:i = x\n", &ParseOptions::default(), &mut Vec::new()).unwrap_err().column, 7);
+    }
 
-/// Parses a BLK section from the input string.
-fn parse_section(input: &str) -> IResult<&str, BlkEntry> {
-    (parse_identifier, delimited(char('{'), parse_block, char('}')))
-        .map(|(name, block)| BlkEntry::Section(BlkSection { name: name.to_string(), entries: block.entries }))
-        .parse(input)
-}
+    #[test]
+    fn test_parse_without_final_separator() {
+        let expected = parse_config("a:i=1\nx{\nb:t=\"c\"\ny{\n}\n}\n").unwrap().1;
+
+        assert_eq!(parse_config("a:i=1\nx{ b:t=\"c\"; y{} }").unwrap(), ("", expected.clone()));
+        assert_eq!(parse_config("a:i=1;x{b:t=\"c\"\ny{}} // end").unwrap(), ("", expected));
+        assert!(parse_file("a:i=1 b:i=2", &ParseOptions::default(), &mut Vec::new()).is_err());
+        assert_eq!(parse_chunked("a:i=1\nb:i=23".as_bytes(), 8, &ParseOptions::default(), &mut Vec::new()).unwrap(),
+            parse_config("a:i=1\nb:i=23\n").unwrap().1);
+    }
 
-/// Parses a single entry in a BLK configuration, which can be either a section or a property.
-fn parse_entry(input: &str) -> IResult<&str, BlkEntry> {
-    delimited(multispace0, alt((parse_section, parse_property)), parse_separator).parse(input)
-}
+    #[test]
+    fn test_parse_warning_spans() {
+        let input = "a:i=1\nx{\n  big:i=4294967295; a:i=2; a:i=3\n}\n";
+        let options = ParseOptions { overflow: IntegerOverflow::Saturate, warn_duplicates: true, ..Default::default() };
+        let (_, warnings) = parse_config_with_diagnostics(input, &options).unwrap();
+        let locations = |warnings: &[ParseWarning]| warnings.iter()
+            .map(|warning| (warning.kind, warning.span.as_ref().map(|span| (span.line, span.column, span.byte_range.clone()))))
+            .collect::<Vec<_>>();
+
+        assert_eq!(locations(&warnings), [
+            (WarningKind::SuspiciousValue, Some((3, 9, 17..27))),
+            (WarningKind::DuplicateKey, Some((3, 28, 36..41)))
+        ]);
+
+        let mut streamed = Vec::new();
+        parse_chunked(input.as_bytes(), 4, &options, &mut streamed).unwrap();
+        assert_eq!(locations(&streamed), locations(&warnings));
+    }
 
-/// Parses a block of entries in a BLK configuration.
-fn parse_block(input: &str) -> IResult<&str, BlkBlock> {
-    terminated(many0(parse_entry), multispace0).map(|entries| BlkBlock { entries }).parse(input)
-}
+    #[test]
+    fn test_warn_duplicates() {
+        let input = "a:i=1\nx{ b:i=1; b:i=2; y{}; y{} }\nz{ b:i=3 }\na:i=2\n";
+        let options = ParseOptions { warn_duplicates: true, ..Default::default() };
+        let mut warnings = Vec::new();
+
+        let config = parse_file(input, &options, &mut warnings).unwrap();
+        let duplicates = find_duplicate_keys(&config);
+
+        assert_eq!(duplicates.iter().map(|duplicate| duplicate.path.join("/")).collect::<Vec<_>>(), ["x/b", "a"]);
+        assert_eq!(warnings.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+            "`x/b` is defined again at line 2, column 11, first at line 2, column 4",
+            "`a` is defined again at line 4, column 1, first at line 1, column 1"
+        ]);
+
+        let mut warnings = Vec::new();
+        parse_stream(input.as_bytes(), &options, &mut warnings).unwrap();
+        assert_eq!(warnings.len(), 2);
+
+        let mut warnings = Vec::new();
+        parse_file(input, &ParseOptions::default(), &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+    }
 
-/// Parses a BLK configuration from the input string.
-pub fn parse_config(input: &str) -> IResult<&str, BlkConfig> {
-    parse_block.map(|block| BlkConfig { block }).parse(input)
-}
+    #[test]
+    fn test_parse_missing_values() {
+        let (remaining, config) = parse_config("a:t=\nb:i= // none\nc{ d:p2=}\ne:t=\"\"\n").unwrap();
+        let value = |path: &[&str]| match config.find(path) {
+            Some(BlkEntry::Property(property)) => property.value.clone(),
+            _ => panic!("no property {:?}", path)
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(remaining, "");
+        assert_eq!(value(&["a"]), BlkPropertyValue::None(BlkType::Text));
+        assert_eq!(value(&["b"]), BlkPropertyValue::None(BlkType::Integer));
+        assert_eq!(value(&["c", "d"]), BlkPropertyValue::None(BlkType::Point2));
+        assert_eq!(value(&["e"]), BlkPropertyValue::Text(String::new()));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a:t=\nb:i= // none\nc{\n    d:p2=\n}\ne:t=\"\"\n");
+    }
 
     #[test]
     fn test_parse_empty_config() {
@@ -187,20 +1528,39 @@ mod tests {
 
         assert_eq!(remaining, "");
         assert_eq!(config, BlkConfig {
+            bom: false,
+            crlf: false,
+            encoding: None,
+            comments: Vec::new(),
             block: BlkBlock {
                 entries: vec![
                     BlkEntry::Property(BlkProperty {
                         key: "meow".to_string(),
-                        value: BlkPropertyValue::Text("uwu".to_string())
+                        value: BlkPropertyValue::Text("uwu".to_string()),
+                        quote: QuoteStyle::Double,
+                        hex: false,
+                        hex_color: false,
+                        raw: None,
+                        span: None,
+                        comments: Comments::default()
                     }),
                     BlkEntry::Section(BlkSection {
                         name: "uwu".to_string(),
+                        annotation: None,
                         entries: vec![
                             BlkEntry::Property(BlkProperty {
                                 key: "owo".to_string(),
-                                value: BlkPropertyValue::Integer(32)
+                                value: BlkPropertyValue::Integer(32),
+                                quote: QuoteStyle::Double,
+                                hex: false,
+                                hex_color: false,
+                                raw: None,
+                                span: None,
+                                comments: Comments::default()
                             })
-                        ]
+                        ],
+                        span: None,
+                        comments: Comments::default()
                     })
                 ]
             }
@@ -218,20 +1578,39 @@ mod tests {
 
         assert_eq!(remaining, "");
         assert_eq!(config, BlkConfig {
+            bom: false,
+            crlf: false,
+            encoding: None,
+            comments: Vec::new(),
             block: BlkBlock {
                 entries: vec![
                     BlkEntry::Property(BlkProperty {
                         key: "meow".to_string(),
-                        value: BlkPropertyValue::Text("uwu".to_string())
+                        value: BlkPropertyValue::Text("uwu".to_string()),
+                        quote: QuoteStyle::Double,
+                        hex: false,
+                        hex_color: false,
+                        raw: None,
+                        span: None,
+                        comments: Comments::default()
                     }),
                     BlkEntry::Section(BlkSection {
                         name: "uwu".to_string(),
+                        annotation: None,
                         entries: vec![
                             BlkEntry::Property(BlkProperty {
                                 key: "owo".to_string(),
-                                value: BlkPropertyValue::Integer(32)
+                                value: BlkPropertyValue::Integer(32),
+                                quote: QuoteStyle::Double,
+                                hex: false,
+                                hex_color: false,
+                                raw: None,
+                                span: None,
+                                comments: Comments::default()
                             })
-                        ]
+                        ],
+                        span: None,
+                        comments: Comments::default()
                     })
                 ]
             }
@@ -264,6 +1643,650 @@ mod tests {
         assert_eq!(config.block.entries.len(), 1);
     }
 
+    #[test]
+    fn test_parse_line_comments() {
+        let input = "// exported settings\ngraphics{ // video\n  sky:i=2 // high\n  // url:t=\"a\"\n  url:t=\"http://localhost\"; // trailing\n}\n// end";
+        let (remaining, config) = parse_config(input).unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("graphics{sky:i=2;url:t=\"http://localhost\";};").unwrap().1);
+    }
+
+    #[test]
+    fn test_parse_block_comments() {
+        let input = "/* sight\n   settings */\ndrawLines{ /* inline */ line:p2=1, /* x */ 2\n  /*\n  width:i=3\n  */\n  width:i=2 /* px */;\n}\n";
+        let (remaining, config) = parse_config(input).unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("drawLines{line:p2=1,2;width:i=2;};").unwrap().1);
+    }
+
+    #[test]
+    fn test_parse_escaped_strings() {
+        let (remaining, config) = parse_config("path:t=\"C:\\\\Games\\\\\\\"WT\\\"\\n\\t\";empty:t=\"\";\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["path"]), Some(&BlkEntry::Property(BlkProperty {
+            key: "path".to_string(),
+            value: BlkPropertyValue::Text("C:\\Games\\\"WT\"\n\t".to_string()),
+            quote: QuoteStyle::Double,
+            hex: false,
+            hex_color: false,
+            raw: None,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(parse_config(std::str::from_utf8(&output).unwrap()).unwrap().1, config);
+    }
+
+    #[test]
+    fn test_parse_unknown_escapes() {
+        let (remaining, config) = parse_config("path:t=\"C:\\Games\\WT\";name:t='a\\qb\\\\c';\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert!(matches!(config.find(&["path"]), Some(BlkEntry::Property(property)) if property.value == BlkPropertyValue::Text("C:\\Games\\WT".to_string())));
+        assert!(matches!(config.find(&["name"]), Some(BlkEntry::Property(property)) if property.value == BlkPropertyValue::Text("a\\qb\\c".to_string())));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "path:t=\"C:\\\\Games\\\\WT\"\nname:t='a\\\\qb\\\\c'\n");
+        assert_eq!(parse_config(std::str::from_utf8(&output).unwrap()).unwrap().1, config);
+    }
+
+    #[test]
+    fn test_stringify_escapes_text() {
+        let text = "quote \" apostrophe ' backslash \\ tab\t\r\n\"\"\" end\\";
+        let (_, mut config) = parse_config("text:t=\"\"\n").unwrap();
+
+        if let BlkEntry::Property(property) = &mut config.block.entries[0] {
+            property.value = BlkPropertyValue::Text(text.to_string());
+        }
+
+        for quote in [QuoteStyle::Double, QuoteStyle::Single, QuoteStyle::Triple] {
+            let mut output = Vec::new();
+            stringify_config_with(&config, &mut output, &SerializeOptions { quote: Some(quote), ..Default::default() }).unwrap();
+            assert_eq!(parse_config(std::str::from_utf8(&output).unwrap()).unwrap(), ("", config.clone()));
+        }
+    }
+
+    #[test]
+    fn test_parse_borrowed() {
+        let input = "name:t=\"plain\";escaped:t=\"a\\\"b\";'quoted key'{ x:i=1; }\n";
+        let config = parse_borrowed(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+
+        let [BorrowedEntry::Property(plain), BorrowedEntry::Property(escaped), BorrowedEntry::Section(section)] = &config.entries[..] else {
+            panic!("unexpected entries: {:?}", config.entries);
+        };
+
+        assert!(matches!(&plain.key, Cow::Borrowed("name")));
+        assert!(matches!(&plain.value, BorrowedValue::Text(Cow::Borrowed("plain"))));
+        assert!(matches!(&escaped.value, BorrowedValue::Text(Cow::Owned(text)) if text == "a\"b"));
+        assert!(matches!(&section.name, Cow::Borrowed("quoted key")));
+        assert_eq!(config.into_owned(), parse_config(input).unwrap().1);
+    }
+
+    #[test]
+    fn test_parse_single_quoted_strings() {
+        let (remaining, config) = parse_config("name:t='say \"hi\" isn\\'t';other:t=\"x\";\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["name"]), Some(&BlkEntry::Property(BlkProperty {
+            key: "name".to_string(),
+            value: BlkPropertyValue::Text("say \"hi\" isn't".to_string()),
+            quote: QuoteStyle::Single,
+            hex: false,
+            hex_color: false,
+            raw: None,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "name:t='say \"hi\" isn\\'t'\nother:t=\"x\"\n");
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { quote: Some(QuoteStyle::Double), ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "name:t=\"say \\\"hi\\\" isn't\"\nother:t=\"x\"\n");
+    }
+
+    #[test]
+    fn test_parse_multiline_strings() {
+        let input = "description:t=\"\"\"First line\n  \"quoted\" C:\\path\n\"\"\"\nescaped:t=\"one\\r\\ntwo\"\nplain:t=\"a\nb\"\n";
+        let (remaining, config) = parse_config(input).unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["description"]), Some(&BlkEntry::Property(BlkProperty {
+            key: "description".to_string(),
+            value: BlkPropertyValue::Text("First line\n  \"quoted\" C:\\path\n".to_string()),
+            quote: QuoteStyle::Triple,
+            hex: false,
+            hex_color: false,
+            raw: None,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), input.replace("a\nb", "a\\nb"));
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { quote: Some(QuoteStyle::Triple), ..Default::default() }).unwrap();
+        let (_, reparsed) = parse_config(std::str::from_utf8(&output).unwrap()).unwrap();
+        assert_eq!(reparsed, config);
+        assert!(!is_raw_string("ends with \"") && !is_raw_string("has \"\"\" inside"));
+    }
+
+    #[test]
+    fn test_parse_identifier_characters() {
+        let input = "ID_FIRE_2.5x:t=\"a\"\nsome-key:i=1\nhud@main:b=no\nweapon{ ammo/belt:i=2 // note\n}\nend:b=yes/*x*/\n";
+        let (remaining, config) = parse_config(input).unwrap();
+
+        assert_eq!(remaining, "");
+        assert!(config.find(&["ID_FIRE_2.5x"]).is_some());
+        assert!(config.find(&["some-key"]).is_some());
+        assert!(config.find(&["hud@main"]).is_some());
+        assert!(config.find(&["weapon", "ammo/belt"]).is_some());
+        assert!(config.find(&["end"]).is_some());
+        assert!(is_identifier("weapon@primary") && !is_identifier("a//b") && !is_identifier("my key"));
+    }
+
+    #[test]
+    fn test_parse_quoted_names() {
+        let (remaining, config) = parse_config("\"my key\":t=\"x\"\n'odd name'{ \"ключ\":i=1; plain:i=2; }\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert!(config.find(&["my key"]).is_some());
+        assert!(config.find(&["odd name", "ключ"]).is_some());
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "\"my key\":t=\"x\"\n\"odd name\"{\n    \"ключ\":i=1\n    plain:i=2\n}\n");
+    }
+
+    #[test]
+    fn test_parse_long() {
+        let (remaining, config) = parse_config("lastLogin:i64=1718000000123;small:i=5;\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["lastLogin"]), Some(&BlkEntry::Property(BlkProperty {
+            key: "lastLogin".to_string(),
+            value: BlkPropertyValue::Long(1718000000123),
+            quote: QuoteStyle::Double,
+            hex: false,
+            hex_color: false,
+            raw: None,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "lastLogin:i64=1718000000123\nsmall:i=5\n");
+    }
+
+    #[test]
+    fn test_parse_integer_points() {
+        let (remaining, config) = parse_config("size:ip2=1920, 1080;pos:ip3=-4, 0, 12;\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.block.entries.iter().map(|entry| match entry {
+            BlkEntry::Property(property) => property.value.clone(),
+            _ => unreachable!()
+        }).collect::<Vec<_>>(), vec![BlkPropertyValue::IntVector2(1920, 1080), BlkPropertyValue::IntVector3(-4, 0, 12)]);
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "size:ip2=1920, 1080\npos:ip3=-4, 0, 12\n");
+    }
+
+    #[test]
+    fn test_parse_matrix() {
+        let (remaining, config) = parse_config("tm:m=[[1, 0, 0] [0, 1, 0]\n  [0, 0, 1] [10.5, -2, 3]]\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["tm"]), Some(&BlkEntry::Property(BlkProperty {
+            key: "tm".to_string(),
+            value: BlkPropertyValue::Matrix(Box::new([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [10.5, -2.0, 3.0]])),
+            quote: QuoteStyle::Double,
+            hex: false,
+            hex_color: false,
+            raw: None,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "tm:m=[[1, 0, 0] [0, 1, 0] [0, 0, 1] [10.5, -2, 3]]\n");
+    }
+
+    #[test]
+    fn test_parse_hex_integers() {
+        let (remaining, config) = parse_config("mask:i=0xFF;tint:c=0xFF, 0x80, 0, 255;all:i=0xFFFFFFFF;plain:i=16;\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("mask:i=255;tint:c=255, 128, 0, 255;all:i=-1;plain:i=16;").unwrap().1);
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "mask:i=255\ntint:c=255, 128, 0, 255\nall:i=-1\nplain:i=16\n");
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { keep_hex: true, ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "mask:i=0xFF\ntint:c=0xFF, 0x80, 0x0, 0xFF\nall:i=0xFFFFFFFF\nplain:i=16\n");
+    }
+
+    #[test]
+    fn test_parse_entries_after_sections() {
+        let input = "crosshair{ line{ line:p4=1, 2, 3, 4; move:b=no; } line{ line:p4=5, 6, 7, 8; move:b=yes; } }\nempty{}end:i=1\n";
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+        let BlkEntry::Section(crosshair) = &config.block.entries[0] else { panic!("expected a section") };
+
+        assert_eq!(crosshair.entries.len(), 2);
+        assert!(config.find(&["end"]).is_some());
+
+        for chunk_size in [1, 7, 64] {
+            assert_eq!(parse_chunked(input.as_bytes(), chunk_size, &ParseOptions::default(), &mut Vec::new()).unwrap(), config);
+        }
+
+        assert!(parse_file("a:i=1 b:i=2\n", &ParseOptions::default(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_parse_trailing_commas() {
+        let input = "crosshair{ line:p4=1, 2, 3, 4,; pos:ip2=1, 2 , // left\n}\ncolor:c=255, 0, 0, 255,\n";
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(config, parse_config("crosshair{ line:p4=1, 2, 3, 4; pos:ip2=1, 2; }\ncolor:c=255, 0, 0, 255\n").unwrap().1);
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert!(std::str::from_utf8(&output).unwrap().ends_with("color:c=255, 0, 0, 255\n"));
+
+        let options = ParseOptions { dialect: Dialect::STRICT, ..Default::default() };
+        let error = parse_file("a:p2=1, 2,\n", &options, &mut Vec::new()).unwrap_err();
+        assert_eq!((error.message.as_str(), error.column), ("trailing commas are not allowed by the strict dialect", 10));
+    }
+
+    #[test]
+    fn test_stringify_indent() {
+        let (_, config) = parse_config("a{ b{ c:i=1; }; }").unwrap();
+        let stringify = |indent: &str| config.to_blk_string(&SerializeOptions { indent: indent.parse().unwrap(), ..Default::default() });
+
+        assert_eq!(stringify("4"), "a{\n    b{\n        c:i=1\n    }\n}\n");
+        assert_eq!(stringify("2"), "a{\n  b{\n    c:i=1\n  }\n}\n");
+        assert_eq!(stringify("tabs"), "a{\n\tb{\n\t\tc:i=1\n\t}\n}\n");
+        assert_eq!(stringify("none"), "a{\nb{\nc:i=1\n}\n}\n");
+        assert!("wide".parse::<IndentStyle>().is_err());
+    }
+
+    #[test]
+    fn test_stringify_compact() {
+        let input = "a:i=1\nsight{\n  b:t=\"x\"\n  empty{}\n  inner{ c:r=0.5 }\n}\nd:b=yes\n";
+        let (_, config) = parse_config(input).unwrap();
+        let output = config.to_blk_string(&SerializeOptions { style: OutputStyle::Compact, ..Default::default() });
+
+        assert_eq!(output, "a:i=1; sight{ b:t=\"x\"; empty{}; inner{ c:r=0.5 } }; d:b=yes\n");
+        assert_eq!(parse_config(&output).unwrap(), ("", config));
+        assert!("dense".parse::<OutputStyle>().is_err());
+    }
+
+    #[test]
+    fn test_stringify_sorted() {
+        let (_, config) = parse_config("zoom:i=1; video{ b:i=2; a:i=1 } alpha:i=3; audio{} beta:i=4\n").unwrap();
+        let stringify = |sort, group| config.to_blk_string(&SerializeOptions { style: OutputStyle::Compact, sort, group, ..Default::default() });
+
+        assert_eq!(stringify(Some(SortKeys::Properties), false), "alpha:i=3; video{ a:i=1; b:i=2 }; beta:i=4; audio{}; zoom:i=1\n");
+        assert_eq!(stringify(Some(SortKeys::All), false), "alpha:i=3; audio{}; beta:i=4; video{ a:i=1; b:i=2 }; zoom:i=1\n");
+        assert_eq!(stringify(None, true), "zoom:i=1; alpha:i=3; beta:i=4; video{ b:i=2; a:i=1 }; audio{}\n");
+        assert_eq!(stringify(Some(SortKeys::All), true), "alpha:i=3; beta:i=4; zoom:i=1; audio{}; video{ a:i=1; b:i=2 }\n");
+        assert!("keys".parse::<SortKeys>().is_err());
+    }
+
+    #[test]
+    fn test_stringify_canonical() {
+        let first = parse_file("\u{FEFF}b:r=0.50; a:c=#FF8000C0\r\nz{ y:b=on; x:t='q' }\r\nmask:i=0x0F\r\n", &ParseOptions::default(), &mut Vec::new()).unwrap();
+        let second = parse_file("mask:i=15; b:r=.5; z{ x:t=\"q\"; y:b=true }; a:c=255, 128, 0, 192\n", &ParseOptions::default(), &mut Vec::new()).unwrap();
+        let canonical = first.to_blk_string(&SerializeOptions::CANONICAL);
+
+        assert_eq!(canonical, "a:c=255, 128, 0, 192\nb:r=0.5\nmask:i=15\nz{\n    x:t=\"q\"\n    y:b=yes\n}\n");
+        assert_eq!(second.to_blk_string(&SerializeOptions::CANONICAL), canonical);
+    }
+
+    #[test]
+    fn test_display_values() {
+        let (_, config) = parse_config("a:t='say \"hi\"'; b:b=on; c:r=0.50; d:p2=1, 2.5; e:c=#FF8000C0; f:i=; g:m=[[1, 0, 0] [0, 1, 0] [0, 0, 1] [0, 0, 0]]\n").unwrap();
+        let values: Vec<String> = config.block.entries.iter().map(|entry| match entry {
+            BlkEntry::Property(property) => property.value.to_string(),
+            entry => panic!("unexpected entry: {:?}", entry)
+        }).collect();
+
+        assert_eq!(values, ["\"say \\\"hi\\\"\"", "yes", "0.5", "1, 2.5", "255, 128, 0, 192", "", "[[1, 0, 0] [0, 1, 0] [0, 0, 1] [0, 0, 0]]"]);
+    }
+
+    #[test]
+    fn test_stringify_blank_lines() {
+        let input = "a:i=1\n\nb:i=2\n// note\nc{\n  d:i=3\n\n  e{}\n}\n\n\nf{}\n";
+        let stringify = |config: &BlkConfig, blank_lines| config.to_blk_string(&SerializeOptions { blank_lines, indent: IndentStyle::None, ..Default::default() });
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(stringify(&config, BlankLines::None), "a:i=1\nb:i=2\n// note\nc{\nd:i=3\ne{\n}\n}\nf{\n}\n");
+        assert_eq!(stringify(&config, BlankLines::Sections), "a:i=1\nb:i=2\n\n// note\nc{\nd:i=3\n\ne{\n}\n}\n\nf{\n}\n");
+        assert_eq!(stringify(&config, BlankLines::Preserve), "a:i=1\n\nb:i=2\n// note\nc{\nd:i=3\n\ne{\n}\n}\n\nf{\n}\n");
+
+        for chunk_size in [1, 4, 1024] {
+            let streamed = parse_chunked(input.as_bytes(), chunk_size, &ParseOptions::default(), &mut Vec::new()).unwrap();
+            assert_eq!(stringify(&streamed, BlankLines::Preserve), stringify(&config, BlankLines::Preserve));
+        }
+    }
+
+    #[test]
+    fn test_stringify_comments() {
+        let input = "// file\na:i=1 // one\n\n/* block */\ns{ // section\n  // inside\n  b:i=2; /* two */\n  // closing\n} // end\ninclude \"x//y.blk\"\n// tail\n";
+        let expected = "// file\na:i=1 // one\n\n/* block */\ns{ // section\n    // inside\n    b:i=2 /* two */\n    // closing\n} // end\ninclude \"x//y.blk\"\n// tail\n";
+        let options = SerializeOptions { blank_lines: BlankLines::Preserve, ..Default::default() };
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+
+        assert_eq!(config.to_blk_string(&options), expected);
+        assert_eq!(parse_config(input).unwrap().1.to_blk_string(&options), expected);
+
+        for chunk_size in [1, 4, 1024] {
+            let streamed = parse_chunked(input.as_bytes(), chunk_size, &ParseOptions::default(), &mut Vec::new()).unwrap();
+            assert_eq!(streamed.to_blk_string(&options), expected, "chunk size {}", chunk_size);
+        }
+
+        // sections with comments inside stay on several lines, the compact style leaves comments out
+        let options = SerializeOptions { inline_sections: InlineSections::Single, ..Default::default() };
+        assert_eq!(parse_config("s{ a:i=1 // one\n}").unwrap().1.to_blk_string(&options), "s{\n    a:i=1 // one\n}\n");
+
+        let options = SerializeOptions { style: OutputStyle::Compact, ..Default::default() };
+        assert_eq!(config.to_blk_string(&options), "a:i=1; s{ b:i=2 }; include \"x//y.blk\"\n");
+    }
+
+    #[test]
+    fn test_stringify_terminators() {
+        let input = "a:i=1;\nb:i=2\nc{ d:i=3; };\ninclude \"x.blk\"\n";
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+        let stringify = |terminators| config.to_blk_string(&SerializeOptions { terminators, indent: IndentStyle::None, ..Default::default() });
+
+        assert_eq!(stringify(Terminators::Newline), "a:i=1\nb:i=2\nc{\nd:i=3\n}\ninclude \"x.blk\"\n");
+        assert_eq!(stringify(Terminators::Semicolon), "a:i=1;\nb:i=2;\nc{\nd:i=3;\n}\ninclude \"x.blk\";\n");
+        assert_eq!(stringify(Terminators::Preserve), "a:i=1;\nb:i=2\nc{\nd:i=3;\n};\ninclude \"x.blk\"\n");
+        assert_eq!(parse_config(&stringify(Terminators::Preserve)).unwrap(), ("", config));
+    }
+
+    #[test]
+    fn test_stringify_fmt() {
+        let (_, config) = parse_config("name:t=\"Пилот\"; sky{ clouds:r=0.5 }\n").unwrap();
+        let mut output = String::from("// generated\n");
+        stringify_config_fmt(&config, &mut output, &SerializeOptions { encoding: Some(crate::encoding::FALLBACK), ..Default::default() }).unwrap();
+
+        assert_eq!(output, "// generated\nname:t=\"Пилот\"\nsky{\n    clouds:r=0.5\n}\n");
+    }
+
+    #[test]
+    fn test_stringify_subtree() {
+        let (_, config) = parse_config("sky:i=1; hotkeys{ fire:t=\"mouse1\"; menu{ open:t=\"esc\" } }\n").unwrap();
+        let Some(BlkEntry::Section(hotkeys)) = config.find(&["hotkeys"]) else { panic!("missing hotkeys") };
+        let write = |stringify: &dyn Fn(&mut Vec<u8>) -> io::Result<()>| {
+            let mut output = Vec::new();
+            stringify(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(write(&|output| stringify_section(hotkeys, output, &SerializeOptions::default())), "hotkeys{\n    fire:t=\"mouse1\"\n    menu{\n        open:t=\"esc\"\n    }\n}\n");
+        assert_eq!(write(&|output| stringify_entries(&hotkeys.entries, output, &SerializeOptions::default())), "fire:t=\"mouse1\"\nmenu{\n    open:t=\"esc\"\n}\n");
+        assert_eq!(
+            write(&|output| stringify_section(hotkeys, output, &SerializeOptions { style: OutputStyle::Compact, line_endings: Some(LineEnding::CrLf), ..Default::default() })),
+            "hotkeys{ fire:t=\"mouse1\"; menu{ open:t=\"esc\" } }\r\n"
+        );
+    }
+
+    #[test]
+    fn test_stringify_formatters() {
+        let (_, config) = parse_config("zoom:r=0.5; fov:r=1.25; tint:c=255,128,0,255; name:t=\"sight\"\n").unwrap();
+        let mut formatters = ValueFormatters::new();
+        formatters.register(BlkType::Real, |value| match value {
+            BlkPropertyValue::Real(real) => Some(format!("{:.3}", real)),
+            _ => None
+        });
+        formatters.register(BlkType::Color, |value| match value {
+            BlkPropertyValue::Color(r, g, b, a) => Some(format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)),
+            _ => None
+        });
+        let options = SerializeOptions { formatters, style: OutputStyle::Compact, ..Default::default() };
+        let output = config.to_blk_string(&options);
+
+        assert_eq!(output, "zoom:r=0.500; fov:r=1.250; tint:c=#ff8000ff; name:t=\"sight\"\n");
+        assert_eq!(parse_config(&output).unwrap().1, config);
+        assert_eq!(options.clone(), options);
+        assert_ne!(options, SerializeOptions { style: OutputStyle::Compact, ..Default::default() });
+    }
+
+    #[test]
+    fn test_stringify_aligned() {
+        let (_, config) = parse_config("crosshair_distance:r=2; x:i=1; sight{ 'odd key':t=\"a\"; y:i=2 }\n").unwrap();
+        let output = config.to_blk_string(&SerializeOptions { align: true, ..Default::default() });
+
+        assert_eq!(output, "crosshair_distance:r=2\nx                 :i=1\nsight{\n    \"odd key\":t=\"a\"\n    y        :i=2\n}\n");
+        assert_eq!(parse_config(&output).unwrap(), ("", config));
+    }
+
+    #[test]
+    fn test_stringify_quote_keys() {
+        let (_, config) = parse_config("plain:i=1; \"with space\":i=2; \"a@b\"{}\n").unwrap();
+        let stringify = |quote_keys| config.to_blk_string(&SerializeOptions { quote_keys, style: OutputStyle::Compact, ..Default::default() });
+
+        assert_eq!(stringify(QuoteKeys::WhenNeeded), "plain:i=1; \"with space\":i=2; \"a@b\"{}\n");
+        assert_eq!(stringify(QuoteKeys::Always), "\"plain\":i=1; \"with space\":i=2; \"a@b\"{}\n");
+        assert_eq!(stringify(QuoteKeys::Never), "plain:i=1; with space:i=2; a@b{}\n");
+        assert!("sometimes".parse::<QuoteKeys>().is_err());
+    }
+
+    #[test]
+    fn test_stringify_component_spacing() {
+        let (_, mut config) = parse_config("pos:p3=1,2,3; size:ip2=4, 5; tint:c=1,2,3,4; scale:r=0.50\n").unwrap();
+
+        if let BlkEntry::Property(property) = &mut config.block.entries[0] {
+            property.value = BlkPropertyValue::Vector3(1.0, 2.0, 3.5);
+        }
+
+        let stringify = |spacing| config.to_blk_string(&SerializeOptions { spacing, style: OutputStyle::Compact, ..Default::default() });
+
+        assert_eq!(stringify(None), "pos:p3=1,2,3.5; size:ip2=4, 5; tint:c=1,2,3,4; scale:r=0.50\n");
+        assert_eq!(stringify(Some(ComponentSpacing::Spaced)), "pos:p3=1, 2, 3.5; size:ip2=4, 5; tint:c=1, 2, 3, 4; scale:r=0.50\n");
+        assert_eq!(stringify(Some(ComponentSpacing::Tight)), "pos:p3=1,2,3.5; size:ip2=4,5; tint:c=1,2,3,4; scale:r=0.50\n");
+    }
+
+    #[test]
+    fn test_stringify_header() {
+        let (_, config) = parse_config("a:i=1\n").unwrap();
+        let header = Some("Merged by blk-merge\n\nbase: a.blk".to_string());
+
+        assert_eq!(config.to_blk_string(&SerializeOptions { header: header.clone(), ..Default::default() }), "// Merged by blk-merge\n//\n// base: a.blk\na:i=1\n");
+        assert_eq!(config.to_blk_string(&SerializeOptions { header, style: OutputStyle::Compact, ..Default::default() }), "// Merged by blk-merge\n//\n// base: a.blk\na:i=1\n");
+    }
+
+    #[test]
+    fn test_stringify_inline_sections() {
+        let (_, config) = parse_config("empty{}\nsingle{ a:i=1 }\nnested{ inner{} }\nfull{ b:i=2; c:i=3 }\n").unwrap();
+        let stringify = |inline_sections| config.to_blk_string(&SerializeOptions { inline_sections, indent: IndentStyle::Spaces(2), ..Default::default() });
+
+        assert_eq!(stringify(InlineSections::Never), "empty{\n}\nsingle{\n  a:i=1\n}\nnested{\n  inner{\n  }\n}\nfull{\n  b:i=2\n  c:i=3\n}\n");
+        assert_eq!(stringify(InlineSections::Empty), "empty{}\nsingle{\n  a:i=1\n}\nnested{\n  inner{}\n}\nfull{\n  b:i=2\n  c:i=3\n}\n");
+        assert_eq!(stringify(InlineSections::Single), "empty{}\nsingle{ a:i=1 }\nnested{\n  inner{}\n}\nfull{\n  b:i=2\n  c:i=3\n}\n");
+        assert_eq!(parse_config(&stringify(InlineSections::Single)).unwrap(), ("", config));
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let input = "a:i=1\r\nb{\r\n  c:t=\"x\"\r\n}\r\n";
+        let config = parse_file(input, &ParseOptions::default(), &mut Vec::new()).unwrap();
+        let stringify = |line_endings| config.to_blk_string(&SerializeOptions { line_endings, ..Default::default() });
+
+        assert!(config.crlf);
+        assert!(parse_chunked(input.as_bytes(), 3, &ParseOptions::default(), &mut Vec::new()).unwrap().crlf);
+        assert!(!parse_file("a:i=1\nb:i=2\r\n", &ParseOptions::default(), &mut Vec::new()).unwrap().crlf);
+        assert_eq!(stringify(None), "a:i=1\r\nb{\r\n    c:t=\"x\"\r\n}\r\n");
+        assert_eq!(stringify(Some(LineEnding::Lf)), "a:i=1\nb{\n    c:t=\"x\"\n}\n");
+
+        let mut output = Vec::new();
+        stringify_config_preserving(&config, input, &mut output, &SerializeOptions { line_endings: Some(LineEnding::Lf), ..Default::default() }).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "a:i=1\nb{\n  c:t=\"x\"\n}\n");
+        assert!("cr".parse::<LineEnding>().is_err());
+    }
+
+    #[test]
+    fn test_parse_boolean_literals() {
+        let (remaining, config) = parse_config("a:b=1;b:b=0;c:b=on;d:b=off;e:b=true;f:b=no;\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("a:b=yes;b:b=no;c:b=yes;d:b=no;e:b=yes;f:b=no;").unwrap().1);
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { booleans: BooleanStyle::OnOff, ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "a:b=on\nb:b=off\nc:b=on\nd:b=off\ne:b=on\nf:b=off\n");
+        assert!(parse_file("a:b=10\n", &ParseOptions::default(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_raw_literals() {
+        let input = "scale:r=0.50\nlimit:i=+10000\nstep:r=1e-3\npos:p3=1.0, 2.50, 3\nmask:i=0x0F\n";
+        let (_, mut config) = parse_config(input).unwrap();
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "scale:r=0.50\nlimit:i=+10000\nstep:r=1e-3\npos:p3=1.0, 2.50, 3\nmask:i=15\n");
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { normalize_numbers: true, ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "scale:r=0.5\nlimit:i=10000\nstep:r=0.001\npos:p3=1, 2.5, 3\nmask:i=15\n");
+
+        if let BlkEntry::Property(property) = &mut config.block.entries[0] {
+            property.value = BlkPropertyValue::Real(0.75);
+        }
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert!(std::str::from_utf8(&output).unwrap().starts_with("scale:r=0.75\nlimit:i=+10000\n"));
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { precision: Some(2), ..Default::default() }).unwrap();
+        assert!(std::str::from_utf8(&output).unwrap().starts_with("scale:r=0.75\n"));
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { precision: Some(2), normalize_numbers: true, ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "scale:r=0.75\nlimit:i=10000\nstep:r=0.00\npos:p3=1.00, 2.50, 3.00\nmask:i=15\n");
+    }
+
+    #[test]
+    fn test_parse_hex_colors() {
+        let (remaining, config) = parse_config("tint:c=#FF8000C0;sky:c=#0080ff;plain:c=1, 2, 3, 4;\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config, parse_config("tint:c=255, 128, 0, 192;sky:c=0, 128, 255, 255;plain:c=1, 2, 3, 4;").unwrap().1);
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "tint:c=#FF8000C0\nsky:c=#0080ff\nplain:c=1, 2, 3, 4\n");
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { normalize_numbers: true, ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "tint:c=#FF8000C0\nsky:c=#0080FFFF\nplain:c=1, 2, 3, 4\n");
+
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { colors: Some(ColorStyle::Decimal), ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "tint:c=255, 128, 0, 192\nsky:c=0, 128, 255, 255\nplain:c=1, 2, 3, 4\n");
+
+        let (_, config) = parse_config("plain:c=1, 2, 3, 4;wide:c=300, 0, 0, 255;").unwrap();
+        let mut output = Vec::new();
+        stringify_config_with(&config, &mut output, &SerializeOptions { colors: Some(ColorStyle::Hex), ..Default::default() }).unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "plain:c=#01020304\nwide:c=300, 0, 0, 255\n");
+
+        assert!(parse_file("a:c=#FFF\n", &ParseOptions::default(), &mut Vec::new()).is_err());
+        let options = ParseOptions { dialect: Dialect::STRICT, ..Default::default() };
+        assert_eq!(parse_file("a:c=#FFFFFF\n", &options, &mut Vec::new()).unwrap_err().message, "hexadecimal colors are not allowed by the strict dialect");
+    }
+
+    #[test]
+    fn test_parse_overflowing_integers() {
+        let input = "mask:i=4294967295;low:i=-3000000000;";
+        let options = |overflow| ParseOptions { overflow, ..Default::default() };
+        let mut warnings = Vec::new();
+
+        let (_, config) = parse_config(input).unwrap();
+        assert_eq!(config, parse_config("mask:i64=4294967295;low:i64=-3000000000;").unwrap().1);
+
+        let (_, config) = parse_config_with(input, &options(IntegerOverflow::Saturate), &mut warnings).unwrap();
+        assert_eq!(config, parse_config("mask:i=2147483647;low:i=-2147483648;").unwrap().1);
+        assert_eq!(warnings[0].message, "`mask` value 4294967295 does not fit in 32 bits, saturated to 2147483647");
+        assert_eq!(warnings.len(), 2);
+
+        assert!(!parse_config_with(input, &options(IntegerOverflow::Error), &mut warnings).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn test_parse_section_annotations() {
+        let input = "weapon:override{\n    ammo:i=1\n}\nturret@base_turret{\n}\n\"a@b\"{\n}\n";
+        let (remaining, config) = parse_config(input).unwrap();
+        let annotation = |name: &str| match config.find(&[name]) {
+            Some(BlkEntry::Section(section)) => section.annotation.clone(),
+            _ => panic!("no section `{}`", name)
+        };
+
+        assert_eq!(remaining, "");
+        assert_eq!(annotation("weapon"), Some(SectionAnnotation::Tag("override".to_string())));
+        assert_eq!(annotation("turret"), Some(SectionAnnotation::Template("base_turret".to_string())));
+        assert_eq!(annotation("a@b"), None);
+        assert_ne!(config, parse_config("weapon{\n    ammo:i=1\n}\nturret{\n}\n\"a@b\"{\n}\n").unwrap().1);
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+
+        let error = parse_file("weapon:override{\n  ammo:i=x\n}\n", &ParseOptions::default(), &mut Vec::new()).unwrap_err();
+        assert_eq!((error.message.as_str(), error.line), ("expected integer value after '='", 2));
+    }
+
+    #[test]
+    fn test_parse_unnamed_sections() {
+        let (remaining, config) = parse_config("points{\n  { x:i=1; }\n  { x:i=2; }\n}\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.find(&["points", ""]), Some(&BlkEntry::Section(BlkSection {
+            name: String::new(),
+            annotation: None,
+            entries: parse_config("x:i=1;").unwrap().1.block.entries,
+            span: None,
+            comments: Comments::default()
+        })));
+
+        let mut output = Vec::new();
+        stringify_config(&config, &mut output).unwrap();
+
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "points{\n    {\n        x:i=1\n    }\n    {\n        x:i=2\n    }\n}\n");
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let (remaining, config) = parse_config("include \"shared/video.blk\"\ngraphics{\n  include \"sky.blk\";\n}\n").unwrap();
+
+        assert_eq!(remaining, "");
+        assert_eq!(config.block.entries[0], BlkEntry::Include("shared/video.blk".to_string()));
+        assert_eq!(config.find(&["graphics", "sky.blk"]), Some(&BlkEntry::Include("sky.blk".to_string())));
+    }
+
     #[test]
     fn test_parse_multiline_config() {
         let input = r#"
@@ -284,28 +2307,56 @@ mod tests {
 
         assert_eq!(remaining, "");
         assert_eq!(config, BlkConfig {
+            bom: false,
+            crlf: false,
+            encoding: None,
+            comments: Vec::new(),
             block: BlkBlock { entries: vec![
                 BlkEntry::Section(BlkSection {
                     name: "input".to_string(),
+                    annotation: None,
                     entries: vec![
                         BlkEntry::Property(BlkProperty {
                             key: "owo".to_string(),
-                            value: BlkPropertyValue::Integer(32)
+                            value: BlkPropertyValue::Integer(32),
+                            quote: QuoteStyle::Double,
+                            hex: false,
+                            hex_color: false,
+                            raw: None,
+                            span: None,
+                            comments: Comments::default()
                         }),
                         BlkEntry::Property(BlkProperty {
                             key: "uwu".to_string(),
-                            value: BlkPropertyValue::Text("uwu".to_string())
+                            value: BlkPropertyValue::Text("uwu".to_string()),
+                            quote: QuoteStyle::Double,
+                            hex: false,
+                            hex_color: false,
+                            raw: None,
+                            span: None,
+                            comments: Comments::default()
                         }),
                         BlkEntry::Section(BlkSection {
                             name: "output".to_string(),
+                            annotation: None,
                             entries: vec![
                                 BlkEntry::Property(BlkProperty {
                                     key: "someText".to_string(),
-                                    value: BlkPropertyValue::Text("OwO".to_string())
+                                    value: BlkPropertyValue::Text("OwO".to_string()),
+                                    quote: QuoteStyle::Double,
+                                    hex: false,
+                                    hex_color: false,
+                                    raw: None,
+                                    span: None,
+                                    comments: Comments::default()
                                 })
-                            ]
+                            ],
+                            span: None,
+                            comments: Comments::default()
                         })
-                    ]
+                    ],
+                    span: None,
+                    comments: Comments::default()
                 })
             ] }
         })
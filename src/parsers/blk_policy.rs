@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use crate::{parsers, path::PathPattern, policy::*, types::*};
+
+/// Parses a policy value, blaming it with the given kind if it is invalid.
+fn convert<T: FromStr<Err = String>>(text: &str, kind: PolicyErrorKind) -> Result<T, PolicyError> {
+    text.parse().map_err(|message| PolicyError::new(kind, text, message))
+}
+
+/// Adds the entries of one policy section to the policy, `path` being the section's path.
+fn read_section(entries: &[BlkEntry], path: &[String], policy: &mut MergePolicy) -> Result<(), PolicyError> {
+    use PolicyErrorKind::*;
+
+    let location = if path.is_empty() { "the top level".to_string() } else { format!("`{}`", path.join("/")) };
+    let section_pattern = || convert::<PathPattern>(&path.join("/"), InvalidPattern);
+    let relative = |text: &str| [path.join("/"), text.to_string()].join("/").trim_start_matches('/').to_string();
+
+    // `priority` and `if` belong to the section's `strategy` rule, wherever they are written
+    let setting = |name: &str| entries.iter().find_map(|entry| match entry {
+        BlkEntry::Property(BlkProperty { key, value, .. }) if key == name => Some(value),
+        _ => None
+    });
+
+    let priority = match setting("priority") {
+        None => 0,
+        Some(BlkPropertyValue::Integer(priority)) if !path.is_empty() => *priority,
+        Some(_) => return Err(PolicyError::new(InvalidValue, "priority", format!("`priority` in {} must be an integer property of a section", location)))
+    };
+
+    let condition = match setting("if") {
+        None => None,
+        Some(BlkPropertyValue::Text(condition)) if !path.is_empty() => Some(convert(condition, InvalidCondition)?),
+        Some(_) => return Err(PolicyError::new(InvalidValue, "if", format!("`if` in {} must be a text property of a section", location)))
+    };
+
+    for entry in entries {
+        let property = match entry {
+            BlkEntry::Section(section) => {
+                let path = [path, std::slice::from_ref(&section.name)].concat();
+                read_section(&section.entries, &path, policy)?;
+                continue;
+            },
+            BlkEntry::Property(property) if property.key == "priority" || property.key == "if" => continue,
+            BlkEntry::Property(property) => property,
+            BlkEntry::Conflict(_) => return Err(PolicyError::new(Syntax, "", format!("unexpected conflict in {}", location))),
+            BlkEntry::Include(file) => return Err(PolicyError::new(Syntax, file, format!("includes are not supported in policies, found one in {}", location)))
+        };
+
+        let BlkPropertyValue::Text(text) = &property.value else {
+            return Err(PolicyError::new(InvalidValue, &property.key, format!("`{}` in {} must be a text property", property.key, location)));
+        };
+
+        match (property.key.as_str(), path.is_empty()) {
+            ("strategy", true) => policy.strategy = Some(convert(text, UnknownStrategy)?),
+            ("strategy", false) => policy.rules.push(PolicyRule {
+                pattern: section_pattern()?,
+                strategy: convert(text, UnknownStrategy)?,
+                priority,
+                condition: condition.clone()
+            }),
+            ("rule" | "strip", _) if !path.is_empty() && text.starts_with("regex:") => return Err(PolicyError::new(
+                InvalidPattern,
+                text,
+                format!("regex patterns must be written at the top level, found one in {}", location)
+            )),
+            ("rule", _) => policy.rules.push(parse_rule(&relative(text))?),
+            ("strip", _) => policy.stripped.push(convert(&relative(text), InvalidPattern)?),
+            ("alias", _) => policy.aliases.push(convert(text, InvalidValue)?),
+            ("when", true) => policy.conditions.push(convert(text, InvalidCondition)?),
+            ("when", false) => policy.conditions.push(ConditionalRule { pattern: section_pattern()?, condition: convert(text, InvalidCondition)? }),
+            ("transform", true) => policy.transforms.push(convert(text, InvalidTransform)?),
+            ("transform", false) => policy.transforms.push(TransformRule { pattern: section_pattern()?, transform: convert(text, InvalidTransform)? }),
+            ("order", true) => policy.order.push(convert(&format!("/ {}", text), InvalidValue)?),
+            ("order", false) => policy.order.push(convert(&format!("{} {}", path.join("/"), text), InvalidValue)?),
+            ("coerce", true) => policy.coercions.push(convert(text, InvalidValue)?),
+            ("coerce", false) => policy.coercions.push(CoerceRule { pattern: section_pattern()?, ty: convert(text, InvalidValue)? }),
+            ("allow", true) => policy.allowed.push(convert(text, InvalidValue)?),
+            ("allow", false) => policy.allowed.push(convert(&format!("{} {}", path.join("/"), text), InvalidValue)?),
+            ("require", _) => {
+                let mut required: RequiredKey = convert(text, InvalidValue)?;
+                required.path.splice(0..0, path.iter().cloned());
+                policy.required.push(required);
+            },
+            ("assert", true) => policy.assertions.push(convert(text, InvalidCondition)?),
+            ("assert", false) => return Err(PolicyError::new(
+                InvalidCondition,
+                text,
+                format!("assertions must be written at the top level, found one in {}", location)
+            )),
+            ("protect", true) => policy.protected.push(convert(text, InvalidValue)?),
+            ("protect", false) => policy.protected.push(match text.as_str() {
+                "yes" => ProtectRule { pattern: section_pattern()?, strict: false },
+                "strict" => ProtectRule { pattern: section_pattern()?, strict: true },
+                _ => return Err(PolicyError::new(InvalidValue, text, format!("`protect` in {} must be `yes` or `strict`, got `{}`", location, text)))
+            }),
+            (key, _) => return Err(PolicyError::new(UnknownDirective, key, format!("unknown policy property `{}` in {}", key, location)))
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a `pattern=strategy [if condition]` rule, blaming the part that is invalid.
+fn parse_rule(text: &str) -> Result<PolicyRule, PolicyError> {
+    let (rule, condition) = match text.split_once(" if ") {
+        Some((rule, condition)) => (rule, Some(condition)),
+        None => (text, None)
+    };
+
+    let Some((pattern, strategy)) = rule.split_once('=') else {
+        return Err(PolicyError::new(PolicyErrorKind::Syntax, text, format!("expected `path=strategy`, got `{}`", text)));
+    };
+
+    Ok(PolicyRule {
+        pattern: convert(pattern.trim(), PolicyErrorKind::InvalidPattern)?,
+        strategy: convert(strategy.trim(), PolicyErrorKind::UnknownStrategy)?,
+        priority: 0,
+        condition: condition.map(|condition| convert(condition, PolicyErrorKind::InvalidCondition)).transpose()?
+    })
+}
+
+/// Parses a policy written in BLK syntax.
+///
+/// Sections name the path their properties apply to, so nested sections spell out a path
+/// pattern. A `strategy` property binds a strategy to its section, or sets the default one
+/// at the top level. An integer `priority` property sets the priority of that rule, and an
+/// `if` property a condition it is gated on. `rule` takes a `pattern=strategy` pair relative to its section, which
+/// allows wildcards, `alias` takes a `from=to` pair and `when` a condition the section is
+/// merged under, written as `pattern if condition` at the top level. `transform` works the
+/// same way for value transforms, and `protect` takes `yes` or `strict` in a section and
+/// `pattern [strict]` at the top level. `coerce` takes a type in a section and
+/// `pattern -> type` at the top level, and `allow` takes `{value, ...} [reject|nearest|keep]`,
+/// prefixed by a pattern at the top level. `require` takes a `path [type]` pair relative to its
+/// section, `strip` a pattern relative to its section naming entries removed from the
+/// output, and `order` lists the names the entries of its section are ordered by. `assert`
+/// takes a condition on the merged config and is only allowed at the top level. Double quotes
+/// in BLK strings must be escaped, so string literals in conditions are easier to write in
+/// single quotes.
+///
+/// ```text
+/// strategy:t="keep"
+/// controls{
+///     hotkeys{ strategy:t="append"; }
+///     rule:t="deviceMapping/*=keep"
+/// }
+/// hud{ when:t="clientType == '32bit'"; }
+/// alias:t="skyQuality=cloudsQuality"
+/// ```
+pub fn parse_policy(input: &str) -> Result<MergePolicy, PolicyError> {
+    let config = parsers::blk::parse_file(input, &Default::default(), &mut Vec::new()).map_err(|err| PolicyError {
+        location: Some((err.line, err.column)),
+        ..PolicyError::new(PolicyErrorKind::Syntax, err.text.trim(), err.message)
+    })?;
+
+    let mut policy = MergePolicy::default();
+    read_section(&config.block.entries, &[], &mut policy)?;
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeStrategy;
+
+    #[test]
+    fn test_parse_blk_policy() {
+        let input = r#"
+            strategy:t="keep"
+            controls{
+                hotkeys{ strategy:t="append"; }
+                rule:t="deviceMapping/*=delete"
+            }
+            hud{ when:t="clientType == '32bit'"; }
+            alias:t="skyQuality=cloudsQuality"
+        "#;
+        let policy = parse_policy(input).unwrap();
+
+        assert_eq!(policy.strategy, Some(MergeStrategy::Keep));
+        assert_eq!(policy.rules, vec![
+            "controls/hotkeys=append".parse().unwrap(),
+            "controls/deviceMapping/*=delete".parse().unwrap()
+        ]);
+        assert_eq!(policy.aliases, vec!["skyQuality=cloudsQuality".parse().unwrap()]);
+        assert_eq!(policy.conditions.len(), 1);
+        assert!(parse_policy("graphics{ strategy:i=1; }\n").is_err());
+        assert_eq!(parse_policy("graphics{ rule:t=\"sky=sometimes\"; }\n").unwrap_err().kind, PolicyErrorKind::UnknownStrategy);
+    }
+}
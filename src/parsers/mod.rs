@@ -1,2 +1,114 @@
+pub mod bbf;
 pub mod blk;
+pub mod blk_policy;
 pub mod pol;
+pub mod toml_policy;
+
+use std::{fmt, io::{self, BufRead, BufReader, Read}};
+
+use crate::{encoding::{self, DecodingReader}, types::BlkConfig};
+
+/// Errors reading a BLK file.
+#[derive(Debug)]
+pub enum BlkError {
+    /// The input cannot be read or is not text in the expected encoding.
+    Io(io::Error),
+    /// A text file does not parse.
+    Parse(blk::ParseError),
+    /// A binary file is malformed.
+    Binary(String)
+}
+
+impl fmt::Display for BlkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlkError::Io(err) => write!(f, "{}", err),
+            BlkError::Parse(err) => write!(f, "{}", err),
+            BlkError::Binary(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for BlkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlkError::Io(err) => Some(err),
+            BlkError::Parse(err) => Some(err),
+            BlkError::Binary(_) => None
+        }
+    }
+}
+
+/// Unwraps the parse errors [`blk::parse_stream`] returns as I/O errors.
+impl From<io::Error> for BlkError {
+    fn from(err: io::Error) -> Self {
+        match err.get_ref().is_some_and(|inner| inner.is::<blk::ParseError>()) {
+            true => BlkError::Parse(*err.into_inner().unwrap().downcast().unwrap()),
+            false => BlkError::Io(err)
+        }
+    }
+}
+
+/// Parses the content of a BLK file, decoding binary files and parsing text files as the options demand.
+pub fn parse_bytes(data: &[u8], options: &blk::ParseOptions, warnings: &mut Vec<blk::ParseWarning>) -> Result<BlkConfig, String> {
+    if bbf::is_binary(data) {
+        return bbf::parse_bbf(data, options);
+    }
+
+    let (text, encoding) = encoding::decode(data, options.encoding).map_err(|err| err.to_string())?;
+    let mut config = blk::parse_file(&text, options, warnings).map_err(|err| err.to_string())?;
+
+    config.encoding = Some(encoding).filter(|&encoding| encoding != encoding_rs::UTF_8);
+    Ok(config)
+}
+
+/// Like [`parse_bytes`], streaming text files from the reader instead of reading them at once.
+pub fn parse_reader(reader: impl BufRead, options: &blk::ParseOptions, warnings: &mut Vec<blk::ParseWarning>) -> Result<BlkConfig, String> {
+    parse_config_from_reader_with(reader, options, warnings).map_err(|err| err.to_string())
+}
+
+/// Parses a text or binary BLK file from any reader, like a pipe or a file in an archive,
+/// with the default options.
+pub fn parse_config_from_reader(reader: impl Read) -> Result<BlkConfig, BlkError> {
+    parse_config_from_reader_with(reader, &blk::ParseOptions::default(), &mut Vec::new())
+}
+
+/// Like [`parse_config_from_reader`], parsing as the options demand and adding warnings about the input to `warnings`.
+pub fn parse_config_from_reader_with(reader: impl Read, options: &blk::ParseOptions, warnings: &mut Vec<blk::ParseWarning>) -> Result<BlkConfig, BlkError> {
+    let mut reader = BufReader::new(reader);
+
+    if bbf::is_binary(reader.fill_buf()?) {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        return bbf::parse_bbf(&data, options).map_err(BlkError::Binary);
+    }
+
+    let mut reader = DecodingReader::new(reader, options.encoding);
+    let mut config = blk::parse_stream(&mut reader, options, warnings)?;
+
+    config.encoding = Some(reader.encoding()).filter(|&encoding| encoding != encoding_rs::UTF_8);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config_from_reader() {
+        let config = parse_config_from_reader("sky:i=2\ngraphics{ clouds:r=0.5 }\n".as_bytes()).unwrap();
+        assert_eq!(config, blk::parse_config("sky:i=2;graphics{ clouds:r=0.5; };").unwrap().1);
+
+        let Err(BlkError::Parse(err)) = parse_config_from_reader("sky:i=2\nclouds:r=x\n".as_bytes()) else {
+            panic!("expected a parse error");
+        };
+        assert_eq!((err.line, err.column), (2, 10));
+
+        assert!(matches!(parse_config_from_reader(&b"\x00BBF"[..]), Err(BlkError::Binary(_))));
+
+        let options = blk::ParseOptions { encoding: "utf-8".parse().unwrap(), ..Default::default() };
+        let err = parse_config_from_reader_with(&b"a:t=\"\xcf\"\n"[..], &options, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, BlkError::Io(ref err) if err.kind() == io::ErrorKind::InvalidData));
+    }
+}
@@ -1 +1,282 @@
-// todo: implement parsing for policy files (.blkr? .pol? idk yet)
+use std::str::FromStr;
+
+use nom::{branch::alt, bytes::complete::{is_not, tag}, character::complete::{space0, space1}, combinator::{all_consuming, opt, rest}, sequence::{delimited, preceded, separated_pair}, IResult, Parser};
+use crate::{condition::Condition, merge::MergeStrategy, path::PathPattern, policy::*, transform::Transform, types::BlkType};
+
+/// Represents a single line of a policy file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyDirective {
+    Default(MergeStrategy),
+    Rule(PolicyRule),
+    Alias(KeyAlias),
+    When(ConditionalRule),
+    Transform(TransformRule),
+    Protect(ProtectRule),
+    Require(RequiredKey),
+    Coerce(CoerceRule),
+    Allow(AllowRule),
+    Order(OrderRule),
+    Strip(PathPattern),
+    Assert(Assertion)
+}
+
+/// Parses a whitespace-delimited word.
+fn parse_word(input: &str) -> IResult<&str, &str> {
+    is_not(" \t").parse(input)
+}
+
+/// Parses a word and converts it with `FromStr`, failing the parser on conversion errors.
+fn parse_value<T: FromStr>(input: &str) -> IResult<&str, T> {
+    parse_word.map_res(str::parse).parse(input)
+}
+
+/// Parses a `default <strategy>` directive.
+fn parse_default(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("default"), space1), parse_value)
+        .map(PolicyDirective::Default)
+        .parse(input)
+}
+
+/// Parses a `rule <pattern> <strategy> [priority <n>] [if <condition>]` directive.
+fn parse_rule(input: &str) -> IResult<&str, PolicyDirective> {
+    let priority = opt(preceded((space1, tag("priority"), space1), nom::character::complete::i32));
+    let condition = opt(preceded((space1, tag("if"), space1), rest.map_res(str::parse)));
+
+    preceded((tag("rule"), space1), (parse_value::<PathPattern>, preceded(space1, parse_value), priority, condition))
+        .map(|(pattern, strategy, priority, condition)| {
+            PolicyDirective::Rule(PolicyRule { pattern, strategy, priority: priority.unwrap_or(0), condition })
+        })
+        .parse(input)
+}
+
+/// Parses an `alias <from> -> <to>` directive.
+fn parse_alias(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("alias"), space1), separated_pair(parse_word, delimited(space0, tag("->"), space0), parse_word))
+        .map(|(from, to)| PolicyDirective::Alias(KeyAlias { from: from.to_string(), to: to.to_string() }))
+        .parse(input)
+}
+
+/// Parses a `when <pattern> if <condition>` directive.
+fn parse_when(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("when"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::When)
+        .parse(input)
+}
+
+/// Parses a `transform <pattern> <transform>` directive.
+fn parse_transform(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("transform"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Transform)
+        .parse(input)
+}
+
+/// Parses a `protect <pattern> [strict]` directive.
+fn parse_protect(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("protect"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Protect)
+        .parse(input)
+}
+
+/// Parses a `require <path> [type]` directive.
+fn parse_require(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("require"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Require)
+        .parse(input)
+}
+
+/// Parses a `coerce <pattern> -> <type>` directive.
+fn parse_coerce(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("coerce"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Coerce)
+        .parse(input)
+}
+
+/// Parses an `allow <pattern> {<value>, ...} [reject|nearest|keep]` directive.
+fn parse_allow(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("allow"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Allow)
+        .parse(input)
+}
+
+/// Parses an `order <section> <name>...` directive.
+fn parse_order(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("order"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Order)
+        .parse(input)
+}
+
+/// Parses a `strip <pattern>` directive.
+fn parse_strip(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("strip"), space1), parse_value)
+        .map(PolicyDirective::Strip)
+        .parse(input)
+}
+
+/// Parses an `assert <condition>` directive.
+fn parse_assert(input: &str) -> IResult<&str, PolicyDirective> {
+    preceded((tag("assert"), space1), rest.map_res(str::parse))
+        .map(PolicyDirective::Assert)
+        .parse(input)
+}
+
+/// Parses a single policy directive.
+fn parse_directive(input: &str) -> IResult<&str, PolicyDirective> {
+    all_consuming(alt((parse_default, parse_rule, parse_alias, parse_when, parse_transform, parse_protect, parse_require, parse_coerce, parse_allow, parse_order, parse_strip, parse_assert))).parse(input)
+}
+
+/// Directive keywords of the line-based format.
+const KEYWORDS: &[&str] = &["default", "rule", "alias", "when", "transform", "protect", "require", "coerce", "allow", "order", "strip", "assert"];
+
+/// Parses a part of a directive, blaming it with the given kind if it is invalid.
+fn check<T: FromStr<Err = String>>(text: &str, kind: PolicyErrorKind) -> Option<PolicyError> {
+    text.parse::<T>().err().map(|message| PolicyError::new(kind, text, message))
+}
+
+/// Explains why a directive line failed to parse, pointing at the offending part.
+fn describe_error(line: &str) -> PolicyError {
+    use PolicyErrorKind::*;
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let rest = words.first().map_or("", |keyword| line[keyword.len()..].trim());
+
+    let error = match words.as_slice() {
+        ["default", strategy] => check::<MergeStrategy>(strategy, UnknownStrategy),
+        ["rule", ..] => {
+            let (rule, condition) = match rest.split_once(" if ") {
+                Some((rule, condition)) => (rule, Some(condition.trim())),
+                None => (rest, None)
+            };
+
+            match rule.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [pattern, strategy, tail @ ..] if matches!(tail, [] | ["priority", _]) => check::<PathPattern>(pattern, InvalidPattern)
+                    .or_else(|| check::<MergeStrategy>(strategy, UnknownStrategy))
+                    .or_else(|| tail.get(1).and_then(|priority| priority.parse::<i32>().err()
+                        .map(|_| PolicyError::new(InvalidValue, priority, format!("invalid priority `{}`", priority)))))
+                    .or_else(|| condition.and_then(|condition| check::<Condition>(condition, InvalidCondition))),
+                _ => None
+            }
+        },
+        ["when", ..] => rest.split_once(" if ").and_then(|(pattern, condition)| {
+            check::<PathPattern>(pattern.trim(), InvalidPattern).or_else(|| check::<Condition>(condition.trim(), InvalidCondition))
+        }),
+        ["transform", pattern, ..] => check::<PathPattern>(pattern, InvalidPattern)
+            .or_else(|| check::<Transform>(rest[pattern.len()..].trim(), InvalidTransform)),
+        ["protect", pattern, ..] => check::<PathPattern>(pattern, InvalidPattern).or_else(|| check::<ProtectRule>(rest, InvalidValue)),
+        ["require", ..] => check::<RequiredKey>(rest, InvalidValue),
+        ["coerce", ..] => match rest.split_once("->") {
+            Some((pattern, ty)) => check::<PathPattern>(pattern.trim(), InvalidPattern).or_else(|| check::<BlkType>(ty.trim(), InvalidValue)),
+            None => check::<CoerceRule>(rest, InvalidValue)
+        },
+        ["allow", ..] => rest.split_once('{')
+            .and_then(|(pattern, _)| check::<PathPattern>(pattern.trim(), InvalidPattern))
+            .or_else(|| check::<AllowRule>(rest, InvalidValue)),
+        ["order", ..] => check::<OrderRule>(rest, InvalidValue),
+        ["strip", pattern] => check::<PathPattern>(pattern, InvalidPattern),
+        ["assert", ..] => check::<Assertion>(rest, InvalidCondition),
+        [keyword, ..] if !KEYWORDS.contains(keyword) => Some(PolicyError::new(UnknownDirective, keyword, format!("unknown directive `{}`", keyword))),
+        _ => None
+    };
+
+    error.unwrap_or_else(|| PolicyError::new(Syntax, line, format!("invalid directive `{}`", line)))
+}
+
+/// Parses every directive of a policy file, paired with its 1-based line number.
+///
+/// Each non-empty line holds one directive, `#` and `//` start comment lines.
+pub fn parse_directives(input: &str) -> Vec<(usize, Result<PolicyDirective, PolicyError>)> {
+    input.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line, line.trim()))
+        .filter(|(_, _, directive)| !(directive.is_empty() || directive.starts_with('#') || directive.starts_with("//")))
+        .map(|(number, line, directive)| {
+            let parsed = parse_directive(directive)
+                .map(|(_, directive)| directive)
+                .map_err(|_| describe_error(directive).on_line(number, line));
+
+            (number, parsed)
+        })
+        .collect()
+}
+
+/// Parses a policy file, failing on the first invalid directive.
+pub fn parse_policy(input: &str) -> Result<MergePolicy, PolicyError> {
+    let mut policy = MergePolicy::default();
+
+    for (_, directive) in parse_directives(input) {
+        match directive? {
+            PolicyDirective::Default(strategy) => policy.strategy = Some(strategy),
+            PolicyDirective::Rule(rule) => policy.rules.push(rule),
+            PolicyDirective::Alias(alias) => policy.aliases.push(alias),
+            PolicyDirective::When(rule) => policy.conditions.push(rule),
+            PolicyDirective::Transform(rule) => policy.transforms.push(rule),
+            PolicyDirective::Protect(rule) => policy.protected.push(rule),
+            PolicyDirective::Require(required) => policy.required.push(required),
+            PolicyDirective::Coerce(rule) => policy.coercions.push(rule),
+            PolicyDirective::Allow(rule) => policy.allowed.push(rule),
+            PolicyDirective::Order(rule) => policy.order.push(rule),
+            PolicyDirective::Strip(pattern) => policy.stripped.push(pattern),
+            PolicyDirective::Assert(assertion) => policy.assertions.push(assertion)
+        }
+    }
+
+    Ok(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        let input = r#"
+            # keep local settings unless stated otherwise
+            default keep
+
+            rule graphics override
+            rule controls/hotkeys append
+            rule telemetry delete
+            alias skyQuality -> cloudsQuality
+            when hud if clientType == "32bit"
+            transform rendinstDistMul clamp 0.1 2.0
+            protect devId strict
+            require graphics/cloudsQuality integer
+            coerce graphics/cloudsQuality -> integer
+            allow graphics/shadowQuality {ultralow, low, medium, high} keep
+            order / version *
+            strip telemetry
+            assert graphics/cloudsQuality <= 2
+        "#;
+        let policy = parse_policy(input).unwrap();
+
+        assert_eq!(policy.strategy, Some(MergeStrategy::Keep));
+        assert_eq!(policy.rules, vec![
+            "graphics=override".parse().unwrap(),
+            "controls/hotkeys=append".parse().unwrap(),
+            "telemetry=delete".parse().unwrap()
+        ]);
+        assert_eq!(policy.aliases, vec!["skyQuality=cloudsQuality".parse().unwrap()]);
+        assert_eq!(policy.conditions.len(), 1);
+        assert_eq!(policy.transforms, vec!["rendinstDistMul clamp 0.1 2.0".parse().unwrap()]);
+        assert_eq!(policy.protected, vec!["devId strict".parse().unwrap()]);
+        assert_eq!(policy.required, vec!["graphics/cloudsQuality integer".parse().unwrap()]);
+        assert_eq!(policy.coercions, vec!["graphics/cloudsQuality -> integer".parse().unwrap()]);
+        assert_eq!(policy.allowed, vec!["graphics/shadowQuality {ultralow, low, medium, high} keep".parse().unwrap()]);
+        assert_eq!(policy.order, vec!["/ version".parse().unwrap()]);
+        assert_eq!(policy.stripped, vec!["telemetry".parse().unwrap()]);
+        assert_eq!(policy.assertions, vec!["graphics/cloudsQuality <= 2".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_parse_policy_reports_line() {
+        let err = parse_policy("default keep\nrule graphics sometimes\n").unwrap_err();
+
+        assert_eq!(err.to_string(), "line 2, column 15: unknown merge strategy `sometimes`");
+        assert_eq!((err.kind, err.text.as_str()), (PolicyErrorKind::UnknownStrategy, "sometimes"));
+
+        let err = parse_policy("  transform hud/** clamp 2 1").unwrap_err();
+
+        assert_eq!((err.kind, err.location), (PolicyErrorKind::InvalidTransform, Some((1, 20))));
+        assert_eq!(parse_policy("strip a/**b").unwrap_err().kind, PolicyErrorKind::InvalidPattern);
+        assert_eq!(parse_policy("merge a").unwrap_err().kind, PolicyErrorKind::UnknownDirective);
+    }
+}
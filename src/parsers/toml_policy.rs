@@ -0,0 +1,306 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+use toml::Spanned;
+use crate::{condition::Literal, policy::*};
+
+/// Layout of a TOML policy file, mirroring the directives of the line-based format.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyDocument {
+    default: Option<Spanned<String>>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleTable>,
+    #[serde(default, rename = "alias")]
+    aliases: Vec<AliasTable>,
+    #[serde(default, rename = "when")]
+    conditions: Vec<WhenTable>,
+    #[serde(default, rename = "transform")]
+    transforms: Vec<TransformTable>,
+    #[serde(default, rename = "allow")]
+    allowed: Vec<AllowTable>,
+    #[serde(default, rename = "protect")]
+    protected: Vec<ProtectTable>,
+    #[serde(default, rename = "require")]
+    required: Vec<RequireTable>,
+    #[serde(default, rename = "coerce")]
+    coercions: Vec<CoerceTable>,
+    #[serde(default)]
+    order: Vec<OrderTable>,
+    #[serde(default)]
+    strip: Vec<Spanned<String>>,
+    #[serde(default, rename = "assert")]
+    assertions: Vec<Spanned<String>>
+}
+
+/// A `[[rule]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleTable {
+    path: Spanned<String>,
+    strategy: Spanned<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(rename = "if")]
+    condition: Option<Spanned<String>>
+}
+
+/// An `[[alias]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AliasTable {
+    from: Spanned<String>,
+    to: String
+}
+
+/// A `[[when]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WhenTable {
+    path: Spanned<String>,
+    #[serde(rename = "if")]
+    condition: Spanned<String>
+}
+
+/// A `[[transform]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformTable {
+    path: Spanned<String>,
+    apply: Spanned<String>
+}
+
+/// An `[[allow]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllowTable {
+    path: Spanned<String>,
+    values: Vec<Spanned<toml::Value>>,
+    otherwise: Option<Spanned<String>>
+}
+
+/// A `[[protect]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProtectTable {
+    path: Spanned<String>,
+    #[serde(default)]
+    strict: bool
+}
+
+/// A `[[require]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequireTable {
+    path: Spanned<String>,
+    #[serde(rename = "type")]
+    ty: Option<Spanned<String>>
+}
+
+/// A `[[coerce]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CoerceTable {
+    path: Spanned<String>,
+    #[serde(rename = "type")]
+    ty: Spanned<String>
+}
+
+/// An `[[order]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrderTable {
+    section: Spanned<String>,
+    entries: Vec<String>
+}
+
+/// Parses a string of the document, locating errors at the string.
+fn convert<T: FromStr<Err = String>>(input: &str, value: &Spanned<String>, kind: PolicyErrorKind) -> Result<T, PolicyError> {
+    value.get_ref().parse()
+        .map_err(|message| PolicyError::new(kind, value.get_ref(), message).at(input, value.span().start))
+}
+
+/// Converts a TOML value listed in an `[[allow]]` table to a literal.
+fn literal(input: &str, value: &Spanned<toml::Value>) -> Result<Literal, PolicyError> {
+    match value.get_ref() {
+        toml::Value::String(text) => Ok(Literal::Text(text.clone())),
+        toml::Value::Integer(integer) => Ok(Literal::Number(*integer as f64)),
+        toml::Value::Float(real) => Ok(Literal::Number(*real)),
+        toml::Value::Boolean(boolean) => Ok(Literal::Boolean(*boolean)),
+        other => Err(PolicyError::new(
+            PolicyErrorKind::InvalidValue,
+            &other.to_string(),
+            format!("allowed values must be strings, numbers or booleans, got {}", other)
+        ).at(input, value.span().start))
+    }
+}
+
+/// Parses a policy written in TOML:
+///
+/// ```toml
+/// default = "keep"
+/// strip = ["telemetry"]
+/// assert = ['graphics/shadowQuality == "medium"']
+///
+/// [[rule]]
+/// path = "controls/hotkeys"
+/// strategy = "append"
+///
+/// [[alias]]
+/// from = "skyQuality"
+/// to = "cloudsQuality"
+///
+/// [[when]]
+/// path = "hud"
+/// if = 'clientType == "32bit"'
+///
+/// [[transform]]
+/// path = "rendinstDistMul"
+/// apply = "clamp 0.1 2.0"
+///
+/// [[allow]]
+/// path = "graphics/shadowQuality"
+/// values = ["ultralow", "low", "medium", "high"]
+/// otherwise = "keep"
+///
+/// [[protect]]
+/// path = "devId"
+/// strict = true
+///
+/// [[require]]
+/// path = "graphics/cloudsQuality"
+/// type = "integer"
+///
+/// [[coerce]]
+/// path = "graphics/cloudsQuality"
+/// type = "integer"
+///
+/// [[order]]
+/// section = "/"
+/// entries = ["version", "*"]
+/// ```
+pub fn parse_policy(input: &str) -> Result<MergePolicy, PolicyError> {
+    use PolicyErrorKind::*;
+
+    let document: PolicyDocument = toml::from_str(input).map_err(|err| {
+        let span = err.span().unwrap_or_default();
+        let error = PolicyError::new(Syntax, input.get(span.clone()).unwrap_or_default(), err.message());
+
+        if err.span().is_some() { error.at(input, span.start) } else { error }
+    })?;
+
+    let rules = document.rules.iter()
+        .map(|rule| Ok(PolicyRule {
+            pattern: convert(input, &rule.path, InvalidPattern)?,
+            strategy: convert(input, &rule.strategy, UnknownStrategy)?,
+            priority: rule.priority,
+            condition: rule.condition.as_ref().map(|condition| convert(input, condition, InvalidCondition)).transpose()?
+        }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let aliases = document.aliases.iter()
+        .map(|alias| format!("{}={}", alias.from.get_ref(), alias.to).parse()
+            .map_err(|message| PolicyError::new(InvalidValue, alias.from.get_ref(), message).at(input, alias.from.span().start)))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let conditions = document.conditions.iter()
+        .map(|rule| Ok(ConditionalRule {
+            pattern: convert(input, &rule.path, InvalidPattern)?,
+            condition: convert(input, &rule.condition, InvalidCondition)?
+        }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let transforms = document.transforms.iter()
+        .map(|rule| Ok(TransformRule {
+            pattern: convert(input, &rule.path, InvalidPattern)?,
+            transform: convert(input, &rule.apply, InvalidTransform)?
+        }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let allowed = document.allowed.iter()
+        .map(|rule| Ok(AllowRule {
+            pattern: convert(input, &rule.path, InvalidPattern)?,
+            values: rule.values.iter().map(|value| literal(input, value)).collect::<Result<_, PolicyError>>()?,
+            otherwise: rule.otherwise.as_ref().map(|otherwise| convert(input, otherwise, InvalidValue)).transpose()?.unwrap_or_default()
+        }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let protected = document.protected.iter()
+        .map(|rule| Ok(ProtectRule { pattern: convert(input, &rule.path, InvalidPattern)?, strict: rule.strict }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let required = document.required.iter()
+        .map(|required| Ok(RequiredKey {
+            ty: required.ty.as_ref().map(|ty| convert(input, ty, InvalidValue)).transpose()?,
+            ..convert(input, &required.path, InvalidValue)?
+        }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let coercions = document.coercions.iter()
+        .map(|rule| Ok(CoerceRule { pattern: convert(input, &rule.path, InvalidPattern)?, ty: convert(input, &rule.ty, InvalidValue)? }))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let order = document.order.iter()
+        .map(|rule| format!("{} {}", rule.section.get_ref(), rule.entries.join(" ")).parse()
+            .map_err(|message| PolicyError::new(InvalidValue, rule.section.get_ref(), message).at(input, rule.section.span().start)))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let stripped = document.strip.iter()
+        .map(|pattern| convert(input, pattern, InvalidPattern))
+        .collect::<Result<_, PolicyError>>()?;
+
+    let assertions = document.assertions.iter()
+        .map(|assertion| convert(input, assertion, InvalidCondition))
+        .collect::<Result<_, PolicyError>>()?;
+
+    Ok(MergePolicy {
+        strategy: document.default.as_ref().map(|strategy| convert(input, strategy, UnknownStrategy)).transpose()?,
+        rules,
+        aliases,
+        conditions,
+        coercions,
+        transforms,
+        allowed,
+        protected,
+        required,
+        assertions,
+        order,
+        stripped
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::MergeStrategy;
+
+    #[test]
+    fn test_parse_toml_policy() {
+        let input = r#"
+            default = "keep"
+
+            [[rule]]
+            path = "controls/hotkeys"
+            strategy = "append"
+
+            [[alias]]
+            from = "skyQuality"
+            to = "cloudsQuality"
+
+            [[when]]
+            path = "hud"
+            if = 'clientType == "32bit"'
+        "#;
+        let policy = parse_policy(input).unwrap();
+
+        assert_eq!(policy.strategy, Some(MergeStrategy::Keep));
+        assert_eq!(policy.rules, vec!["controls/hotkeys=append".parse().unwrap()]);
+        assert_eq!(policy.aliases, vec!["skyQuality=cloudsQuality".parse().unwrap()]);
+        assert_eq!(policy.conditions.len(), 1);
+
+        let err = parse_policy("[[rule]]\npath = \"a\"\nstrategy = \"sometimes\"").unwrap_err();
+
+        assert_eq!(err.to_string(), "line 3, column 12: unknown merge strategy `sometimes`");
+        assert_eq!(err.kind, PolicyErrorKind::UnknownStrategy);
+    }
+}
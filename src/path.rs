@@ -0,0 +1,208 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::{Mutex, OnceLock}};
+
+use regex::Regex;
+
+/// A single segment of a path pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// Matches one path segment, `*` and `?` act as wildcards within it.
+    Glob(String),
+    /// `**`, matches any number of segments including none.
+    Any
+}
+
+impl Segment {
+    /// Returns true if the segment contains no wildcards.
+    fn is_literal(&self) -> bool {
+        matches!(self, Segment::Glob(glob) if !glob.contains(['*', '?']))
+    }
+}
+
+/// Matches a single path segment against a glob with `*` and `?` wildcards.
+fn glob_matches(glob: &[char], text: &[char]) -> bool {
+    match glob.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| glob_matches(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && glob_matches(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob_matches(rest, &text[1..])
+    }
+}
+
+/// Matches a whole path against pattern segments.
+fn segments_match(pattern: &[Segment], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Segment::Any, rest)) => (0..=path.len()).any(|skip| segments_match(rest, &path[skip..])),
+        Some((Segment::Glob(glob), rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                let glob: Vec<char> = glob.chars().collect();
+                let segment: Vec<char> = segment.chars().collect();
+
+                glob_matches(&glob, &segment) && segments_match(rest, path_rest)
+            },
+            None => false
+        }
+    }
+}
+
+/// Compiles a regex, reusing earlier compilations of the same expression.
+fn compile_regex(expression: &str) -> Result<Regex, regex::Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap_or_else(|err| err.into_inner());
+
+    if let Some(regex) = cache.get(expression) {
+        return Ok(regex.clone());
+    }
+
+    let regex = Regex::new(expression)?;
+    cache.insert(expression.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// A pattern matched against `/`-separated paths of sections and keys.
+///
+/// A pattern starting with `/` is anchored at the root of the config and must match the
+/// whole path. Otherwise it matches the trailing segments of a path, so `hotkeys` matches
+/// both `hotkeys` and `controls/hotkeys`. Segments may contain `*` and `?` wildcards, and
+/// a `**` segment matches any number of segments, e.g. `hotkeys/**` or `deviceMapping/*/devId`.
+///
+/// A pattern written as `regex:<expression>` instead matches the last segment of a path
+/// against a regular expression, e.g. `regex:^ID_ACTION_BAR_ITEM_\d+$`.
+#[derive(Debug, Clone)]
+pub enum PathPattern {
+    Glob { anchored: bool, segments: Vec<Segment> },
+    Regex(Regex)
+}
+
+impl PartialEq for PathPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PathPattern::Glob { anchored: a, segments: x }, PathPattern::Glob { anchored: b, segments: y }) => a == b && x == y,
+            (PathPattern::Regex(a), PathPattern::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false
+        }
+    }
+}
+
+impl PathPattern {
+    /// Returns true if the pattern matches the given path.
+    pub fn matches(&self, path: &[String]) -> bool {
+        match self {
+            PathPattern::Glob { anchored: true, segments } => segments_match(segments, path),
+            PathPattern::Glob { anchored: false, segments } =>
+                (0..=path.len()).any(|start| segments_match(segments, &path[start..])),
+            PathPattern::Regex(regex) => path.last().is_some_and(|name| regex.is_match(name))
+        }
+    }
+
+    /// Rough measure of how specific the pattern is, used to prefer more specific patterns.
+    ///
+    /// Literal segments count twice as much as wildcard segments, `**` does not count.
+    /// A regex counts as a single wildcard segment.
+    pub fn specificity(&self) -> usize {
+        let PathPattern::Glob { segments, .. } = self else {
+            return 1;
+        };
+
+        segments.iter()
+            .map(|segment| match segment {
+                _ if segment.is_literal() => 2,
+                Segment::Glob(_) => 1,
+                Segment::Any => 0
+            })
+            .sum()
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(expression) = s.strip_prefix("regex:") {
+            return compile_regex(expression)
+                .map(PathPattern::Regex)
+                .map_err(|err| format!("invalid regex `{}`: {}", expression, err));
+        }
+
+        let anchored = s.starts_with('/');
+        let mut segments = Vec::new();
+
+        for segment in s.trim_start_matches('/').split('/') {
+            match segment {
+                "" => return Err(format!("path pattern `{}` contains an empty segment", s)),
+                "**" => segments.push(Segment::Any),
+                _ if segment.contains("**") => return Err(format!("`**` must be a whole segment in path pattern `{}`", s)),
+                _ => segments.push(Segment::Glob(segment.to_string()))
+            }
+        }
+
+        Ok(PathPattern::Glob { anchored, segments })
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (anchored, segments) = match self {
+            PathPattern::Glob { anchored, segments } => (*anchored, segments),
+            PathPattern::Regex(regex) => return write!(f, "regex:{}", regex.as_str())
+        };
+
+        if anchored {
+            write!(f, "/")?;
+        }
+
+        let segments: Vec<&str> = segments.iter()
+            .map(|segment| match segment {
+                Segment::Glob(glob) => glob.as_str(),
+                Segment::Any => "**"
+            })
+            .collect();
+
+        write!(f, "{}", segments.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('/').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_literal_patterns() {
+        let pattern: PathPattern = "hotkeys".parse().unwrap();
+
+        assert!(pattern.matches(&path("hotkeys")));
+        assert!(pattern.matches(&path("controls/hotkeys")));
+        assert!(!pattern.matches(&path("controls/hotkeys/ID_AAM")));
+        assert!(!"/hotkeys".parse::<PathPattern>().unwrap().matches(&path("controls/hotkeys")));
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let subtree: PathPattern = "hotkeys/**".parse().unwrap();
+        let children: PathPattern = "deviceMapping/joystick/*".parse().unwrap();
+        let prefix: PathPattern = "ID_ACTION_BAR_ITEM_?".parse().unwrap();
+
+        assert!(subtree.matches(&path("controls/hotkeys")));
+        assert!(subtree.matches(&path("controls/hotkeys/ID_AAM/mouseButton")));
+        assert!(children.matches(&path("controls/deviceMapping/joystick/devId")));
+        assert!(!children.matches(&path("controls/deviceMapping/joystick")));
+        assert!(prefix.matches(&path("hotkeys/ID_ACTION_BAR_ITEM_1")));
+        assert!(!prefix.matches(&path("hotkeys/ID_ACTION_BAR_ITEM_10")));
+        assert!(children.specificity() > subtree.specificity());
+    }
+
+    #[test]
+    fn test_regex_patterns() {
+        let pattern: PathPattern = r"regex:^ID_ACTION_BAR_ITEM_\d+$".parse().unwrap();
+
+        assert!(pattern.matches(&path("controls/hotkeys/ID_ACTION_BAR_ITEM_12")));
+        assert!(!pattern.matches(&path("controls/hotkeys/ID_ACTION_BAR_ITEM_12/keyboardKey")));
+        assert!("regex:(".parse::<PathPattern>().is_err());
+        assert_eq!(pattern.to_string(), r"regex:^ID_ACTION_BAR_ITEM_\d+$");
+    }
+}
@@ -0,0 +1,1186 @@
+use std::{cmp::Reverse, fmt, path::{Path, PathBuf}, str::FromStr};
+
+use crate::{condition::{Condition, Literal}, merge::MergeStrategy, parsers::{self, pol::PolicyDirective}, path::{PathPattern, Segment}, transform::Transform, types::{BlkConfig, BlkEntry, BlkPropertyValue, BlkType}};
+
+/// Binds a merge strategy to every entry under the sections matching a path pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyRule {
+    pub pattern: PathPattern,
+    pub strategy: MergeStrategy,
+    /// Rules with a higher priority win over any rule with a lower one, regardless of depth
+    /// and specificity.
+    pub priority: i32,
+    /// The rule only applies while this condition holds on the original base.
+    pub condition: Option<Condition>
+}
+
+impl FromStr for PolicyRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern=strategy`, optionally followed by `if condition`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rule, condition) = match s.split_once(" if ") {
+            Some((rule, condition)) => (rule, Some(condition.parse()?)),
+            None => (s, None)
+        };
+
+        let (pattern, strategy) = rule.split_once('=')
+            .ok_or_else(|| format!("expected `path=strategy`, got `{}`", s))?;
+
+        Ok(PolicyRule { pattern: pattern.trim().parse()?, strategy: strategy.trim().parse()?, priority: 0, condition })
+    }
+}
+
+/// Applies overlay entries matching a path pattern only when a condition holds on the base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionalRule {
+    pub pattern: PathPattern,
+    pub condition: Condition
+}
+
+impl FromStr for ConditionalRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern if condition`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, condition) = s.split_once(" if ")
+            .ok_or_else(|| format!("expected `path if condition`, got `{}`", s))?;
+
+        Ok(ConditionalRule { pattern: pattern.trim().parse()?, condition: condition.parse()? })
+    }
+}
+
+/// Transforms overlay values at paths matching a pattern before they are merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformRule {
+    pub pattern: PathPattern,
+    pub transform: Transform
+}
+
+impl FromStr for TransformRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern transform`, e.g. `rendinstDistMul clamp 0.1 2.0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, transform) = s.trim().split_once(char::is_whitespace)
+            .ok_or_else(|| format!("expected `path transform`, got `{}`", s))?;
+
+        Ok(TransformRule { pattern: pattern.parse()?, transform: transform.parse()? })
+    }
+}
+
+/// Marks entries at paths matching a pattern, and everything below them, as protected.
+///
+/// Overlays can never change or remove protected base entries, nor add new entries below a
+/// protected path, whatever the merge strategy. A strict rule fails the merge when an overlay
+/// attempts it, otherwise the overlay entry is skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtectRule {
+    pub pattern: PathPattern,
+    pub strict: bool
+}
+
+impl FromStr for ProtectRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern` or `pattern strict`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [pattern] => Ok(ProtectRule { pattern: pattern.parse()?, strict: false }),
+            [pattern, "strict"] => Ok(ProtectRule { pattern: pattern.parse()?, strict: true }),
+            _ => Err(format!("expected `path` or `path strict`, got `{}`", s))
+        }
+    }
+}
+
+/// Requires a property or section to exist in the merged config, optionally with a given type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredKey {
+    /// Literal `/`-separated path from the root of the config.
+    pub path: Vec<String>,
+    /// Type a property must have, any entry is accepted if unset.
+    pub ty: Option<BlkType>
+}
+
+impl RequiredKey {
+    /// Checks the config, describing the problem if the requirement is not met.
+    pub fn check(&self, config: &BlkConfig) -> Option<String> {
+        let path = self.path.join("/");
+
+        match (config.find(&self.path), self.ty) {
+            (None, _) => Some(format!("`{}` is missing", path)),
+            (Some(_), None) => None,
+            (Some(BlkEntry::Property(property)), Some(ty)) if property.value.ty() == ty => None,
+            (Some(BlkEntry::Property(property)), Some(ty)) =>
+                Some(format!("`{}` is {} {}, expected {}", path, article(property.value.ty()), property.value.ty(), ty)),
+            (Some(BlkEntry::Section(_)), Some(ty)) => Some(format!("`{}` is a section, expected {} {}", path, article(ty), ty)),
+            (Some(BlkEntry::Conflict(_)), Some(_)) => Some(format!("`{}` is an unresolved conflict", path)),
+            (Some(BlkEntry::Include(_)), Some(_)) => Some(format!("`{}` is an unresolved include", path))
+        }
+    }
+}
+
+/// Returns the indefinite article for a type name.
+fn article(ty: BlkType) -> &'static str {
+    if ty.name().starts_with(['a', 'e', 'i', 'o', 'u']) { "an" } else { "a" }
+}
+
+impl FromStr for RequiredKey {
+    type Err = String;
+
+    /// Parses a requirement written as `path` or `path type`, e.g. `graphics/cloudsQuality integer`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, ty) = match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [path] => (*path, None),
+            [path, ty] => (*path, Some(ty.parse()?)),
+            _ => return Err(format!("expected `path` or `path type`, got `{}`", s))
+        };
+
+        let path: Vec<String> = path.trim_start_matches('/').split('/').map(str::to_string).collect();
+
+        if path.iter().any(String::is_empty) {
+            return Err(format!("required path `{}` contains an empty segment", s));
+        }
+
+        Ok(RequiredKey { path, ty })
+    }
+}
+
+/// A condition the merged config must satisfy, e.g. `graphics/shadowQuality == "medium"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    /// The condition as written in the policy, used in failure messages.
+    pub source: String,
+    pub condition: Condition
+}
+
+impl Assertion {
+    /// Checks the config, describing the failure if the condition does not hold.
+    pub fn check(&self, config: &BlkConfig) -> Option<String> {
+        (!self.condition.evaluate(config)).then(|| format!("assertion `{}` failed", self.source))
+    }
+}
+
+impl FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Assertion { source: s.trim().to_string(), condition: s.parse()? })
+    }
+}
+
+/// Forces overlay values at paths matching a pattern to a BLK type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoerceRule {
+    pub pattern: PathPattern,
+    pub ty: BlkType
+}
+
+impl FromStr for CoerceRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern -> type`, e.g. `graphics/cloudsQuality -> integer`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, ty) = s.split_once("->")
+            .ok_or_else(|| format!("expected `path -> type`, got `{}`", s))?;
+
+        Ok(CoerceRule { pattern: pattern.trim().parse()?, ty: ty.trim().parse()? })
+    }
+}
+
+/// How an overlay value outside the set of an [`AllowRule`] is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Disallowed {
+    /// Fail the merge.
+    #[default]
+    Reject,
+    /// Replace a number by the closest allowed number, other values are rejected.
+    Nearest,
+    /// Skip the overlay value, leaving the base untouched.
+    Keep
+}
+
+impl FromStr for Disallowed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Disallowed::Reject),
+            "nearest" => Ok(Disallowed::Nearest),
+            "keep" => Ok(Disallowed::Keep),
+            _ => Err(format!("expected `reject`, `nearest` or `keep`, got `{}`", s))
+        }
+    }
+}
+
+/// Restricts overlay values at paths matching a pattern to a set of allowed values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowRule {
+    pub pattern: PathPattern,
+    pub values: Vec<Literal>,
+    pub otherwise: Disallowed
+}
+
+impl AllowRule {
+    /// Returns true if the value is one of the allowed values.
+    pub fn permits(&self, value: &BlkPropertyValue) -> bool {
+        self.values.iter().any(|literal| literal.matches(value))
+    }
+
+    /// Finds the allowed number closest to a numeric value. Integers stay integers if the
+    /// closest number is integral.
+    pub fn nearest(&self, value: &BlkPropertyValue) -> Option<BlkPropertyValue> {
+        let number = match value {
+            BlkPropertyValue::Integer(integer) => f64::from(*integer),
+            BlkPropertyValue::Long(long) => *long as f64,
+            BlkPropertyValue::Real(real) => f64::from(*real),
+            _ => return None
+        };
+
+        let nearest = self.values.iter()
+            .filter_map(|literal| match literal {
+                Literal::Number(allowed) => Some(*allowed),
+                _ => None
+            })
+            .min_by(|a, b| (a - number).abs().total_cmp(&(b - number).abs()))?;
+
+        Some(match value {
+            BlkPropertyValue::Integer(_) if nearest.fract() == 0.0 => BlkPropertyValue::Integer(nearest as i32),
+            BlkPropertyValue::Long(_) if nearest.fract() == 0.0 => BlkPropertyValue::Long(nearest as i64),
+            _ => BlkPropertyValue::Real(nearest as f32)
+        })
+    }
+
+    /// Lists the allowed values, e.g. `{"low", "high"}`.
+    pub fn describe_values(&self) -> String {
+        let values: Vec<String> = self.values.iter().map(Literal::to_string).collect();
+        format!("{{{}}}", values.join(", "))
+    }
+}
+
+impl FromStr for AllowRule {
+    type Err = String;
+
+    /// Parses a rule written as `pattern {value, ...}`, optionally followed by `reject`,
+    /// `nearest` or `keep`, e.g. `graphics/shadowQuality {ultralow, low, medium, high} keep`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, values, otherwise) = s.split_once('{')
+            .and_then(|(pattern, rest)| rest.split_once('}').map(|(values, otherwise)| (pattern, values, otherwise)))
+            .ok_or_else(|| format!("expected `path {{value, ...}}`, got `{}`", s))?;
+
+        let values = values.split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Literal>, String>>()?;
+
+        if values.is_empty() {
+            return Err(format!("`{}` does not allow any value", pattern.trim()));
+        }
+
+        let otherwise = match otherwise.trim() {
+            "" => Disallowed::default(),
+            otherwise => otherwise.parse()?
+        };
+
+        Ok(AllowRule { pattern: pattern.trim().parse()?, values, otherwise })
+    }
+}
+
+/// Orders the entries of sections matching a pattern in the serialized output.
+///
+/// Listed names come first in the given order. A `*` stands for all unlisted entries, so
+/// names after it are moved to the end. Unlisted entries keep their relative order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRule {
+    /// Sections whose entries are ordered, `None` for the top level.
+    pub section: Option<PathPattern>,
+    pub first: Vec<String>,
+    pub last: Vec<String>
+}
+
+impl OrderRule {
+    /// Returns true if the rule applies to the section at the path, the empty path being the top level.
+    pub fn applies_to(&self, path: &[String]) -> bool {
+        match &self.section {
+            Some(pattern) => !path.is_empty() && pattern.matches(path),
+            None => path.is_empty()
+        }
+    }
+
+    /// Sort key of an entry name, unlisted entries sort between the first and last names.
+    pub fn rank(&self, name: &str) -> (usize, usize) {
+        if let Some(index) = self.first.iter().position(|first| first == name) {
+            (0, index)
+        } else if let Some(index) = self.last.iter().position(|last| last == name) {
+            (2, index)
+        } else {
+            (1, 0)
+        }
+    }
+}
+
+impl FromStr for OrderRule {
+    type Err = String;
+
+    /// Parses a rule written as `section name...`, `/` being the top level, e.g. `/ version *`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+
+        let section = match words.next() {
+            Some("/") => None,
+            Some(pattern) => Some(pattern.parse()?),
+            None => return Err("expected `section name...`, got nothing".to_string())
+        };
+
+        let names: Vec<&str> = words.collect();
+
+        if names.is_empty() {
+            return Err(format!("order `{}` lists no entries", s));
+        }
+
+        let (first, last) = match names.iter().position(|name| *name == "*") {
+            Some(index) => (&names[..index], &names[index + 1..]),
+            None => (&names[..], &[][..])
+        };
+
+        if last.contains(&"*") {
+            return Err(format!("order `{}` contains more than one `*`", s));
+        }
+
+        Ok(OrderRule {
+            section,
+            first: first.iter().map(|name| name.to_string()).collect(),
+            last: last.iter().map(|name| name.to_string()).collect()
+        })
+    }
+}
+
+/// Maps a key in the overlay onto a differently named key in the base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyAlias {
+    pub from: String,
+    pub to: String
+}
+
+impl FromStr for KeyAlias {
+    type Err = String;
+
+    /// Parses an alias written as `from=to`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once('=')
+            .ok_or_else(|| format!("expected `from=to`, got `{}`", s))?;
+
+        let (from, to) = (from.trim(), to.trim());
+
+        if from.is_empty() || to.is_empty() {
+            return Err(format!("alias `{}` has an empty side", s));
+        }
+
+        Ok(KeyAlias { from: from.to_string(), to: to.to_string() })
+    }
+}
+
+/// A value substituted for `${name}` placeholders in policy files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyVariable {
+    pub name: String,
+    pub value: String
+}
+
+impl FromStr for PolicyVariable {
+    type Err = String;
+
+    /// Parses a variable written as `name=value`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=')
+            .ok_or_else(|| format!("expected `name=value`, got `{}`", s))?;
+
+        if name.trim().is_empty() {
+            return Err(format!("variable `{}` has no name", s));
+        }
+
+        Ok(PolicyVariable { name: name.trim().to_string(), value: value.to_string() })
+    }
+}
+
+/// Replaces every `${name}` placeholder in a policy with the value of the variable, later
+/// variables win. Placeholders of undefined variables are errors.
+pub fn substitute_variables(input: &str, variables: &[PolicyVariable]) -> Result<String, PolicyError> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${") {
+        let offset = input.len() - rest.len() + start;
+
+        let Some((name, after)) = rest[start + 2..].split_once('}').filter(|(name, _)| !name.contains('\n')) else {
+            let text = rest[start..].lines().next().unwrap_or_default();
+            return Err(PolicyError::new(PolicyErrorKind::Syntax, text, format!("unterminated placeholder `{}`", text)).at(input, offset));
+        };
+
+        let Some(variable) = variables.iter().rev().find(|variable| variable.name == name) else {
+            let text = &rest[start..start + name.len() + 3];
+            return Err(PolicyError::new(PolicyErrorKind::UndefinedVariable, text, format!("undefined policy variable `{}`", name)).at(input, offset));
+        };
+
+        output.push_str(&rest[..start]);
+        output.push_str(&variable.value);
+        rest = after;
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// What is wrong with a policy, see [`PolicyError`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyErrorKind {
+    /// The policy file cannot be read.
+    Io,
+    /// The policy is not valid in its format, e.g. broken TOML or a malformed directive.
+    Syntax,
+    UnknownDirective,
+    UnknownStrategy,
+    InvalidPattern,
+    InvalidCondition,
+    InvalidTransform,
+    UndefinedVariable,
+    /// Any other invalid value, e.g. an unknown type name or a bad priority.
+    InvalidValue
+}
+
+/// An error in a policy, pointing at the offending text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyError {
+    pub kind: PolicyErrorKind,
+    pub message: String,
+    /// The offending text as written in the policy.
+    pub text: String,
+    /// 1-based line and column of the offending text, `None` if unknown.
+    pub location: Option<(usize, usize)>,
+    /// The policy file, `None` for policies not read from a file.
+    pub file: Option<PathBuf>
+}
+
+impl PolicyError {
+    pub fn new(kind: PolicyErrorKind, text: &str, message: impl Into<String>) -> PolicyError {
+        PolicyError { kind, message: message.into(), text: text.to_string(), location: None, file: None }
+    }
+
+    /// Locates the error at a byte offset of the policy.
+    pub fn at(mut self, input: &str, offset: usize) -> PolicyError {
+        let before = &input[..offset.min(input.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        self.location = Some((before.matches('\n').count() + 1, before[line_start..].chars().count() + 1));
+        self
+    }
+
+    /// Locates the error on a line of the policy, at the first occurrence of the offending text.
+    pub fn on_line(mut self, number: usize, line: &str) -> PolicyError {
+        let column = line.find(&self.text)
+            .or_else(|| line.find(|c: char| !c.is_whitespace()))
+            .map_or(1, |index| line[..index].chars().count() + 1);
+
+        self.location = Some((number, column));
+        self
+    }
+
+    /// Attributes the error to a policy file.
+    pub fn in_file(mut self, path: &Path) -> PolicyError {
+        self.file = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.location) {
+            (Some(file), Some((line, column))) => write!(f, "{}:{}:{}: {}", file.display(), line, column, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file.display(), self.message),
+            (None, Some((line, column))) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            (None, None) => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Policies bundled with the binary for common War Thunder files, selected as `preset:<name>`.
+pub const PRESETS: &[(&str, &str)] = &[
+    ("controls", include_str!("policies/controls.pol")),
+    ("graphics", include_str!("policies/graphics.pol")),
+    ("sights", include_str!("policies/sights.pol"))
+];
+
+/// Syntax a policy file is written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyFormat {
+    /// Line-based directives, see [`parsers::pol`].
+    Pol,
+    /// TOML tables, see [`parsers::toml_policy`].
+    Toml,
+    /// BLK sections and properties, see [`parsers::blk_policy`].
+    Blk
+}
+
+impl PolicyFormat {
+    /// Guesses the format from the file extension, defaulting to the line-based format.
+    pub fn from_path(path: &Path) -> PolicyFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => PolicyFormat::Toml,
+            Some("blk") => PolicyFormat::Blk,
+            _ => PolicyFormat::Pol
+        }
+    }
+
+    /// Parses policy file content written in this format.
+    pub fn parse(self, input: &str) -> Result<MergePolicy, PolicyError> {
+        match self {
+            PolicyFormat::Pol => parsers::pol::parse_policy(input),
+            PolicyFormat::Toml => parsers::toml_policy::parse_policy(input),
+            PolicyFormat::Blk => parsers::blk_policy::parse_policy(input)
+        }
+    }
+}
+
+impl FromStr for PolicyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pol" => Ok(PolicyFormat::Pol),
+            "toml" => Ok(PolicyFormat::Toml),
+            "blk" => Ok(PolicyFormat::Blk),
+            _ => Err(format!("unknown policy format `{}`", s))
+        }
+    }
+}
+
+/// A set of rules the merge engine consults for every key and section decision.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergePolicy {
+    /// Strategy used where no rule matches, `override` if unset.
+    pub strategy: Option<MergeStrategy>,
+    /// Path-specific strategies, the most specific match wins and later rules win ties.
+    pub rules: Vec<PolicyRule>,
+    pub aliases: Vec<KeyAlias>,
+    /// Overlay entries are skipped unless every matching condition holds on the original base.
+    pub conditions: Vec<ConditionalRule>,
+    /// Types overlay values are converted to before transforms apply, later rules win.
+    pub coercions: Vec<CoerceRule>,
+    /// Applied in declaration order to overlay values whose path matches.
+    pub transforms: Vec<TransformRule>,
+    /// Values overlay values are restricted to after transforms apply, later rules win.
+    pub allowed: Vec<AllowRule>,
+    pub protected: Vec<ProtectRule>,
+    /// Entries the merged config must contain.
+    pub required: Vec<RequiredKey>,
+    /// Conditions the merged config must satisfy.
+    pub assertions: Vec<Assertion>,
+    /// Output order of section entries, later rules win.
+    pub order: Vec<OrderRule>,
+    /// Entries removed from the merged config, whatever the inputs contain.
+    pub stripped: Vec<PathPattern>
+}
+
+impl MergePolicy {
+    /// Reads and parses a policy file, choosing the format by its extension.
+    pub fn load(path: &Path, variables: &[PolicyVariable]) -> Result<MergePolicy, PolicyError> {
+        MergePolicy::load_as(path, PolicyFormat::from_path(path), variables)
+    }
+
+    /// Reads and parses a policy file written in the given format, filling in its placeholders.
+    pub fn load_as(path: &Path, format: PolicyFormat, variables: &[PolicyVariable]) -> Result<MergePolicy, PolicyError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| PolicyError::new(PolicyErrorKind::Io, "", format!("failed to read policy: {}", err)).in_file(path))?;
+
+        substitute_variables(&content, variables)
+            .and_then(|content| format.parse(&content))
+            .map_err(|err| err.in_file(path))
+    }
+
+    /// Adds the rules of another policy, which take precedence over the existing ones.
+    pub fn extend(&mut self, other: MergePolicy) {
+        self.strategy = other.strategy.or(self.strategy);
+        self.rules.extend(other.rules);
+        self.aliases.extend(other.aliases);
+        self.conditions.extend(other.conditions);
+        self.coercions.extend(other.coercions);
+        self.transforms.extend(other.transforms);
+        self.allowed.extend(other.allowed);
+        self.protected.extend(other.protected);
+        self.required.extend(other.required);
+        self.assertions.extend(other.assertions);
+        self.order.extend(other.order);
+        self.stripped.extend(other.stripped);
+    }
+
+    /// Finds the alias for an overlay key, later aliases win.
+    pub fn alias_for(&self, name: &str) -> Option<&KeyAlias> {
+        self.aliases.iter().rev().find(|alias| alias.from == name)
+    }
+
+    /// Parses the policy embedded in the binary.
+    pub fn builtin() -> MergePolicy {
+        parsers::pol::parse_policy(include_str!("policies/default.pol"))
+            .expect("built-in policy is valid")
+    }
+
+    /// Parses a bundled preset by name, see [`PRESETS`].
+    pub fn preset(name: &str) -> Result<MergePolicy, String> {
+        let (_, source) = PRESETS.iter().find(|(preset, _)| *preset == name).ok_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|(preset, _)| *preset).collect();
+            format!("unknown policy preset `{}`, available presets: {}", name, names.join(", "))
+        })?;
+
+        Ok(parsers::pol::parse_policy(source).expect("bundled presets are valid"))
+    }
+
+    /// Finds the strategy for an entry at the given path.
+    ///
+    /// Rules apply to the path they match and everything below it. The second value is true
+    /// when the winning rule starts applying at the path itself, i.e. it matches the path but
+    /// none of its ancestors.
+    ///
+    /// Conditional rules are evaluated against `config`, usually the base before merging.
+    pub fn strategy_for(&self, path: &[String], config: &BlkConfig) -> (MergeStrategy, bool) {
+        self.rule_strategy_for(path, config).unwrap_or((self.strategy.unwrap_or_default(), false))
+    }
+
+    /// Lists the rules matching the path or one of its ancestors, the winning rule first.
+    ///
+    /// Each rule is paired with the depth of the deepest ancestor it matches. Rules are ranked
+    /// by priority, then by that depth, then by pattern specificity, and finally later rules
+    /// win over earlier ones. Rules whose condition does not hold on `config` are left out.
+    pub fn matching_rules(&self, path: &[String], config: &BlkConfig) -> Vec<(usize, &PolicyRule)> {
+        let mut matches: Vec<(usize, usize, &PolicyRule)> = self.rules.iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.condition.as_ref().is_none_or(|condition| condition.evaluate(config)))
+            .filter_map(|(index, rule)| {
+                (1..=path.len()).rev()
+                    .find(|&depth| rule.pattern.matches(&path[..depth]))
+                    .map(|depth| (index, depth, rule))
+            })
+            .collect();
+
+        matches.sort_by_key(|&(index, depth, rule)| Reverse((rule.priority, depth, rule.pattern.specificity(), index)));
+        matches.into_iter().map(|(_, depth, rule)| (depth, rule)).collect()
+    }
+
+    /// Like [`MergePolicy::strategy_for`], but returns `None` if no rule matches.
+    fn rule_strategy_for(&self, path: &[String], config: &BlkConfig) -> Option<(MergeStrategy, bool)> {
+        self.matching_rules(path, config).first().map(|&(depth, rule)| {
+            let exact = depth == path.len() && !(1..depth).any(|ancestor| rule.pattern.matches(&path[..ancestor]));
+            (rule.strategy, exact)
+        })
+    }
+
+    /// Returns false if a conditional rule matching the path evaluates to false on the config.
+    pub fn conditions_hold(&self, path: &[String], config: &BlkConfig) -> bool {
+        self.conditions.iter()
+            .filter(|rule| rule.pattern.matches(path))
+            .all(|rule| rule.condition.evaluate(config))
+    }
+
+    /// Finds the order rule for the section at the path.
+    pub fn order_for(&self, path: &[String]) -> Option<&OrderRule> {
+        self.order.iter().rev().find(|rule| rule.applies_to(path))
+    }
+
+    /// Finds the type values at the path are coerced to.
+    pub fn coercion_for(&self, path: &[String]) -> Option<BlkType> {
+        self.coercions.iter().rev().find(|rule| rule.pattern.matches(path)).map(|rule| rule.ty)
+    }
+
+    /// Applies the transforms of all rules matching the path to a value.
+    pub fn transform(&self, path: &[String], value: BlkPropertyValue) -> BlkPropertyValue {
+        self.transforms.iter()
+            .filter(|rule| rule.pattern.matches(path))
+            .fold(value, |value, rule| rule.transform.apply(&value))
+    }
+
+    /// Returns true if the entry at the path is stripped from the merged config.
+    pub fn strips(&self, path: &[String]) -> bool {
+        self.stripped.iter().any(|pattern| pattern.matches(path))
+    }
+
+    /// Finds the rule restricting the values at the path.
+    pub fn allow_for(&self, path: &[String]) -> Option<&AllowRule> {
+        self.allowed.iter().rev().find(|rule| rule.pattern.matches(path))
+    }
+
+    /// Returns whether the path or one of its ancestors is protected, and if so whether strictly.
+    pub fn protection_for(&self, path: &[String]) -> Option<bool> {
+        self.protected.iter()
+            .filter(|rule| (1..=path.len()).any(|depth| rule.pattern.matches(&path[..depth])))
+            .map(|rule| rule.strict)
+            .reduce(|a, b| a || b)
+    }
+}
+
+/// Where a policy in a [`PolicySet`] comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicySource {
+    /// The default policy embedded in the binary.
+    Builtin,
+    /// A bundled preset.
+    Preset(String),
+    /// A policy file.
+    File(PathBuf),
+    /// Rules given on the command line or directly through the library.
+    CommandLine
+}
+
+impl fmt::Display for PolicySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicySource::Builtin => write!(f, "built-in policy"),
+            PolicySource::Preset(name) => write!(f, "preset:{}", name),
+            PolicySource::File(path) => write!(f, "{}", path.display()),
+            PolicySource::CommandLine => write!(f, "command line")
+        }
+    }
+}
+
+/// A policy rule matching a path, as listed by [`PolicySet::explain`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleMatch<'a> {
+    pub source: &'a PolicySource,
+    pub rule: &'a PolicyRule,
+    /// Number of path segments the rule matched, less than the path length for ancestors.
+    pub depth: usize
+}
+
+/// A policy together with its source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyLayer {
+    pub source: PolicySource,
+    pub policy: MergePolicy
+}
+
+/// Layered policies, consulted from the most to the least important one.
+///
+/// The first layer with a rule matching a path decides its strategy, so the usual order is
+/// command line flags over user policy files over the built-in defaults. Aliases resolve the
+/// same way, while the conditions of all layers have to hold.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PolicySet {
+    /// Layers from the least to the most important one.
+    pub layers: Vec<PolicyLayer>
+}
+
+impl PolicySet {
+    /// Adds a policy that takes precedence over all layers added so far.
+    pub fn push(&mut self, source: PolicySource, policy: MergePolicy) {
+        self.layers.push(PolicyLayer { source, policy });
+    }
+
+    /// Strategy used where no rule matches, taken from the most important layer that sets one.
+    pub fn default_strategy(&self) -> MergeStrategy {
+        self.layers.iter().rev().find_map(|layer| layer.policy.strategy).unwrap_or_default()
+    }
+
+    /// Finds the alias for an overlay key.
+    pub fn alias_for(&self, name: &str) -> Option<&KeyAlias> {
+        self.layers.iter().rev().find_map(|layer| layer.policy.alias_for(name))
+    }
+
+    /// Lists every rule matching the path, the winning rule first.
+    ///
+    /// Rules of more important layers come first, and within a layer rules are ordered as
+    /// described in [`MergePolicy::matching_rules`].
+    pub fn explain(&self, path: &[String], config: &BlkConfig) -> Vec<RuleMatch<'_>> {
+        self.layers.iter().rev()
+            .flat_map(|layer| {
+                layer.policy.matching_rules(path, config).into_iter()
+                    .map(|(depth, rule)| RuleMatch { source: &layer.source, rule, depth })
+            })
+            .collect()
+    }
+
+    /// Finds the strategy for an entry at the given path, see [`MergePolicy::strategy_for`].
+    pub fn strategy_for(&self, path: &[String], config: &BlkConfig) -> (MergeStrategy, bool) {
+        self.layers.iter().rev()
+            .find_map(|layer| layer.policy.rule_strategy_for(path, config))
+            .unwrap_or((self.default_strategy(), false))
+    }
+
+    /// Returns true if any layer has conditional rules or strategy rules with a condition.
+    pub fn has_conditions(&self) -> bool {
+        self.layers.iter().any(|layer| {
+            !layer.policy.conditions.is_empty() || layer.policy.rules.iter().any(|rule| rule.condition.is_some())
+        })
+    }
+
+    /// Returns true if any layer has order rules.
+    pub fn has_order(&self) -> bool {
+        self.layers.iter().any(|layer| !layer.policy.order.is_empty())
+    }
+
+    /// Returns true if any layer strips entries from the merged config.
+    pub fn has_strips(&self) -> bool {
+        self.layers.iter().any(|layer| !layer.policy.stripped.is_empty())
+    }
+
+    /// Returns true if any layer strips the entry at the path from the merged config.
+    pub fn strips(&self, path: &[String]) -> bool {
+        self.layers.iter().any(|layer| layer.policy.strips(path))
+    }
+
+    /// Finds the order rule for the section at the path, consulting the most important layer first.
+    pub fn order_for(&self, path: &[String]) -> Option<&OrderRule> {
+        self.layers.iter().rev().find_map(|layer| layer.policy.order_for(path))
+    }
+
+    /// Finds the type values at the path are coerced to, consulting the most important layer first.
+    pub fn coercion_for(&self, path: &[String]) -> Option<BlkType> {
+        self.layers.iter().rev().find_map(|layer| layer.policy.coercion_for(path))
+    }
+
+    /// Applies the transforms of all layers to a value, starting with the least important layer.
+    pub fn transform(&self, path: &[String], value: BlkPropertyValue) -> BlkPropertyValue {
+        self.layers.iter().fold(value, |value, layer| layer.policy.transform(path, value))
+    }
+
+    /// Finds the rule restricting the values at the path, consulting the most important layer first.
+    pub fn allow_for(&self, path: &[String]) -> Option<&AllowRule> {
+        self.layers.iter().rev().find_map(|layer| layer.policy.allow_for(path))
+    }
+
+    /// Returns whether any layer protects the path, and if so whether strictly.
+    pub fn protection_for(&self, path: &[String]) -> Option<bool> {
+        self.layers.iter()
+            .filter_map(|layer| layer.policy.protection_for(path))
+            .reduce(|a, b| a || b)
+    }
+
+    /// Checks the required entries of all layers, describing every requirement that is not met.
+    pub fn check_required(&self, config: &BlkConfig) -> Vec<String> {
+        self.layers.iter()
+            .flat_map(|layer| &layer.policy.required)
+            .filter_map(|required| required.check(config))
+            .collect()
+    }
+
+    /// Checks the assertions of all layers, describing every assertion that fails.
+    pub fn check_assertions(&self, config: &BlkConfig) -> Vec<String> {
+        self.layers.iter()
+            .flat_map(|layer| &layer.policy.assertions)
+            .filter_map(|assertion| assertion.check(config))
+            .collect()
+    }
+
+    /// Returns false if a conditional rule of any layer matching the path evaluates to false.
+    pub fn conditions_hold(&self, path: &[String], config: &BlkConfig) -> bool {
+        self.layers.iter().all(|layer| layer.policy.conditions_hold(path, config))
+    }
+}
+
+impl From<MergePolicy> for PolicySet {
+    fn from(policy: MergePolicy) -> Self {
+        PolicySet { layers: vec![PolicyLayer { source: PolicySource::CommandLine, policy }] }
+    }
+}
+
+/// Returns the entry matching the one at `index` by kind, name and occurrence, like the merge engine pairs them.
+fn counterpart<'a>(entries: &'a [BlkEntry], others: &[BlkEntry], index: usize) -> Option<&'a BlkEntry> {
+    let entry = &others[index];
+    let same = |candidate: &&BlkEntry| candidate.same_kind(entry) && candidate.name() == entry.name();
+    let occurrence = others[..index].iter().filter(same).count();
+
+    entries.iter().filter(same).nth(occurrence)
+}
+
+/// Adds a path to the list unless it is already there.
+fn record_difference(differences: &mut Vec<Vec<String>>, path: &[String], name: &str) {
+    let path = [path, &[name.to_string()]].concat();
+
+    if !differences.contains(&path) {
+        differences.push(path);
+    }
+}
+
+/// Collects the paths where two entry lists differ.
+fn collect_differences(base: &[BlkEntry], overlay: &[BlkEntry], path: &mut Vec<String>, differences: &mut Vec<Vec<String>>) {
+    for (index, entry) in overlay.iter().enumerate() {
+        match (entry, counterpart(base, overlay, index)) {
+            (BlkEntry::Section(overlay_section), Some(BlkEntry::Section(base_section))) => {
+                path.push(overlay_section.name.clone());
+                collect_differences(&base_section.entries, &overlay_section.entries, path, differences);
+                path.pop();
+            },
+            (_, Some(counterpart)) if counterpart == entry => {},
+            _ => record_difference(differences, path, entry.name())
+        }
+    }
+
+    for (index, entry) in base.iter().enumerate() {
+        if counterpart(overlay, base, index).is_none() {
+            record_difference(differences, path, entry.name());
+        }
+    }
+}
+
+/// Lists the segments of the paths where two configs differ.
+fn differing_segments(base: &BlkConfig, overlay: &BlkConfig) -> Vec<Vec<String>> {
+    let mut differences = Vec::new();
+    collect_differences(&base.block.entries, &overlay.block.entries, &mut Vec::new(), &mut differences);
+    differences
+}
+
+/// Lists the anchored paths where two configs differ.
+///
+/// Sections present in both are compared entry by entry, while a section only one config
+/// has is reported as a whole.
+pub fn differing_paths(base: &BlkConfig, overlay: &BlkConfig) -> Vec<String> {
+    differing_segments(base, overlay).into_iter()
+        .map(|segments| format!("/{}", segments.join("/")))
+        .collect()
+}
+
+/// Returns true if a name can be written as a literal segment of a path pattern in a policy file.
+fn is_literal_segment(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || matches!(c, '/' | '*' | '?'))
+}
+
+/// Builds a skeleton policy with an `override` rule for every path where two configs differ.
+///
+/// Paths with a name that cannot be written as a literal pattern segment, e.g. an empty one or
+/// one with spaces or wildcards, get no rule and are returned alongside the policy instead.
+pub fn starter_policy(base: &BlkConfig, overlay: &BlkConfig) -> (MergePolicy, Vec<String>) {
+    let mut rules = Vec::new();
+    let mut skipped = Vec::new();
+
+    for path in differing_segments(base, overlay) {
+        if !path.iter().all(|name| is_literal_segment(name)) {
+            skipped.push(format!("/{}", path.join("/")));
+            continue;
+        }
+
+        let pattern = PathPattern::Glob { anchored: true, segments: path.into_iter().map(Segment::Glob).collect() };
+        rules.push(PolicyRule { pattern, strategy: MergeStrategy::Override, priority: 0, condition: None });
+    }
+
+    (MergePolicy { rules, ..Default::default() }, skipped)
+}
+
+/// How serious a policy diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+/// A problem found while validating a policy file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyDiagnostic {
+    /// 1-based line number the diagnostic refers to.
+    pub line: usize,
+    /// 1-based column of the offending text, or of the directive.
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String
+}
+
+/// Returns the literal segments of an unanchored pattern, or `None` if it has wildcards.
+fn literal_segments(pattern: &PathPattern) -> Option<Vec<String>> {
+    let pattern = pattern.to_string();
+
+    if pattern.starts_with('/') || pattern.starts_with("regex:") || pattern.contains(['*', '?']) {
+        return None;
+    }
+
+    Some(pattern.split('/').map(str::to_string).collect())
+}
+
+/// Checks a policy file for syntax errors, unknown strategies, unreachable and conflicting rules.
+pub fn validate_policy(input: &str) -> Vec<PolicyDiagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut rules: Vec<(usize, PolicyRule)> = Vec::new();
+    let mut defaults: Vec<usize> = Vec::new();
+
+    let column = |line: usize| input.lines().nth(line - 1)
+        .and_then(|text| text.find(|c: char| !c.is_whitespace()))
+        .map_or(1, |index| index + 1);
+
+    for (line, directive) in parsers::pol::parse_directives(input) {
+        match directive {
+            Err(err) => diagnostics.push(PolicyDiagnostic {
+                line,
+                column: err.location.map_or(1, |(_, column)| column),
+                severity: Severity::Error,
+                message: err.message
+            }),
+            Ok(PolicyDirective::Rule(rule)) => rules.push((line, rule)),
+            Ok(PolicyDirective::Default(_)) => defaults.push(line),
+            Ok(_) => {}
+        }
+    }
+
+    if let Some((last, earlier)) = defaults.split_last() {
+        for &line in earlier {
+            diagnostics.push(PolicyDiagnostic {
+                line,
+                column: column(line),
+                severity: Severity::Warning,
+                message: format!("default strategy is overridden on line {}", last)
+            });
+        }
+    }
+
+    for (index, (line, rule)) in rules.iter().enumerate() {
+        // a rule loses to an unconditional rule for the same pattern with a higher priority, or the same one declared later
+        let winner = rules.iter().enumerate().find(|(other_index, (_, other))| {
+            other.pattern == rule.pattern
+                && other.condition.is_none()
+                && (other.priority > rule.priority || (other.priority == rule.priority && *other_index > index))
+        });
+
+        if let Some((_, (other_line, other))) = winner {
+            let message = if other.strategy == rule.strategy {
+                format!("rule `{}` is repeated on line {}", rule.pattern, other_line)
+            } else {
+                format!("rule `{}` conflicts with line {} and is never applied", rule.pattern, other_line)
+            };
+
+            diagnostics.push(PolicyDiagnostic { line: *line, column: column(*line), severity: Severity::Warning, message });
+        }
+
+        // a rule below a section that is deleted can never apply
+        let Some(segments) = literal_segments(&rule.pattern) else {
+            continue;
+        };
+
+        let shadowing = rules.iter().find(|(_, other)| {
+            other.strategy == MergeStrategy::Delete
+                && other.condition.is_none()
+                && literal_segments(&other.pattern).is_some_and(|prefix| prefix.len() < segments.len() && segments.starts_with(&prefix))
+        });
+
+        if let Some((other_line, other)) = shadowing {
+            diagnostics.push(PolicyDiagnostic {
+                line: *line,
+                column: column(*line),
+                severity: Severity::Warning,
+                message: format!("rule `{}` is unreachable, `{}` is deleted on line {}", rule.pattern, other.pattern, other_line)
+            });
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_set_precedence() {
+        let mut policies = PolicySet::default();
+        policies.push(PolicySource::Builtin, MergePolicy::builtin());
+        policies.push(PolicySource::File("user.pol".into()), parsers::pol::parse_policy("default keep\nrule graphics/** error").unwrap());
+        policies.push(PolicySource::CommandLine, "graphics=override".parse::<PolicyRule>().map(|rule| MergePolicy { rules: vec![rule], ..Default::default() }).unwrap());
+
+        let path = |path: &str| path.split('/').map(str::to_string).collect::<Vec<_>>();
+
+        let config = BlkConfig::default();
+
+        assert_eq!(policies.strategy_for(&path("graphics/sky"), &config), (MergeStrategy::Override, false));
+        assert_eq!(policies.strategy_for(&path("controls/deviceMapping/joystick"), &config), (MergeStrategy::Keep, false));
+        assert_eq!(policies.strategy_for(&path("hud"), &config), (MergeStrategy::Keep, false));
+    }
+
+    #[test]
+    fn test_required_keys() {
+        let config = parsers::blk::parse_config("graphics{cloudsQuality:r=1.0;};version:i=2;").unwrap().1;
+        let check = |required: &str| required.parse::<RequiredKey>().unwrap().check(&config);
+
+        assert_eq!(check("version i"), None);
+        assert_eq!(check("graphics"), None);
+        assert_eq!(check("graphics/cloudsQuality integer").unwrap(), "`graphics/cloudsQuality` is a real, expected integer");
+        assert_eq!(check("graphics/skyQuality").unwrap(), "`graphics/skyQuality` is missing");
+        assert!("graphics//sky".parse::<RequiredKey>().is_err());
+    }
+
+    #[test]
+    fn test_substitute_variables() {
+        let variables: Vec<PolicyVariable> = vec!["device=js1".parse().unwrap(), "width=1920".parse().unwrap(), "device=js2".parse().unwrap()];
+
+        assert_eq!(
+            substitute_variables("protect deviceMapping/${device}\nrequire video/${width}x${width}", &variables).unwrap(),
+            "protect deviceMapping/js2\nrequire video/1920x1920"
+        );
+        let err = substitute_variables("default keep\nrule ${height} keep", &variables).unwrap_err();
+
+        assert_eq!((err.kind, err.text.as_str(), err.location), (PolicyErrorKind::UndefinedVariable, "${height}", Some((2, 6))));
+        assert!(substitute_variables("rule ${width keep", &variables).is_err());
+    }
+
+    #[test]
+    fn test_presets_parse() {
+        for (name, _) in PRESETS {
+            assert!(MergePolicy::preset(name).is_ok());
+        }
+
+        assert!(MergePolicy::preset("hangar").is_err());
+    }
+
+    #[test]
+    fn test_rule_priority() {
+        let policy = parsers::pol::parse_policy("rule hotkeys/** keep priority 10\nrule ID_AAM override\nrule hotkeys/* error").unwrap();
+        let path: Vec<String> = ["controls", "hotkeys", "ID_AAM"].map(str::to_string).into();
+
+        let config = BlkConfig::default();
+
+        let ranked: Vec<String> = policy.matching_rules(&path, &config).into_iter().map(|(_, rule)| rule.pattern.to_string()).collect();
+
+        assert_eq!(ranked, vec!["hotkeys/**", "hotkeys/*", "ID_AAM"]);
+        assert_eq!(policy.strategy_for(&path, &config), (MergeStrategy::Keep, false));
+    }
+
+    #[test]
+    fn test_conditional_rules() {
+        let policy = parsers::pol::parse_policy("rule controls/hotkeys keep if settings/aileronsMultiplier > 0.5").unwrap();
+        let path: Vec<String> = ["controls", "hotkeys"].map(str::to_string).into();
+        let config = |value: &str| parsers::blk::parse_config(&format!("settings{{aileronsMultiplier:r={};}};", value)).unwrap().1;
+
+        assert_eq!(policy.strategy_for(&path, &config("0.8")), (MergeStrategy::Keep, true));
+        assert_eq!(policy.strategy_for(&path, &config("0.2")), (MergeStrategy::Override, false));
+    }
+
+    #[test]
+    fn test_differing_paths() {
+        let base = parsers::blk::parse_config("graphics{sky:i=1;same:i=1;};old{a:i=1;};hotkeys{k{a:i=1;};k{a:i=1;};};").unwrap().1;
+        let overlay = parsers::blk::parse_config("graphics{sky:i=2;same:i=1;};new:b=yes;hotkeys{k{a:i=1;};k{a:i=2;};};").unwrap().1;
+
+        assert_eq!(differing_paths(&base, &overlay), vec!["/graphics/sky", "/new", "/hotkeys/k/a", "/old"]);
+    }
+
+    #[test]
+    fn test_starter_policy_skips_names_that_are_not_literal_segments() {
+        let base = parsers::blk::parse_config("a:i=1;\"sp ace\":i=1;s{\"x*\":i=1;};").unwrap().1;
+        let overlay = parsers::blk::parse_config("a:i=2;\"sp ace\":i=2;s{\"x*\":i=2;};").unwrap().1;
+
+        let (policy, skipped) = starter_policy(&base, &overlay);
+
+        assert_eq!(policy.rules.iter().map(|rule| rule.pattern.to_string()).collect::<Vec<_>>(), vec!["/a"]);
+        assert_eq!(skipped, vec!["/sp ace", "/s/x*"]);
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        let input = "default keep\nrule graphics sometimes\nrule hud keep\nrule hud override\nrule telemetry delete\nrule telemetry/id keep\nfrobnicate x\n";
+        let diagnostics: Vec<(usize, Severity)> = validate_policy(input).into_iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.severity))
+            .collect();
+
+        assert_eq!(diagnostics, vec![
+            (2, Severity::Error),
+            (3, Severity::Warning),
+            (6, Severity::Warning),
+            (7, Severity::Error)
+        ]);
+    }
+}
@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+use crate::{merge::MergeStrategy, parsers::{self, blk::ParseOptions}, policy::PolicyRule, types::*};
+
+/// A single overlay in a stack manifest, applied in declaration order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackLayer {
+    /// Overlay file, resolved relative to the manifest.
+    pub file: PathBuf,
+    /// Overrides the global strategy for this layer.
+    pub strategy: Option<MergeStrategy>,
+    /// Extra path-specific strategies for this layer.
+    pub rules: Vec<PolicyRule>,
+    /// Policy file for this layer, resolved relative to the manifest.
+    pub policy: Option<PathBuf>
+}
+
+/// Reads a stack manifest written in BLK syntax.
+///
+/// Every `layer{}` section describes one overlay:
+///
+/// ```text
+/// layer{
+///     file:t="graphics.blk"
+///     strategy:t="theirs"
+///     strategyFor:t="hotkeys=append"
+///     policy:t="graphics.pol"
+/// }
+/// ```
+pub fn load_stack(manifest: &Path) -> Result<Vec<StackLayer>, String> {
+    let content = std::fs::read_to_string(manifest)
+        .map_err(|err| format!("failed to read stack manifest {}: {}", manifest.display(), err))?;
+
+    let config = parsers::blk::parse_file(&content, &ParseOptions::default(), &mut Vec::new())
+        .map_err(|err| format!("failed to parse stack manifest {}: {}", manifest.display(), err))?;
+
+    let directory = manifest.parent().unwrap_or(Path::new(""));
+
+    config.block.entries.iter()
+        .map(|entry| match entry {
+            BlkEntry::Section(section) if section.name == "layer" => parse_layer(section, directory),
+            _ => Err(format!("unexpected entry `{}` in stack manifest, expected `layer{{}}`", entry.name()))
+        })
+        .collect()
+}
+
+/// Converts a `layer{}` section into a stack layer.
+fn parse_layer(section: &BlkSection, directory: &Path) -> Result<StackLayer, String> {
+    let mut file = None;
+    let mut layer = StackLayer { file: PathBuf::new(), strategy: None, rules: Vec::new(), policy: None };
+
+    for entry in &section.entries {
+        let BlkEntry::Property(BlkProperty { key, value: BlkPropertyValue::Text(text), .. }) = entry else {
+            return Err(format!("unexpected entry `{}` in layer, expected a text property", entry.name()));
+        };
+
+        match key.as_str() {
+            "file" => file = Some(directory.join(text)),
+            "strategy" => layer.strategy = Some(text.parse()?),
+            "strategyFor" => layer.rules.push(text.parse()?),
+            "policy" => layer.policy = Some(directory.join(text)),
+            _ => return Err(format!("unknown layer property `{}`", key))
+        }
+    }
+
+    layer.file = file.ok_or("layer is missing a `file` property")?;
+    Ok(layer)
+}
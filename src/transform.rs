@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+use crate::types::*;
+
+/// A transformation applied to incoming overlay values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    /// Limits numbers to the inclusive range `[min, max]`.
+    Clamp(f64, f64),
+    /// Multiplies numbers by a factor, integers are rounded afterwards.
+    Scale(f64),
+    /// Rounds reals to the given number of decimals.
+    Round(u32)
+}
+
+impl Transform {
+    /// Applies the transform to a single number.
+    fn apply_number(&self, value: f64) -> f64 {
+        match self {
+            Transform::Clamp(min, max) => clamp(value, *min, *max),
+            Transform::Scale(factor) => value * factor,
+            Transform::Round(decimals) => {
+                let scale = 10f64.powi(*decimals as i32);
+                (value * scale).round() / scale
+            }
+        }
+    }
+
+    /// Applies the transform to a value. Text, booleans, colors and matrices are left unchanged,
+    /// vectors are transformed component-wise.
+    pub fn apply(&self, value: &BlkPropertyValue) -> BlkPropertyValue {
+        let real = |value: f32| self.apply_number(f64::from(value)) as f32;
+        let integer = |value: i32| match self {
+            Transform::Round(_) => value,
+            Transform::Clamp(min, max) => clamp_integer(f64::from(value), *min, *max) as i32,
+            Transform::Scale(_) => self.apply_number(f64::from(value)).round() as i32
+        };
+
+        match value {
+            BlkPropertyValue::Integer(value) => BlkPropertyValue::Integer(integer(*value)),
+            BlkPropertyValue::Long(value) => match self {
+                Transform::Round(_) => BlkPropertyValue::Long(*value),
+                Transform::Clamp(min, max) => BlkPropertyValue::Long(clamp_integer(*value as f64, *min, *max) as i64),
+                Transform::Scale(_) => BlkPropertyValue::Long(self.apply_number(*value as f64).round() as i64)
+            },
+            BlkPropertyValue::Real(x) => BlkPropertyValue::Real(real(*x)),
+            BlkPropertyValue::Vector2(x, y) => BlkPropertyValue::Vector2(real(*x), real(*y)),
+            BlkPropertyValue::Vector3(x, y, z) => BlkPropertyValue::Vector3(real(*x), real(*y), real(*z)),
+            BlkPropertyValue::Vector4(x, y, z, w) => BlkPropertyValue::Vector4(real(*x), real(*y), real(*z), real(*w)),
+            BlkPropertyValue::IntVector2(x, y) => BlkPropertyValue::IntVector2(integer(*x), integer(*y)),
+            BlkPropertyValue::IntVector3(x, y, z) => BlkPropertyValue::IntVector3(integer(*x), integer(*y), integer(*z)),
+            _ => value.clone()
+        }
+    }
+}
+
+/// Limits a number to `[min, max]`, unlike `f64::clamp` without panicking on an empty range or NaN bounds.
+fn clamp(value: f64, min: f64, max: f64) -> f64 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+/// Limits an integer to the integers within `[min, max]`. A value below a range holding
+/// no integer becomes the integer above it, a value above it the integer below it.
+fn clamp_integer(value: f64, min: f64, max: f64) -> f64 {
+    if value < min {
+        min.ceil()
+    } else if value > max {
+        max.floor()
+    } else {
+        value
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    /// Parses a transform written as `clamp <min> <max>`, `scale <factor>` or `round <decimals>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let number = |word: &str| word.parse::<f64>().map_err(|_| format!("expected a number, got `{}`", word));
+
+        match words.as_slice() {
+            ["clamp", min, max] => {
+                let (min, max) = (number(min)?, number(max)?);
+
+                if !min.is_finite() || !max.is_finite() {
+                    return Err(format!("clamp bounds `{} {}` must be finite", min, max));
+                }
+
+                if min > max {
+                    return Err(format!("clamp range `{} {}` is empty", min, max));
+                }
+
+                Ok(Transform::Clamp(min, max))
+            },
+            ["scale", factor] => Ok(Transform::Scale(number(factor)?)),
+            ["round", decimals] => decimals.parse()
+                .map(Transform::Round)
+                .map_err(|_| format!("expected a number of decimals, got `{}`", decimals)),
+            [name, ..] if !["clamp", "scale", "round"].contains(name) => Err(format!("unknown transform `{}`", name)),
+            _ => Err(format!("invalid transform `{}`, expected `clamp <min> <max>`, `scale <factor>` or `round <decimals>`", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_transforms() {
+        let clamp: Transform = "clamp 0.1 2.0".parse().unwrap();
+        let round: Transform = "round 2".parse().unwrap();
+
+        assert_eq!(clamp.apply(&BlkPropertyValue::Real(5.0)), BlkPropertyValue::Real(2.0));
+        assert_eq!(clamp.apply(&BlkPropertyValue::Integer(0)), BlkPropertyValue::Integer(1));
+        assert_eq!(round.apply(&BlkPropertyValue::Real(1.23456)), BlkPropertyValue::Real(1.23));
+        assert_eq!("scale 1.5".parse::<Transform>().unwrap().apply(&BlkPropertyValue::Integer(3)), BlkPropertyValue::Integer(5));
+        assert_eq!(round.apply(&BlkPropertyValue::Text("x".to_string())), BlkPropertyValue::Text("x".to_string()));
+        assert!("clamp 2 1".parse::<Transform>().is_err());
+        assert!("wobble 1".parse::<Transform>().is_err());
+    }
+
+    #[test]
+    fn test_clamp_integers_to_range_without_integers() {
+        let clamp: Transform = "clamp 0.1 0.9".parse().unwrap();
+
+        assert_eq!(clamp.apply(&BlkPropertyValue::Integer(5)), BlkPropertyValue::Integer(0));
+        assert_eq!(clamp.apply(&BlkPropertyValue::Integer(-5)), BlkPropertyValue::Integer(1));
+        assert_eq!(clamp.apply(&BlkPropertyValue::Long(5)), BlkPropertyValue::Long(0));
+        assert_eq!(clamp.apply(&BlkPropertyValue::Real(5.0)), BlkPropertyValue::Real(0.9));
+    }
+
+    #[test]
+    fn test_clamp_rejects_non_finite_bounds() {
+        assert!("clamp nan 1".parse::<Transform>().is_err());
+        assert!("clamp 0 nan".parse::<Transform>().is_err());
+        assert!("clamp -inf 1".parse::<Transform>().is_err());
+    }
+}
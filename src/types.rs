@@ -1,106 +1,1539 @@
-use std::io::Write;
+use std::{borrow::Cow, fmt, io::Write, ops::Range, str::FromStr, sync::Arc};
+
+use serde::Serialize;
+
+use crate::encoding::{self, Encoding};
 
 /// Represents the possible values a property can have in a BLK configuration.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum BlkPropertyValue {
     Text(String),
     Boolean(bool),
     Integer(i32),
+    Long(i64),
     Real(f32),
     Vector2(f32, f32),
     Vector3(f32, f32, f32),
     Vector4(f32, f32, f32, f32),
-    Color(i32, i32, i32, i32)
+    IntVector2(i32, i32),
+    IntVector3(i32, i32, i32),
+    /// A 3x4 transform matrix stored as four rows: three axes followed by the position.
+    Matrix(Box<[[f32; 3]; 4]>),
+    Color(i32, i32, i32, i32),
+    /// No value, written as `key:t=` with nothing after the `=`.
+    None(BlkType)
+}
+
+/// Represents the different types of BLK properties.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum BlkType { Text, Boolean, Integer, Long, Real, Point2, Point3, Point4, IntPoint2, IntPoint3, Matrix, Color }
+
+impl BlkType {
+    /// Returns the name of the type as used in messages and policies.
+    pub fn name(self) -> &'static str {
+        match self {
+            BlkType::Text => "text",
+            BlkType::Boolean => "boolean",
+            BlkType::Integer => "integer",
+            BlkType::Long => "long",
+            BlkType::Real => "real",
+            BlkType::Point2 => "point2",
+            BlkType::Point3 => "point3",
+            BlkType::Point4 => "point4",
+            BlkType::IntPoint2 => "ipoint2",
+            BlkType::IntPoint3 => "ipoint3",
+            BlkType::Matrix => "matrix",
+            BlkType::Color => "color"
+        }
+    }
+
+    /// Returns the suffix of the type written after the `:` of a property.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            BlkType::Text => "t",
+            BlkType::Boolean => "b",
+            BlkType::Integer => "i",
+            BlkType::Long => "i64",
+            BlkType::Real => "r",
+            BlkType::Point2 => "p2",
+            BlkType::Point3 => "p3",
+            BlkType::Point4 => "p4",
+            BlkType::IntPoint2 => "ip2",
+            BlkType::IntPoint3 => "ip3",
+            BlkType::Matrix => "m",
+            BlkType::Color => "c"
+        }
+    }
+}
+
+impl FromStr for BlkType {
+    type Err = String;
+
+    /// Parses a type name or its BLK suffix, e.g. `integer` or `i`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "t" => Ok(BlkType::Text),
+            "boolean" | "bool" | "b" => Ok(BlkType::Boolean),
+            "integer" | "int" | "i" => Ok(BlkType::Integer),
+            "long" | "i64" => Ok(BlkType::Long),
+            "real" | "r" => Ok(BlkType::Real),
+            "point2" | "p2" => Ok(BlkType::Point2),
+            "point3" | "p3" => Ok(BlkType::Point3),
+            "point4" | "p4" => Ok(BlkType::Point4),
+            "ipoint2" | "ip2" => Ok(BlkType::IntPoint2),
+            "ipoint3" | "ip3" => Ok(BlkType::IntPoint3),
+            "matrix" | "m" => Ok(BlkType::Matrix),
+            "color" | "c" => Ok(BlkType::Color),
+            _ => Err(format!("unknown BLK type `{}`", s))
+        }
+    }
+}
+
+impl fmt::Display for BlkType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl BlkPropertyValue {
+    /// Returns the real components of the value.
+    pub fn reals_mut(&mut self) -> Vec<&mut f32> {
+        match self {
+            BlkPropertyValue::Real(x) => vec![x],
+            BlkPropertyValue::Vector2(x, y) => vec![x, y],
+            BlkPropertyValue::Vector3(x, y, z) => vec![x, y, z],
+            BlkPropertyValue::Vector4(x, y, z, w) => vec![x, y, z, w],
+            BlkPropertyValue::Matrix(rows) => rows.iter_mut().flatten().collect(),
+            _ => Vec::new()
+        }
+    }
+
+    /// Returns the BLK type of the value.
+    pub fn ty(&self) -> BlkType {
+        match self {
+            BlkPropertyValue::Text(_) => BlkType::Text,
+            BlkPropertyValue::Boolean(_) => BlkType::Boolean,
+            BlkPropertyValue::Integer(_) => BlkType::Integer,
+            BlkPropertyValue::Long(_) => BlkType::Long,
+            BlkPropertyValue::Real(_) => BlkType::Real,
+            BlkPropertyValue::Vector2(..) => BlkType::Point2,
+            BlkPropertyValue::Vector3(..) => BlkType::Point3,
+            BlkPropertyValue::Vector4(..) => BlkType::Point4,
+            BlkPropertyValue::IntVector2(..) => BlkType::IntPoint2,
+            BlkPropertyValue::IntVector3(..) => BlkType::IntPoint3,
+            BlkPropertyValue::Matrix(_) => BlkType::Matrix,
+            BlkPropertyValue::Color(..) => BlkType::Color,
+            BlkPropertyValue::None(ty) => *ty
+        }
+    }
+
+    /// Converts the value to another type.
+    ///
+    /// Numbers, booleans and text convert into each other when no information is lost,
+    /// e.g. `2.0` becomes the integer `2` but `2.5` fails. Vectors and colors only
+    /// convert to their own type, and a missing value stays missing.
+    pub fn coerce(&self, ty: BlkType) -> Result<BlkPropertyValue, String> {
+        let converted = match (self, ty) {
+            _ if self.ty() == ty => Some(self.clone()),
+            (BlkPropertyValue::None(_), ty) => Some(BlkPropertyValue::None(ty)),
+            (BlkPropertyValue::Integer(integer), BlkType::Real) => Some(BlkPropertyValue::Real(*integer as f32)),
+            (BlkPropertyValue::Real(real), BlkType::Integer) if real.fract() == 0.0 && real.abs() <= i32::MAX as f32 =>
+                Some(BlkPropertyValue::Integer(*real as i32)),
+            (BlkPropertyValue::Integer(integer), BlkType::Long) => Some(BlkPropertyValue::Long(i64::from(*integer))),
+            (BlkPropertyValue::Long(long), BlkType::Integer) => i32::try_from(*long).ok().map(BlkPropertyValue::Integer),
+            (BlkPropertyValue::Boolean(boolean), BlkType::Integer) => Some(BlkPropertyValue::Integer(i32::from(*boolean))),
+            (BlkPropertyValue::Integer(integer @ (0 | 1)), BlkType::Boolean) => Some(BlkPropertyValue::Boolean(*integer == 1)),
+            (BlkPropertyValue::Text(text), BlkType::Integer) => text.trim().parse().ok().map(BlkPropertyValue::Integer),
+            (BlkPropertyValue::Text(text), BlkType::Long) => text.trim().parse().ok().map(BlkPropertyValue::Long),
+            (BlkPropertyValue::Text(text), BlkType::Real) => text.trim().parse().ok().map(BlkPropertyValue::Real),
+            (BlkPropertyValue::Text(text), BlkType::Boolean) => match text.trim() {
+                "yes" | "true" | "on" | "1" => Some(BlkPropertyValue::Boolean(true)),
+                "no" | "false" | "off" | "0" => Some(BlkPropertyValue::Boolean(false)),
+                _ => None
+            },
+            (BlkPropertyValue::Integer(integer), BlkType::Text) => Some(BlkPropertyValue::Text(integer.to_string())),
+            (BlkPropertyValue::Long(long), BlkType::Text) => Some(BlkPropertyValue::Text(long.to_string())),
+            (BlkPropertyValue::Real(real), BlkType::Text) => Some(BlkPropertyValue::Text(real.to_string())),
+            (BlkPropertyValue::Boolean(boolean), BlkType::Text) =>
+                Some(BlkPropertyValue::Text(if *boolean { "yes" } else { "no" }.to_string())),
+            _ => None
+        };
+
+        converted.ok_or_else(|| format!("{:?} cannot be converted to {}", self, ty))
+    }
+}
+
+/// Formats the value as it is written after the `=` of a property with the default options.
+impl fmt::Display for BlkPropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlkPropertyValue::Text(text) => write!(f, "\"{}\"", escape_string(text, QuoteStyle::Double)),
+            BlkPropertyValue::Boolean(boolean) => f.write_str(BooleanStyle::YesNo.literal(*boolean)),
+            BlkPropertyValue::Integer(integer) => write!(f, "{}", integer),
+            BlkPropertyValue::Long(long) => write!(f, "{}", long),
+            BlkPropertyValue::Real(real) => write!(f, "{}", real),
+            BlkPropertyValue::Vector2(x, y) => write!(f, "{}, {}", x, y),
+            BlkPropertyValue::Vector3(x, y, z) => write!(f, "{}, {}, {}", x, y, z),
+            BlkPropertyValue::Vector4(x, y, z, w) => write!(f, "{}, {}, {}, {}", x, y, z, w),
+            BlkPropertyValue::IntVector2(x, y) => write!(f, "{}, {}", x, y),
+            BlkPropertyValue::IntVector3(x, y, z) => write!(f, "{}, {}, {}", x, y, z),
+            BlkPropertyValue::Matrix(rows) => {
+                let rows: Vec<String> = rows.iter().map(|[x, y, z]| format!("[{}, {}, {}]", x, y, z)).collect();
+                write!(f, "[{}]", rows.join(" "))
+            },
+            BlkPropertyValue::Color(r, g, b, a) => write!(f, "{}, {}, {}, {}", r, g, b, a),
+            BlkPropertyValue::None(_) => Ok(())
+        }
+    }
+}
+
+/// Quotes a text value is written with.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum QuoteStyle {
+    #[default]
+    Double,
+    Single,
+    /// `"""` quotes around raw text without escapes, which may span several lines.
+    Triple
+}
+
+impl QuoteStyle {
+    /// Returns the quotes opening and closing a value.
+    pub fn delimiter(self) -> &'static str {
+        match self {
+            QuoteStyle::Double => "\"",
+            QuoteStyle::Single => "'",
+            QuoteStyle::Triple => "\"\"\""
+        }
+    }
+}
+
+impl FromStr for QuoteStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "double" => Ok(QuoteStyle::Double),
+            "single" => Ok(QuoteStyle::Single),
+            "triple" => Ok(QuoteStyle::Triple),
+            _ => Err(format!("unknown quote style `{}`, expected `double`, `single` or `triple`", s))
+        }
+    }
+}
+
+/// Literals boolean values are written with.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BooleanStyle {
+    #[default]
+    YesNo,
+    TrueFalse,
+    OnOff,
+    /// `1` and `0`.
+    Numeric
+}
+
+impl BooleanStyle {
+    /// Returns the literal for a boolean value.
+    pub fn literal(self, value: bool) -> &'static str {
+        match (self, value) {
+            (BooleanStyle::YesNo, true) => "yes",
+            (BooleanStyle::YesNo, false) => "no",
+            (BooleanStyle::TrueFalse, true) => "true",
+            (BooleanStyle::TrueFalse, false) => "false",
+            (BooleanStyle::OnOff, true) => "on",
+            (BooleanStyle::OnOff, false) => "off",
+            (BooleanStyle::Numeric, true) => "1",
+            (BooleanStyle::Numeric, false) => "0"
+        }
+    }
+}
+
+impl FromStr for BooleanStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yes-no" => Ok(BooleanStyle::YesNo),
+            "true-false" => Ok(BooleanStyle::TrueFalse),
+            "on-off" => Ok(BooleanStyle::OnOff),
+            "numeric" => Ok(BooleanStyle::Numeric),
+            _ => Err(format!("unknown boolean style `{}`, expected `yes-no`, `true-false`, `on-off` or `numeric`", s))
+        }
+    }
+}
+
+/// Notation color values are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorStyle {
+    /// Four comma-separated components.
+    Decimal,
+    /// `#RRGGBBAA`, used only for colors with components from 0 to 255.
+    Hex
+}
+
+impl FromStr for ColorStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(ColorStyle::Decimal),
+            "hex" => Ok(ColorStyle::Hex),
+            _ => Err(format!("unknown color style `{}`, expected `decimal` or `hex`", s))
+        }
+    }
+}
+
+/// Location of an entry in the text it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// 1-based line the entry starts on.
+    pub line: usize,
+    /// 1-based column the entry starts at.
+    pub column: usize,
+    pub byte_range: Range<usize>,
+    /// Whether a blank line comes right before the entry, or before the comments leading it.
+    pub blank_before: bool,
+    /// Whether the entry is terminated by a `;`.
+    pub semicolon: bool
+}
+
+/// Comments written around an entry, kept when serializing. Each comment is kept as written,
+/// along with its `//` or `/* */`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Comments {
+    /// Comments on lines of their own before the entry.
+    pub leading: Vec<String>,
+    /// Comments after the entry on the line it ends on.
+    pub trailing: Option<String>,
+    /// Comments after the `{` of a section on the line it opens.
+    pub header: Option<String>,
+    /// Comments on lines of their own after the last entry of a section, before its `}`.
+    pub closing: Vec<String>
+}
+
+impl Comments {
+    /// Returns true if there are no comments.
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_none() && self.header.is_none() && self.closing.is_empty()
+    }
 }
 
 /// Represents a property in a BLK configuration.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct BlkProperty {
     pub key: String,
-    pub value: BlkPropertyValue
+    pub value: BlkPropertyValue,
+    /// Quote character a text value was written with, kept when serializing.
+    pub quote: QuoteStyle,
+    /// Whether an integer or color value was written in hexadecimal.
+    pub hex: bool,
+    /// Whether a color value was written as `#RRGGBBAA`.
+    pub hex_color: bool,
+    /// Literal a number, vector or color value was written as, like `0.50` or `+10000`, kept
+    /// when serializing as long as the value is unchanged.
+    pub raw: Option<String>,
+    /// Where the property was written, `None` if it was not parsed from text.
+    pub span: Option<Span>,
+    pub comments: Comments
+}
+
+/// Properties are equal if their keys and values are, however and wherever the values were written.
+impl PartialEq for BlkProperty {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key && self.value == other.value
+    }
+}
+
+/// Annotation written between the name of a section and its `{`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionAnnotation {
+    /// `name:tag{`, such as `name:override{`.
+    Tag(String),
+    /// `name@template{`.
+    Template(String)
+}
+
+impl fmt::Display for SectionAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionAnnotation::Tag(tag) => write!(f, ":{}", tag),
+            SectionAnnotation::Template(template) => write!(f, "@{}", template)
+        }
+    }
 }
 
 /// Represents a section in a BLK configuration.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct BlkSection {
     pub name: String,
-    pub entries: Vec<BlkEntry>
+    pub annotation: Option<SectionAnnotation>,
+    pub entries: Vec<BlkEntry>,
+    /// Where the section was written, `None` if it was not parsed from text.
+    pub span: Option<Span>,
+    pub comments: Comments
+}
+
+/// Sections are equal if their names, annotations and entries are, wherever they were written
+/// and whatever comments they have.
+impl PartialEq for BlkSection {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.annotation == other.annotation && self.entries == other.entries
+    }
+}
+
+/// Represents an unresolved merge conflict between two versions of the same entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlkConflict {
+    pub base: Box<BlkEntry>,
+    pub overlay: Box<BlkEntry>
 }
 
 /// Represents an entry in a BLK configuration, which can be either a section or a property.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BlkEntry {
     Section(BlkSection),
-    Property(BlkProperty)
+    Property(BlkProperty),
+    Conflict(BlkConflict),
+    /// An unresolved `include "file.blk"` directive, holding the path as written.
+    Include(String)
+}
+
+impl BlkEntry {
+    /// Returns the section name or property key of this entry, or the path of an include.
+    pub fn name(&self) -> &str {
+        match self {
+            BlkEntry::Section(section) => &section.name,
+            BlkEntry::Property(property) => &property.key,
+            BlkEntry::Conflict(conflict) => conflict.base.name(),
+            BlkEntry::Include(path) => path
+        }
+    }
+
+    /// Returns where a section or property was written, if it was parsed from text.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            BlkEntry::Section(section) => section.span.as_ref(),
+            BlkEntry::Property(property) => property.span.as_ref(),
+            _ => None
+        }
+    }
+
+    /// Returns the comments of a section or property.
+    pub fn comments(&self) -> Option<&Comments> {
+        match self {
+            BlkEntry::Section(section) => Some(&section.comments),
+            BlkEntry::Property(property) => Some(&property.comments),
+            _ => None
+        }
+    }
+
+    /// Returns true if both entries are of the same kind (section, property or conflict).
+    pub fn same_kind(&self, other: &BlkEntry) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
 }
 
 /// Represents a block in a BLK configuration.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct BlkBlock {
     pub entries: Vec<BlkEntry>
 }
 
 /// Represents a BLK configuration, which consists of multiple entries.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Default)]
 pub struct BlkConfig {
-    pub block: BlkBlock
+    pub block: BlkBlock,
+    /// Whether the file started with a UTF-8 byte order mark.
+    pub bom: bool,
+    /// Whether the lines of the file end with `\r\n`.
+    pub crlf: bool,
+    /// Encoding the file was decoded from if it was not UTF-8.
+    pub encoding: Option<&'static Encoding>,
+    /// Comments on lines of their own after the last entry of the file.
+    pub comments: Vec<String>
+}
+
+impl PartialEq for BlkConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.block == other.block
+    }
+}
+
+impl BlkConfig {
+    /// Finds the first entry at a `/`-separated path, descending into the first matching section at each level.
+    pub fn find<S: AsRef<str>>(&self, path: &[S]) -> Option<&BlkEntry> {
+        let (last, parents) = path.split_last()?;
+        let mut entries = &self.block.entries;
+
+        for segment in parents {
+            entries = entries.iter().find_map(|entry| match entry {
+                BlkEntry::Section(section) if section.name == segment.as_ref() => Some(&section.entries),
+                _ => None
+            })?;
+        }
+
+        entries.iter().find(|entry| entry.name() == last.as_ref())
+    }
+
+    /// Writes the config into a string as [`stringify_config_with`] does, always in UTF-8.
+    pub fn to_blk_string(&self, options: &SerializeOptions) -> String {
+        let mut output = String::new();
+
+        stringify_config_fmt(self, &mut output, options).expect("writing to a string does not fail");
+        output
+    }
+}
+
+/// A property value that borrows text from the parsed input where possible.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedValue<'a> {
+    Text(Cow<'a, str>),
+    /// Any value other than text.
+    Other(BlkPropertyValue)
+}
+
+/// A property that borrows its key and text value from the parsed input where possible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowedProperty<'a> {
+    pub key: Cow<'a, str>,
+    pub value: BorrowedValue<'a>,
+    pub quote: QuoteStyle,
+    pub hex: bool,
+    pub hex_color: bool,
+    pub raw: Option<&'a str>,
+    pub span: Option<Span>
+}
+
+/// A section that borrows its name from the parsed input where possible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BorrowedSection<'a> {
+    pub name: Cow<'a, str>,
+    pub annotation: Option<SectionAnnotation>,
+    pub entries: Vec<BorrowedEntry<'a>>,
+    pub span: Option<Span>
+}
+
+/// An entry of a [`BorrowedConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedEntry<'a> {
+    Section(BorrowedSection<'a>),
+    Property(BorrowedProperty<'a>),
+    Include(Cow<'a, str>)
+}
+
+/// A BLK configuration whose names and text values borrow from the parsed input instead of
+/// being allocated one by one. Only names and text containing escapes are owned.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BorrowedConfig<'a> {
+    pub entries: Vec<BorrowedEntry<'a>>,
+    /// Whether the file started with a UTF-8 byte order mark.
+    pub bom: bool,
+    /// Whether the lines of the file end with `\r\n`.
+    pub crlf: bool
+}
+
+impl BorrowedEntry<'_> {
+    /// Converts the entry into an owned entry.
+    pub fn into_owned(self) -> BlkEntry {
+        match self {
+            BorrowedEntry::Section(section) => BlkEntry::Section(BlkSection {
+                name: section.name.into_owned(),
+                annotation: section.annotation,
+                entries: section.entries.into_iter().map(BorrowedEntry::into_owned).collect(),
+                span: section.span,
+                comments: Comments::default()
+            }),
+            BorrowedEntry::Property(property) => BlkEntry::Property(BlkProperty {
+                key: property.key.into_owned(),
+                value: match property.value {
+                    BorrowedValue::Text(text) => BlkPropertyValue::Text(text.into_owned()),
+                    BorrowedValue::Other(value) => value
+                },
+                quote: property.quote,
+                hex: property.hex,
+                hex_color: property.hex_color,
+                raw: property.raw.map(str::to_string),
+                span: property.span,
+                comments: Comments::default()
+            }),
+            BorrowedEntry::Include(path) => BlkEntry::Include(path.into_owned())
+        }
+    }
+}
+
+impl BorrowedConfig<'_> {
+    /// Converts the config into an owned config.
+    pub fn into_owned(self) -> BlkConfig {
+        BlkConfig { block: BlkBlock { entries: self.entries.into_iter().map(BorrowedEntry::into_owned).collect() }, bom: self.bom, crlf: self.crlf, encoding: None, comments: Vec::new() }
+    }
+}
+
+/// Returns whether `c` may appear in an identifier.
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/' | '@')
+}
+
+/// Returns whether `name` can be written as a bare key or section name.
+///
+/// A `/` may not start a comment, so `//` and `/*` are not allowed within a name.
+pub fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_identifier_char) && !name.contains("//") && !name.contains("/*")
+}
+
+/// Escapes a string value written in the given quotes so that the BLK parser reads it back unchanged.
+///
+/// Text in `"""` quotes is raw and returned as is, see [`is_raw_string`].
+pub fn escape_string(text: &str, quote: QuoteStyle) -> Cow<'_, str> {
+    if quote == QuoteStyle::Triple {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' if quote == QuoteStyle::Double => escaped.push_str("\\\""),
+            '\'' if quote == QuoteStyle::Single => escaped.push_str("\\'"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c)
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Returns whether text can be written raw in `"""` quotes, which it cannot close early.
+pub fn is_raw_string(text: &str) -> bool {
+    !text.contains("\"\"\"") && !text.ends_with('"')
+}
+
+/// Returns a key or section name as written in BLK, quoted only if it is not a bare identifier.
+pub fn quote_name(name: &str) -> Cow<'_, str> {
+    if is_identifier(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", escape_string(name, QuoteStyle::Double)))
+    }
+}
+
+/// Like [`quote_name`] for section names, which are also quoted if they contain `@`, as it
+/// would start a template annotation otherwise.
+pub fn quote_section_name(name: &str) -> Cow<'_, str> {
+    match name.contains('@') {
+        true => Cow::Owned(format!("\"{}\"", escape_string(name, QuoteStyle::Double))),
+        false => quote_name(name)
+    }
+}
+
+/// When property keys and section names are written in quotes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum QuoteKeys {
+    /// Never, which writes output the parser cannot read back if a name is not an identifier.
+    Never,
+    /// Only names that are not identifiers, see [`quote_name`] and [`quote_section_name`].
+    #[default]
+    WhenNeeded,
+    Always
+}
+
+impl QuoteKeys {
+    /// Returns a property key, or a section name if `section` is set, as it is written.
+    fn apply(self, name: &str, section: bool) -> Cow<'_, str> {
+        match self {
+            QuoteKeys::Never => Cow::Borrowed(name),
+            QuoteKeys::WhenNeeded if section => quote_section_name(name),
+            QuoteKeys::WhenNeeded => quote_name(name),
+            QuoteKeys::Always => Cow::Owned(format!("\"{}\"", escape_string(name, QuoteStyle::Double)))
+        }
+    }
+}
+
+impl FromStr for QuoteKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(QuoteKeys::Never),
+            "when-needed" => Ok(QuoteKeys::WhenNeeded),
+            "always" => Ok(QuoteKeys::Always),
+            _ => Err(format!("unknown key quoting `{}`, expected `never`, `when-needed` or `always`", s))
+        }
+    }
+}
+
+/// Indentation of the entries in sections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentStyle {
+    /// The given number of spaces per level.
+    Spaces(usize),
+    Tabs,
+    /// No indentation at all.
+    None
+}
+
+impl IndentStyle {
+    /// Returns the indentation of entries nested `depth` sections deep.
+    pub fn repeat(self, depth: usize) -> String {
+        match self {
+            IndentStyle::Spaces(count) => " ".repeat(count * depth),
+            IndentStyle::Tabs => "\t".repeat(depth),
+            IndentStyle::None => String::new()
+        }
+    }
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        IndentStyle::Spaces(4)
+    }
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tabs" => Ok(IndentStyle::Tabs),
+            "none" => Ok(IndentStyle::None),
+            count => count.parse().map(IndentStyle::Spaces)
+                .map_err(|_| format!("unknown indentation `{}`, expected a number of spaces, `tabs` or `none`", s))
+        }
+    }
+}
+
+/// Layout of written configs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputStyle {
+    /// Every entry on its own line, with the entries of sections indented.
+    #[default]
+    Expanded,
+    /// All entries on a single line, separated by `;`. Comments are left out, as line
+    /// comments would end the line.
+    Compact
+}
+
+impl FromStr for OutputStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expanded" => Ok(OutputStyle::Expanded),
+            "compact" => Ok(OutputStyle::Compact),
+            _ => Err(format!("unknown output style `{}`, expected `expanded` or `compact`", s))
+        }
+    }
+}
+
+/// Entries [`stringify_config_with`] sorts by name within each section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKeys {
+    /// Properties, leaving sections where they are.
+    Properties,
+    /// Properties and sections, each kind among the places of its own kind.
+    All
+}
+
+impl FromStr for SortKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "properties" => Ok(SortKeys::Properties),
+            "all" => Ok(SortKeys::All),
+            _ => Err(format!("unknown sorting `{}`, expected `properties` or `all`", s))
+        }
+    }
+}
+
+/// Returns entries in the order they are written in, grouped and sorted as the options demand.
+/// Sorting is stable, so sections and properties of the same name keep their order.
+fn sorted<'e>(entries: &'e [BlkEntry], options: &SerializeOptions) -> Vec<&'e BlkEntry> {
+    let mut ordered: Vec<&BlkEntry> = entries.iter().collect();
+
+    if options.group {
+        ordered.sort_by_key(|entry| match entry {
+            BlkEntry::Include(_) => 0,
+            BlkEntry::Section(_) => 2,
+            _ => 1
+        });
+    }
+
+    let kinds: &[fn(&BlkEntry) -> bool] = match options.sort {
+        None => &[],
+        Some(SortKeys::Properties) => &[|entry| matches!(entry, BlkEntry::Property(_))],
+        Some(SortKeys::All) => &[|entry| matches!(entry, BlkEntry::Property(_)), |entry| matches!(entry, BlkEntry::Section(_))]
+    };
+
+    for kind in kinds {
+        let places: Vec<usize> = (0..ordered.len()).filter(|&index| kind(ordered[index])).collect();
+        let mut by_name: Vec<&BlkEntry> = places.iter().map(|&index| ordered[index]).collect();
+        by_name.sort_by(|a, b| a.name().cmp(b.name()));
+
+        for (place, entry) in places.into_iter().zip(by_name) {
+            ordered[place] = entry;
+        }
+    }
+
+    ordered
+}
+
+/// Blank lines written between the entries of a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BlankLines {
+    /// No blank lines at all.
+    #[default]
+    None,
+    /// A blank line before every section that is not the first entry of its block.
+    Sections,
+    /// A blank line before every entry that had one in the parsed file.
+    Preserve
+}
+
+impl FromStr for BlankLines {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(BlankLines::None),
+            "sections" => Ok(BlankLines::Sections),
+            "preserve" => Ok(BlankLines::Preserve),
+            _ => Err(format!("unknown blank lines `{}`, expected `none`, `sections` or `preserve`", s))
+        }
+    }
+}
+
+/// Terminators written after entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Terminators {
+    /// Only the line break.
+    #[default]
+    Newline,
+    /// A `;` before the line break of every property and include, sections end with `}` anyway.
+    Semicolon,
+    /// A `;` after every entry that had one in the parsed file.
+    Preserve
+}
+
+impl FromStr for Terminators {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(Terminators::Newline),
+            "semicolon" => Ok(Terminators::Semicolon),
+            "preserve" => Ok(Terminators::Preserve),
+            _ => Err(format!("unknown terminators `{}`, expected `newline`, `semicolon` or `preserve`", s))
+        }
+    }
+}
+
+/// Spacing between the components of vectors, matrices and colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ComponentSpacing {
+    /// `1, 2, 3`
+    #[default]
+    Spaced,
+    /// `1,2,3`
+    Tight
+}
+
+impl ComponentSpacing {
+    /// Returns the spacing of a literal, or `None` if it has a single component.
+    pub fn of(literal: &str) -> Option<ComponentSpacing> {
+        let (_, rest) = literal.split_once(',')?;
+        Some(if rest.starts_with([' ', '\t']) { ComponentSpacing::Spaced } else { ComponentSpacing::Tight })
+    }
+
+    /// Returns what is written between two components.
+    pub fn separator(self) -> &'static str {
+        match self {
+            ComponentSpacing::Spaced => ", ",
+            ComponentSpacing::Tight => ","
+        }
+    }
+}
+
+impl FromStr for ComponentSpacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spaced" => Ok(ComponentSpacing::Spaced),
+            "tight" => Ok(ComponentSpacing::Tight),
+            _ => Err(format!("unknown component spacing `{}`, expected `spaced` or `tight`", s))
+        }
+    }
+}
+
+/// Sections written on a single line in the expanded style.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InlineSections {
+    /// None, every section spans at least two lines.
+    #[default]
+    Never,
+    /// Empty sections, as `name{}`.
+    Empty,
+    /// Empty sections and sections holding a single property, as `name{ key:i=1 }`.
+    Single
+}
+
+impl FromStr for InlineSections {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(InlineSections::Never),
+            "empty" => Ok(InlineSections::Empty),
+            "single" => Ok(InlineSections::Single),
+            _ => Err(format!("unknown inline sections `{}`, expected `never`, `empty` or `single`", s))
+        }
+    }
+}
+
+/// Line endings of written files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::CrLf),
+            _ => Err(format!("unknown line ending `{}`, expected `lf` or `crlf`", s))
+        }
+    }
+}
+
+/// Formats a value as the literal written after the `=`, or leaves it to the serializer by returning `None`.
+pub type ValueFormatter = dyn Fn(&BlkPropertyValue) -> Option<String> + Send + Sync;
+
+/// Formatters registered for the values of some types, consulted before the serializer
+/// formats a value itself.
+#[derive(Clone, Default)]
+pub struct ValueFormatters(Vec<(BlkType, Arc<ValueFormatter>)>);
+
+impl ValueFormatters {
+    pub const fn new() -> Self {
+        ValueFormatters(Vec::new())
+    }
+
+    /// Formats values of the given type with `formatter`, replacing the one registered for it before.
+    ///
+    /// The literal is written as it is returned, it has to read back as the same value for the
+    /// output to parse as the config it was written from, and text has to include its quotes.
+    pub fn register(&mut self, ty: BlkType, formatter: impl Fn(&BlkPropertyValue) -> Option<String> + Send + Sync + 'static) {
+        self.0.retain(|(registered, _)| *registered != ty);
+        self.0.push((ty, Arc::new(formatter)));
+    }
+
+    /// Returns the literal the formatter registered for the type of the value writes, if any.
+    pub fn format(&self, value: &BlkPropertyValue) -> Option<String> {
+        let ty = value.ty();
+        self.0.iter().find(|(registered, _)| *registered == ty).and_then(|(_, formatter)| formatter(value))
+    }
+}
+
+impl fmt::Debug for ValueFormatters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|(ty, _)| ty)).finish()
+    }
+}
+
+impl PartialEq for ValueFormatters {
+    /// Formatters are equal if the same functions are registered for the same types.
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(&other.0).all(|((a_ty, a), (b_ty, b))| a_ty == b_ty && Arc::ptr_eq(a, b))
+    }
+}
+
+/// Options controlling how [`stringify_config_with`] writes a config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SerializeOptions {
+    /// Comment written before the entries, one `//` line per line of text. Not written by
+    /// [`stringify_config_preserving`], which would stack it on the one of the source.
+    pub header: Option<String>,
+    /// Layout of the output, not followed by [`stringify_config_preserving`].
+    pub style: OutputStyle,
+    /// Entries to sort by name, not sorted by [`stringify_config_preserving`].
+    pub sort: Option<SortKeys>,
+    /// Write the includes of each block first and its sections last, keeping the order within
+    /// each group like the game does. Not followed by [`stringify_config_preserving`].
+    pub group: bool,
+    /// Quotes for all text values, each value keeps the quotes it was parsed with if unset.
+    pub quote: Option<QuoteStyle>,
+    /// When keys and section names are quoted.
+    pub quote_keys: QuoteKeys,
+    /// Literals for boolean values.
+    pub booleans: BooleanStyle,
+    pub indent: IndentStyle,
+    /// Sections written on a single line.
+    pub inline_sections: InlineSections,
+    /// Blank lines between entries, not written in the compact style.
+    pub blank_lines: BlankLines,
+    /// Terminators of entries, not written in the compact style.
+    pub terminators: Terminators,
+    /// Pad the keys of the properties in a section to the longest one, aligning the types and
+    /// values in a column. Not followed in the compact style or by [`stringify_config_preserving`].
+    pub align: bool,
+    /// Line endings of the output, the ones of the parsed file if unset.
+    pub line_endings: Option<LineEnding>,
+    /// Spacing between the components of all vectors, matrices and colors, each value keeps the
+    /// spacing of the literal it was parsed from if unset.
+    pub spacing: Option<ComponentSpacing>,
+    /// Notation for all color values, each color keeps the notation it was parsed with if unset.
+    pub colors: Option<ColorStyle>,
+    /// Write integers and colors that were parsed from hexadecimal literals in hexadecimal again.
+    pub keep_hex: bool,
+    /// Number of decimals of reals that are not written as the literal they were parsed from,
+    /// as few as read back as the same number if unset.
+    pub precision: Option<usize>,
+    /// Formatters replacing the formatting of the values of some types, including values
+    /// written as the literal they were parsed from.
+    pub formatters: ValueFormatters,
+    /// Write numbers, vectors and colors in their usual form instead of the literals they were parsed from.
+    pub normalize_numbers: bool,
+    /// Start the output with a UTF-8 byte order mark if the config was parsed from a file starting with one.
+    pub keep_bom: bool,
+    /// Start UTF-8 output with a byte order mark whether or not the parsed file started with one.
+    pub bom: bool,
+    /// Encoding of the output, the one the config was decoded from if unset.
+    pub encoding: Option<&'static Encoding>
+}
+
+impl SerializeOptions {
+    /// Writes configs holding the same entries as the same bytes, whatever file they were parsed
+    /// from and in which order their entries are: includes, properties and sections grouped
+    /// and each sorted by name, text in double quotes, `yes` and `no`, four spaces of indentation,
+    /// no blank lines or `;` terminators, `\n` line endings, decimal colors, `, ` between
+    /// components and the shortest numbers reading back the same, in UTF-8 without a byte order
+    /// mark. This formatting does not change between versions.
+    pub const CANONICAL: SerializeOptions = SerializeOptions {
+        header: None,
+        style: OutputStyle::Expanded,
+        sort: Some(SortKeys::All),
+        group: true,
+        quote: Some(QuoteStyle::Double),
+        quote_keys: QuoteKeys::WhenNeeded,
+        booleans: BooleanStyle::YesNo,
+        indent: IndentStyle::Spaces(4),
+        inline_sections: InlineSections::Never,
+        blank_lines: BlankLines::None,
+        terminators: Terminators::Newline,
+        align: false,
+        line_endings: Some(LineEnding::Lf),
+        spacing: Some(ComponentSpacing::Spaced),
+        colors: Some(ColorStyle::Decimal),
+        keep_hex: false,
+        precision: None,
+        formatters: ValueFormatters::new(),
+        normalize_numbers: true,
+        keep_bom: false,
+        bom: false,
+        encoding: Some(encoding_rs::UTF_8)
+    };
+}
+
+/// Returns the literal a property value was parsed from if it is written as it was, which it is
+/// unless it changed since or the options ask for another notation.
+fn raw_literal<'p>(property: &'p BlkProperty, options: &SerializeOptions) -> Option<&'p str> {
+    let raw = property.raw.as_deref().filter(|_| !options.normalize_numbers)?;
+    let ty = property.value.ty();
+    // hexadecimal literals are written as `keep_hex` demands
+    let restyled = property.hex
+        || (ty == BlkType::Color && options.colors.is_some())
+        || options.spacing.is_some_and(|spacing| ComponentSpacing::of(raw).is_some_and(|written| written != spacing));
+
+    (!restyled && crate::parsers::blk::parse_value(ty, raw).as_ref() == Some(&property.value)).then_some(raw)
+}
+
+/// Formats a real or a component of a vector or matrix with the precision the options ask for.
+fn format_real(value: f32, options: &SerializeOptions) -> String {
+    match options.precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        None => value.to_string()
+    }
 }
 
 /// Ugly function to convert a BLK configuration into a string representation.
 pub fn stringify_config(config: &BlkConfig, writer: &mut dyn Write) -> Result<(), std::io::Error> {
-    fn stringify_config_inner(writer: &mut dyn Write, entry: &BlkEntry, recurse_step: i32) -> Result<(), std::io::Error> {
-        write!(writer, "{}", &"    ".repeat(recurse_step as usize))?;
+    stringify_config_with(config, writer, &SerializeOptions::default())
+}
+
+/// Writes a property without indentation or separator, padding its key to `key_width` characters.
+fn write_property(writer: &mut dyn Write, property: &BlkProperty, key_width: usize, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write!(writer, "{:<width$}", options.quote_keys.apply(&property.key, false), width = key_width)?;
+    let separator = options.spacing.or_else(|| property.raw.as_deref().and_then(ComponentSpacing::of)).unwrap_or_default().separator();
+
+    match &property.value {
+        value if let Some(literal) = options.formatters.format(value) => {
+            write!(writer, ":{}={}", value.ty().suffix(), literal)?;
+        },
+        value if let Some(raw) = raw_literal(property, options) => {
+            write!(writer, ":{}={}", value.ty().suffix(), raw)?;
+        },
+        BlkPropertyValue::Text(text) => {
+            let quote = match options.quote.unwrap_or(property.quote) {
+                QuoteStyle::Triple if !is_raw_string(text) => QuoteStyle::Double,
+                quote => quote
+            };
+            write!(writer, ":t={}{}{}", quote.delimiter(), escape_string(text, quote), quote.delimiter())?;
+        },
+        BlkPropertyValue::Boolean(boolean) => {
+            write!(writer, ":b={}", options.booleans.literal(*boolean))?;
+        },
+        BlkPropertyValue::Integer(integer) if property.hex && options.keep_hex => {
+            write!(writer, ":i=0x{:X}", integer)?;
+        },
+        BlkPropertyValue::Real(real) => {
+            write!(writer, ":r={}", format_real(*real, options))?;
+        },
+        BlkPropertyValue::Vector2(x, y) => {
+            write!(writer, ":p2={}", [x, y].map(|component| format_real(*component, options)).join(separator))?;
+        },
+        BlkPropertyValue::Vector3(x, y, z) => {
+            write!(writer, ":p3={}", [x, y, z].map(|component| format_real(*component, options)).join(separator))?;
+        },
+        BlkPropertyValue::Vector4(x, y, z, w) => {
+            write!(writer, ":p4={}", [x, y, z, w].map(|component| format_real(*component, options)).join(separator))?;
+        },
+        BlkPropertyValue::IntVector2(x, y) => {
+            write!(writer, ":ip2={}", [x, y].map(ToString::to_string).join(separator))?;
+        },
+        BlkPropertyValue::IntVector3(x, y, z) => {
+            write!(writer, ":ip3={}", [x, y, z].map(ToString::to_string).join(separator))?;
+        },
+        BlkPropertyValue::Matrix(rows) => {
+            let rows: Vec<String> = rows.iter()
+                .map(|row| format!("[{}]", row.map(|component| format_real(component, options)).join(separator)))
+                .collect();
+            write!(writer, ":m=[{}]", rows.join(" "))?;
+        },
+        BlkPropertyValue::Color(r, g, b, a) => {
+            let style = options.colors.unwrap_or(if property.hex_color { ColorStyle::Hex } else { ColorStyle::Decimal });
+
+            if style == ColorStyle::Hex && [r, g, b, a].into_iter().all(|component| (0..=255).contains(component)) {
+                write!(writer, ":c=#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)?;
+            } else if property.hex && options.keep_hex && options.colors.is_none() {
+                write!(writer, ":c={}", [r, g, b, a].map(|component| format!("0x{:X}", component)).join(separator))?;
+            } else {
+                write!(writer, ":c={}", [r, g, b, a].map(ToString::to_string).join(separator))?;
+            }
+        },
+        value => {
+            write!(writer, ":{}={}", value.ty().suffix(), value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the name and annotation of a section followed by the `{` opening it.
+fn write_section_header(writer: &mut dyn Write, section: &BlkSection, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    // unnamed sections are array elements and are written without a name
+    let annotation = section.annotation.as_ref().map(ToString::to_string).unwrap_or_default();
+
+    if section.name.is_empty() {
+        write!(writer, "{}{{", annotation)
+    } else {
+        write!(writer, "{}{}{{", options.quote_keys.apply(&section.name, true), annotation)
+    }
+}
+
+/// Writes comments on lines of their own at the given indentation.
+fn write_comment_lines(writer: &mut dyn Write, comments: &[String], indent: &str) -> Result<(), std::io::Error> {
+    for comment in comments {
+        writeln!(writer, "{}{}", indent, comment)?;
+    }
+
+    Ok(())
+}
+
+/// Returns what is written for a comment following an entry on its line.
+fn same_line_comment(comment: Option<&str>) -> String {
+    comment.map(|comment| format!(" {}", comment)).unwrap_or_default()
+}
+
+/// Writes an entry and its nested entries at the given depth, after the comments leading it.
+fn stringify_config_inner(writer: &mut dyn Write, entry: &BlkEntry, recurse_step: i32, key_width: usize, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    let indent = options.indent.repeat(recurse_step as usize);
+
+    if let Some(comments) = entry.comments() {
+        write_comment_lines(writer, &comments.leading, &indent)?;
+    }
+
+    write!(writer, "{}", indent)?;
+
+    match entry {
+        BlkEntry::Section(section) => write_section(writer, section, &indent, recurse_step, terminator(entry, options), options)?,
+        BlkEntry::Property(property) => {
+            write_property(writer, property, key_width, options)?;
+            writeln!(writer, "{}{}", terminator(entry, options), same_line_comment(property.comments.trailing.as_deref()))?;
+        },
+        BlkEntry::Conflict(conflict) => {
+            writeln!(writer, "// <<<<<<< base")?;
+            stringify_config_inner(writer, &conflict.base, recurse_step, key_width, options)?;
+            writeln!(writer, "{}// =======", indent)?;
+            stringify_config_inner(writer, &conflict.overlay, recurse_step, key_width, options)?;
+            writeln!(writer, "{}// >>>>>>> overlay", indent)?;
+        },
+        BlkEntry::Include(path) => {
+            writeln!(writer, "include \"{}\"{}", escape_string(path, QuoteStyle::Double), terminator(entry, options))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a section after its indentation, following its closing `}` with `end`.
+fn write_section(writer: &mut dyn Write, section: &BlkSection, indent: &str, depth: i32, end: &str, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    let trailing = same_line_comment(section.comments.trailing.as_deref());
+    write_section_header(writer, section, options)?;
+
+    if let Some(inline) = inline_entries(section, options) {
+        if let Some(entry @ BlkEntry::Property(property)) = inline {
+            write!(writer, " ")?;
+            write_property(writer, property, 0, options)?;
+            write!(writer, "{} ", terminator(entry, options))?;
+        }
+
+        return writeln!(writer, "}}{}{}", end, trailing);
+    }
+
+    writeln!(writer, "{}", same_line_comment(section.comments.header.as_deref()))?;
+    write_block(writer, &section.entries, depth + 1, options)?;
+    write_comment_lines(writer, &section.comments.closing, &options.indent.repeat(depth as usize + 1))?;
+    writeln!(writer, "{}}}{}{}", indent, end, trailing)
+}
+
+/// Returns whether a section is written on a single line, along with its only entry if it has one.
+/// Sections with comments inside them are never written on a single line.
+fn inline_entries<'s>(section: &'s BlkSection, options: &SerializeOptions) -> Option<Option<&'s BlkEntry>> {
+    let commented = section.comments.header.is_some()
+        || !section.comments.closing.is_empty()
+        || section.entries.iter().any(|entry| entry.comments().is_some_and(|comments| !comments.is_empty()));
+
+    if commented {
+        return None;
+    }
+
+    match (options.inline_sections, &section.entries[..]) {
+        (InlineSections::Empty | InlineSections::Single, []) => Some(None),
+        (InlineSections::Single, [entry @ BlkEntry::Property(_)]) => Some(Some(entry)),
+        _ => None
+    }
+}
+
+/// Returns what is written between an entry and its line break.
+fn terminator(entry: &BlkEntry, options: &SerializeOptions) -> &'static str {
+    let semicolon = match options.terminators {
+        Terminators::Newline => false,
+        Terminators::Semicolon => matches!(entry, BlkEntry::Property(_) | BlkEntry::Include(_)),
+        Terminators::Preserve => entry.span().is_some_and(|span| span.semicolon)
+    };
+
+    if semicolon { ";" } else { "" }
+}
+
+/// Returns the length of the longest key of the properties in a block, including conflicting ones.
+fn key_width(entries: &[BlkEntry], options: &SerializeOptions) -> usize {
+    entries.iter()
+        .flat_map(|entry| match entry {
+            BlkEntry::Conflict(conflict) => vec![&*conflict.base, &*conflict.overlay],
+            entry => vec![entry]
+        })
+        .filter_map(|entry| match entry {
+            BlkEntry::Property(property) => Some(options.quote_keys.apply(&property.key, false).chars().count()),
+            _ => None
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Writes the entries of a block at the given depth, one per line.
+fn write_block(writer: &mut dyn Write, entries: &[BlkEntry], depth: i32, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    let key_width = if options.align { key_width(entries, options) } else { 0 };
+
+    for (index, entry) in sorted(entries, options).into_iter().enumerate() {
+        let blank = match options.blank_lines {
+            BlankLines::None => false,
+            BlankLines::Sections => matches!(entry, BlkEntry::Section(_)),
+            BlankLines::Preserve => entry.span().is_some_and(|span| span.blank_before)
+        };
+
+        if blank && index > 0 {
+            writeln!(writer)?;
+        }
+
+        stringify_config_inner(writer, entry, depth, key_width, options)?;
+    }
+
+    Ok(())
+}
+
+/// Writes entries on a single line, separating them with `;`.
+fn write_compact(writer: &mut dyn Write, entries: &[BlkEntry], options: &SerializeOptions) -> Result<(), std::io::Error> {
+    for (index, entry) in sorted(entries, options).into_iter().enumerate() {
+        if index > 0 {
+            write!(writer, "; ")?;
+        }
 
         match entry {
-            BlkEntry::Section(section) => {
-                write!(writer, "{}{{\n", section.name)?;
+            BlkEntry::Section(section) => write_compact_section(writer, section, options)?,
+            BlkEntry::Property(property) => write_property(writer, property, 0, options)?,
+            BlkEntry::Conflict(conflict) => {
+                // line comments would end the line, so the markers are block comments
+                write!(writer, "/* <<<<<<< base */ ")?;
+                write_compact(writer, std::slice::from_ref(&*conflict.base), options)?;
+                write!(writer, "; /* ======= */ ")?;
+                write_compact(writer, std::slice::from_ref(&*conflict.overlay), options)?;
+                write!(writer, " /* >>>>>>> overlay */")?;
+            },
+            BlkEntry::Include(path) => write!(writer, "include \"{}\"", escape_string(path, QuoteStyle::Double))?
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a section and its entries on a single line.
+fn write_compact_section(writer: &mut dyn Write, section: &BlkSection, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write_section_header(writer, section, options)?;
+
+    if !section.entries.is_empty() {
+        write!(writer, " ")?;
+        write_compact(writer, &section.entries, options)?;
+        write!(writer, " ")?;
+    }
+
+    write!(writer, "}}")
+}
+
+/// Writes the header the options ask for as line comments.
+fn write_header(writer: &mut dyn Write, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    for line in options.header.iter().flat_map(|header| header.lines()) {
+        match line.is_empty() {
+            true => writeln!(writer, "//")?,
+            false => writeln!(writer, "// {}", line)?
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [`stringify_config`], formatting the output as the options demand.
+pub fn stringify_config_with(config: &BlkConfig, writer: &mut dyn Write, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write_config(config, writer, options, &mut |writer| write_top_level(writer, &config.block.entries, &config.comments, options))
+}
+
+/// Like [`stringify_config_with`], writing the given entries as the top level of a file.
+/// Entries taken from a config do not carry its encoding and line endings, so the output
+/// is in UTF-8 with `\n` line endings unless the options ask otherwise.
+pub fn stringify_entries(entries: &[BlkEntry], writer: &mut dyn Write, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write_config(&BlkConfig::default(), writer, options, &mut |writer| write_top_level(writer, entries, &[], options))
+}
 
-                for entry in &section.entries {
-                    stringify_config_inner(writer, entry, recurse_step + 1)?;
+/// Like [`stringify_entries`], writing a single section along with its name, such as just the
+/// `hotkeys{}` of a config.
+pub fn stringify_section(section: &BlkSection, writer: &mut dyn Write, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write_config(&BlkConfig::default(), writer, options, &mut |writer| {
+        write_header(writer, options)?;
+
+        if options.style == OutputStyle::Compact {
+            write_compact_section(writer, section, options)?;
+            return writeln!(writer);
+        }
+
+        let end = match options.terminators {
+            Terminators::Preserve if section.span.as_ref().is_some_and(|span| span.semicolon) => ";",
+            _ => ""
+        };
+        write_comment_lines(writer, &section.comments.leading, "")?;
+        write_section(writer, section, "", 0, end, options)
+    })
+}
+
+/// Writes the header and the entries of the top level of a file, followed by the comments closing it.
+fn write_top_level(writer: &mut dyn Write, entries: &[BlkEntry], closing: &[String], options: &SerializeOptions) -> Result<(), std::io::Error> {
+    write_header(writer, options)?;
+
+    if options.style == OutputStyle::Compact {
+        write_compact(writer, entries, options)?;
+        return writeln!(writer);
+    }
+
+    write_block(writer, entries, 0, options)?;
+    write_comment_lines(writer, closing, "")
+}
+
+/// Passes the text written to it on to a [`fmt::Write`].
+struct FmtWriter<'w>(&'w mut dyn fmt::Write);
+
+impl Write for FmtWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // the serializer writes whole strings, so every write is valid UTF-8 on its own
+        let text = std::str::from_utf8(buf).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        self.0.write_str(text).map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Like [`stringify_config_with`], writing into a [`fmt::Write`] such as a `String` instead.
+/// The output is always UTF-8, whatever encoding the options ask for.
+pub fn stringify_config_fmt(config: &BlkConfig, writer: &mut dyn fmt::Write, options: &SerializeOptions) -> fmt::Result {
+    let options = SerializeOptions { encoding: Some(encoding_rs::UTF_8), ..options.clone() };
+    stringify_config_with(config, &mut FmtWriter(writer), &options).map_err(|_| fmt::Error)
+}
+
+/// Like [`stringify_config_with`], copying the entries that are unchanged from `source`, the
+/// text the config was parsed from, as they were written there along with the comments and
+/// spacing between them. Only entries that changed or were added are formatted, and only
+/// they follow the formatting options.
+pub fn stringify_config_preserving(config: &BlkConfig, source: &str, writer: &mut dyn Write, options: &SerializeOptions) -> Result<(), std::io::Error> {
+    let start = if source.starts_with('\u{FEFF}') { '\u{FEFF}'.len_utf8() } else { 0 };
+    // sections written anew keep the order of their entries like everything copied around them
+    let options = &SerializeOptions { sort: None, group: false, ..options.clone() };
+
+    write_config(config, writer, options, &mut |writer| {
+        write_preserved(writer, &config.block.entries, source, 0, start, source.len(), false, options).map(|_| ())
+    })
+}
+
+/// Finds where an entry was written in `source`, which is where its span points if the text
+/// there reads as an entry of the same kind and name. Returns the range along with whether the
+/// text still reads as the entry itself.
+fn locate(entry: &BlkEntry, source: &str) -> Option<(Range<usize>, bool)> {
+    let span = entry.span()?;
+    let text = source.get(span.byte_range.clone())?;
+    let parsed = crate::parsers::blk::parse_file(text, &Default::default(), &mut Vec::new()).ok()?;
+
+    match (&parsed.block.entries[..], entry) {
+        ([BlkEntry::Section(found)], BlkEntry::Section(section)) if found.name == section.name =>
+            Some((span.byte_range.clone(), found == section)),
+        ([BlkEntry::Property(found)], BlkEntry::Property(property)) if found.key == property.key =>
+            Some((span.byte_range.clone(), found == property)),
+        _ => None
+    }
+}
+
+/// Writes the entries of a block that spans `start..end` in `source`. Unchanged entries are
+/// copied, and so is the text between entries that were written in the same order there.
+/// `open` tells whether the current output line is unfinished, as it is after a section header.
+/// Returns whether the output ends right before the `}` closing the block in the source.
+#[allow(clippy::too_many_arguments)]
+fn write_preserved(
+    writer: &mut dyn Write,
+    entries: &[BlkEntry],
+    source: &str,
+    depth: usize,
+    start: usize,
+    end: usize,
+    mut open: bool,
+    options: &SerializeOptions
+) -> Result<bool, std::io::Error> {
+    let indent = options.indent.repeat(depth);
+    let gap = |from: Option<usize>, to: usize| from
+        .filter(|&from| from <= to)
+        .and_then(|from| source.get(from..to))
+        .filter(|text| crate::parsers::blk::is_blank(text));
+    // where the text between entries starts, if the entry written last ends there in the source
+    let mut cursor = Some(start);
+
+    for entry in entries {
+        let located = locate(entry, source);
+        let leading = located.as_ref().and_then(|(range, _)| gap(cursor, range.start));
+
+        match (located, leading) {
+            (Some((range, unchanged)), leading) if unchanged || leading.is_some() => {
+                match leading {
+                    Some(text) => write!(writer, "{}", text)?,
+                    None if open => write!(writer, "\n{}", indent)?,
+                    None => write!(writer, "{}", indent)?
                 }
 
-                write!(writer, "{}}}\n", &"    ".repeat(recurse_step as usize))?;
-            },
-            BlkEntry::Property(property) => {
-                write!(writer, "{}", property.key)?;
+                match entry {
+                    _ if unchanged => write!(writer, "{}", &source[range.clone()])?,
+                    BlkEntry::Section(section) => {
+                        // the section name and annotation read the same, so the header ends at the first `{`
+                        let block_start = range.start + source[range.clone()].find('{').map_or(0, |brace| brace + 1);
 
-                match &property.value {
-                    BlkPropertyValue::Text(text) => {
-                        write!(writer, ":t=\"{}\"", text)?;
-                    },
-                    BlkPropertyValue::Boolean(boolean) => {
-                        write!(writer, ":b={}", if *boolean { "yes" } else { "no" })?;
-                    },
-                    BlkPropertyValue::Integer(integer) => {
-                        write!(writer, ":i={}", integer)?;
-                    },
-                    BlkPropertyValue::Real(real) => {
-                        write!(writer, ":r={}", real)?;
-                    },
-                    BlkPropertyValue::Vector2(x, y) => {
-                        write!(writer, ":p2={}, {}", x, y)?;
-                    },
-                    BlkPropertyValue::Vector3(x, y, z) => {
-                        write!(writer, ":p3={}, {}, {}", x, y, z)?;
-                    },
-                    BlkPropertyValue::Vector4(x, y, z, w) => {
-                        write!(writer, ":p4={}, {}, {}, {}", x, y, z, w)?;
+                        write_section_header(writer, section, options)?;
+
+                        if !write_preserved(writer, &section.entries, source, depth + 1, block_start, range.end - 1, true, options)? {
+                            write!(writer, "{}", indent)?;
+                        }
+
+                        write!(writer, "}}")?;
                     },
-                    BlkPropertyValue::Color(r, g, b, a) => {
-                        write!(writer, ":c={}, {}, {}, {}", r, g, b, a)?;
-                    }
+                    BlkEntry::Property(property) => write_property(writer, property, 0, options)?,
+                    _ => unreachable!("only sections and properties are located")
+                }
+
+                cursor = Some(range.end);
+                open = true;
+            },
+            _ => {
+                if open {
+                    writeln!(writer)?;
                 }
 
-                write!(writer, "\n")?;
+                stringify_config_inner(writer, entry, depth as i32, 0, options)?;
+                cursor = None;
+                open = false;
             }
         }
+    }
 
-        Ok(())
+    match gap(cursor, end) {
+        Some(text) if open => {
+            write!(writer, "{}", text)?;
+            Ok(true)
+        },
+        _ if open => {
+            writeln!(writer)?;
+            Ok(false)
+        },
+        _ => Ok(false)
     }
+}
+
+/// Writes the output of `write_entries` with the byte order mark and in the encoding the options demand.
+fn write_config(
+    config: &BlkConfig,
+    writer: &mut dyn Write,
+    options: &SerializeOptions,
+    write_entries: &mut dyn FnMut(&mut dyn Write) -> Result<(), std::io::Error>
+) -> Result<(), std::io::Error> {
+    let target = options.encoding.or(config.encoding).unwrap_or(encoding_rs::UTF_8);
+    let crlf = options.line_endings.map_or(config.crlf, |ending| ending == LineEnding::CrLf);
 
-    for entry in &config.block.entries {
-        stringify_config_inner(writer, entry, 0)?;
+    if target == encoding_rs::UTF_8 && (options.bom || (config.bom && options.keep_bom)) {
+        write!(writer, "\u{FEFF}")?;
     }
 
-    Ok(())
+    // text copied from a CRLF file already ends its lines with `\r\n`
+    if target == encoding_rs::UTF_8 && !crlf && !config.crlf {
+        return write_entries(writer);
+    }
+
+    let mut buffer = Vec::new();
+    write_entries(&mut buffer)?;
+
+    let text = String::from_utf8(buffer).expect("serialized configs are UTF-8").replace("\r\n", "\n");
+    let text = if crlf { text.replace('\n', "\r\n") } else { text };
+    writer.write_all(&encoding::encode(&text, target)?)
 }
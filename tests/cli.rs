@@ -0,0 +1,86 @@
+use std::{path::PathBuf, process::{Command, Output}};
+
+/// Returns a path in the temporary directory unique to this test process
+fn temporary_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("blk-merge-cli-{}-{}", std::process::id(), name))
+}
+
+/// Runs the binary with the given arguments
+fn blk_merge(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blk-merge")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_report_json_to_stdout_rewrites_file() {
+    let base = temporary_file("report-base.blk");
+    let overlay = temporary_file("report-overlay.blk");
+    std::fs::write(&base, "a:i=1\n").unwrap();
+    std::fs::write(&overlay, "a:i=2\n").unwrap();
+
+    let output = blk_merge(&["merge", "-f", base.to_str().unwrap(), "-w", overlay.to_str().unwrap(), "--report-json", "-"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // stdout holds nothing but the JSON report
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report.is_object());
+    assert_eq!(std::fs::read_to_string(&base).unwrap(), "a:i=2\n");
+
+    std::fs::remove_file(&base).unwrap();
+    std::fs::remove_file(&overlay).unwrap();
+}
+
+#[test]
+fn test_check_with_report_json_to_stdout_prints_only_json() {
+    let base = temporary_file("check-base.blk");
+    let overlay = temporary_file("check-overlay.blk");
+    std::fs::write(&base, "a:i=1\n").unwrap();
+    std::fs::write(&overlay, "a:i=2\n").unwrap();
+
+    let output = blk_merge(&["merge", "-f", base.to_str().unwrap(), "-w", overlay.to_str().unwrap(), "--check", "--report-json", "-"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report.is_object());
+    assert_eq!(std::fs::read_to_string(&base).unwrap(), "a:i=1\n");
+
+    std::fs::remove_file(&base).unwrap();
+    std::fs::remove_file(&overlay).unwrap();
+}
+
+#[test]
+fn test_policy_test_uses_parse_options() {
+    let policy = temporary_file("test-policy.pol");
+    let base = temporary_file("test-base.blk");
+    let overlay = temporary_file("test-overlay.blk");
+    std::fs::write(&policy, "default override\n").unwrap();
+    std::fs::write(&base, "a:r=nan\n").unwrap();
+    std::fs::write(&overlay, "a:r=1\n").unwrap();
+
+    let args = ["policy", "test", policy.to_str().unwrap(), "--base", base.to_str().unwrap(), "--with", overlay.to_str().unwrap()];
+    assert_eq!(blk_merge(&args).status.code(), Some(2));
+
+    let output = blk_merge(&[&args[..], &["--real-policy", "accept"]].concat());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    std::fs::remove_file(&policy).unwrap();
+    std::fs::remove_file(&base).unwrap();
+    std::fs::remove_file(&overlay).unwrap();
+}
+
+#[test]
+fn test_policy_init_uses_parse_options() {
+    let from = temporary_file("init-from.blk");
+    let with = temporary_file("init-with.blk");
+    std::fs::write(&from, "a:r=nan\nb:i=1\n").unwrap();
+    std::fs::write(&with, "a:r=1\nb:i=2\n").unwrap();
+
+    let args = ["policy", "init", "--from", from.to_str().unwrap(), "--with", with.to_str().unwrap()];
+    assert_eq!(blk_merge(&args).status.code(), Some(2));
+
+    let output = blk_merge(&[&args[..], &["--real-policy", "accept"]].concat());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("rule /b override"));
+
+    std::fs::remove_file(&from).unwrap();
+    std::fs::remove_file(&with).unwrap();
+}